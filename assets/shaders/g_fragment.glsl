@@ -4,7 +4,10 @@ in vec2 o_tex;
 in vec4 o_fg_color;
 in vec4 o_bg_color;
 in float o_has_color;
+in float o_subpixel;
 in vec2 o_underline;
+in vec4 o_underline_color;
+in vec2 o_overline;
 in vec2 o_cursor;
 in vec4 o_cursor_color;
 
@@ -33,9 +36,18 @@ void main() {
   if (bg_and_line_layer) {
     color = o_bg_color;
 
-    vec4 under_color = multiply(o_fg_color, texture(glyph_tex, o_underline));
-    if (under_color.a != 0.0) {
-        color = under_color;
+    // Curly and dotted underlines are antialiased, so use the sprite
+    // coverage to blend the underline color over the background.
+    float under_alpha = texture(glyph_tex, o_underline).a;
+    if (under_alpha != 0.0) {
+      color = vec4(mix(color.rgb, o_underline_color.rgb, under_alpha), max(color.a, under_alpha));
+    }
+
+    // The overline layer also holds the strike through, both of which
+    // take the text color rather than the underline color.
+    float over_alpha = texture(glyph_tex, o_overline).a;
+    if (over_alpha != 0.0) {
+      color = vec4(mix(color.rgb, o_fg_color.rgb, over_alpha), max(color.a, over_alpha));
     }
 
     vec4 cursor_outline = multiply(o_cursor_color, texture(glyph_tex, o_cursor));
@@ -45,7 +57,12 @@ void main() {
 
   } else {
     color = texture(glyph_tex, o_tex);
-    if (o_has_color == 0.0) {
+    if (o_subpixel != 0.0) {
+      // Per-channel coverage: blend each subpixel between the cell
+      // background and the text color.
+      float coverage = max(color.r, max(color.g, color.b));
+      color = vec4(mix(o_bg_color.rgb, o_fg_color.rgb, color.rgb), coverage > 0.0 ? 1.0 : 0.0);
+    } else if (o_has_color == 0.0) {
       color.rgb = o_fg_color.rgb;
     }
   }
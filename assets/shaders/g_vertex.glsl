@@ -3,9 +3,12 @@ in vec2 position;
 in vec2 adjust;
 in vec2 tex;
 in vec2 underline;
+in vec4 underline_color;
+in vec2 overline;
 in vec4 bg_color;
 in vec4 fg_color;
 in float has_color;
+in float subpixel;
 in vec2 cursor;
 in vec4 cursor_color;
 
@@ -16,16 +19,22 @@ out vec2 o_tex;
 out vec4 o_fg_color;
 out vec4 o_bg_color;
 out float o_has_color;
+out float o_subpixel;
 out vec2 o_underline;
+out vec4 o_underline_color;
+out vec2 o_overline;
 out vec2 o_cursor;
 out vec4 o_cursor_color;
 
 void main() {
     o_tex = tex;
     o_has_color = has_color;
+    o_subpixel = subpixel;
     o_fg_color = fg_color;
     o_bg_color = bg_color;
     o_underline = underline;
+    o_underline_color = underline_color;
+    o_overline = overline;
     o_cursor = cursor;
     o_cursor_color = cursor_color;
 
@@ -0,0 +1,53 @@
+# Shell integration for miro: marks prompts, input and command output with
+# OSC 133 so the terminal can navigate between commands.
+# Source this file from ~/.bashrc.
+
+if [[ $- == *i* && -z "$MIRO_SHELL_INTEGRATION" ]]; then
+    MIRO_SHELL_INTEGRATION=1
+
+    __miro_at_prompt=0
+    __miro_ran_command=0
+
+    __miro_preexec() {
+        if [[ $__miro_at_prompt == 1 ]]; then
+            __miro_at_prompt=0
+            __miro_ran_command=1
+            printf '\e]133;C\a'
+        fi
+    }
+
+    __miro_precmd() {
+        local ret=$?
+        if [[ $__miro_ran_command == 1 ]]; then
+            printf '\e]133;D;%s\a' "$ret"
+        fi
+        __miro_ran_command=0
+    }
+
+    __miro_prompt_ready() {
+        __miro_at_prompt=1
+    }
+
+    # A DEBUG trap set before this file was sourced can't be seen from
+    # here or from a function, so ours is installed by the first prompt at
+    # the top level, and runs after the existing one.
+    __miro_install_preexec() {
+        local ret=$? previous=
+        __miro_trap_command() {
+            previous=$3
+        }
+        eval "__miro_trap_command $1"
+        unset -f __miro_trap_command
+        if [[ -n $previous ]]; then
+            trap "$previous"$'\n''__miro_preexec' DEBUG
+        else
+            trap '__miro_preexec' DEBUG
+        fi
+        PROMPT_COMMAND=${PROMPT_COMMAND/"$__miro_install_command"/}
+        return $ret
+    }
+
+    __miro_install_command='__miro_install_preexec "$(trap -p DEBUG)";'
+    PROMPT_COMMAND="${__miro_install_command}__miro_precmd;${PROMPT_COMMAND:+$PROMPT_COMMAND;}__miro_prompt_ready"
+    PS1='\[\e]133;A\a\]'"$PS1"'\[\e]133;B\a\]'
+fi
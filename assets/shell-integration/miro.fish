@@ -0,0 +1,30 @@
+# Shell integration for miro: marks prompts, input and command output with
+# OSC 133 so the terminal can navigate between commands.
+# Source this file from ~/.config/fish/config.fish.
+
+if status is-interactive; and not set -q MIRO_SHELL_INTEGRATION
+    set -g MIRO_SHELL_INTEGRATION 1
+
+    function __miro_prompt_start --on-event fish_prompt
+        if set -q __miro_in_command
+            printf '\e]133;D;%s\a' $__miro_status
+            set -e __miro_in_command
+        end
+        printf '\e]133;A\a'
+    end
+
+    function __miro_preexec --on-event fish_preexec
+        set -g __miro_in_command 1
+        printf '\e]133;C\a'
+    end
+
+    function __miro_postexec --on-event fish_postexec
+        set -g __miro_status $status
+    end
+
+    functions -c fish_prompt __miro_original_fish_prompt
+    function fish_prompt
+        __miro_original_fish_prompt
+        printf '\e]133;B\a'
+    end
+end
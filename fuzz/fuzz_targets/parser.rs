@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use miro::core::escape::parser::Parser;
+
+fuzz_target!(|data: &[u8]| {
+    let mut parser = Parser::new();
+    parser.parse(data, |_| {});
+});
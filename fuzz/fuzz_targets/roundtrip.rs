@@ -0,0 +1,39 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use miro::core::escape::csi::CSI;
+use miro::core::escape::parser::Parser;
+use miro::core::escape::{Action, OperatingSystemCommand};
+
+fn parse(bytes: &[u8]) -> Vec<Action> {
+    let mut actions = vec![];
+    Parser::new().parse(bytes, |action| actions.push(action));
+    actions
+}
+
+/// Whether `action` is a decoded CSI or OSC sequence.  The unspecified
+/// variants only carry the raw parameters, so they aren't expected to
+/// survive being encoded again.
+fn is_decoded(action: &Action) -> bool {
+    match action {
+        Action::CSI(csi) => !matches!(csi, CSI::Unspecified(_)),
+        Action::OperatingSystemCommand(osc) => {
+            !matches!(**osc, OperatingSystemCommand::Unspecified(_))
+        }
+        _ => false,
+    }
+}
+
+// Every CSI and OSC sequence that the parser understands must encode to
+// something that parses back to the same sequence.
+fuzz_target!(|data: &[u8]| {
+    for action in parse(data).into_iter().filter(is_decoded) {
+        let encoded = action.to_string();
+        assert_eq!(
+            parse(encoded.as_bytes()),
+            vec![action.clone()],
+            "{:?} was encoded as {:?}",
+            action,
+            encoded
+        );
+    }
+});
@@ -0,0 +1,71 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use miro::core::escape::csi::Window;
+use miro::core::hyperlink::Hyperlink;
+use miro::term::clipboard::{Clipboard, ClipboardRequest, ClipboardSelection};
+use miro::term::dump::{dump, DumpFormat};
+use miro::term::{Alert, Terminal, TerminalHost};
+use std::sync::Arc;
+
+const ROWS: usize = 8;
+const COLS: usize = 20;
+const SCROLLBACK: usize = 16;
+
+/// Swallows everything the terminal asks of its host.
+#[derive(Default)]
+struct Host {
+    replies: Vec<u8>,
+}
+
+impl TerminalHost for Host {
+    fn writer(&mut self) -> &mut dyn std::io::Write {
+        &mut self.replies
+    }
+
+    fn get_clipboard(&mut self) -> anyhow::Result<Arc<dyn Clipboard>> {
+        anyhow::bail!("there is no clipboard while fuzzing")
+    }
+
+    fn set_title(&mut self, _title: &str) {}
+
+    fn click_link(&mut self, _link: &Arc<Hyperlink>) {}
+
+    fn alert(&mut self, _alert: Alert) {}
+
+    fn clipboard_request(&mut self, _request: ClipboardRequest) {}
+
+    fn window_request(&mut self, _request: Window) {}
+
+    fn paste_selection(&mut self, _selection: ClipboardSelection) {}
+}
+
+fn check_invariants(terminal: &Terminal) {
+    let screen = terminal.screen();
+    assert_eq!((screen.physical_rows, screen.physical_cols), (ROWS, COLS));
+    assert!(screen.num_lines() >= ROWS && screen.num_lines() <= ROWS + SCROLLBACK);
+    for line in screen.lines(0..screen.num_lines()) {
+        assert!(line.cells().len() >= COLS, "line is only {} cells wide", line.cells().len());
+    }
+
+    let cursor = terminal.cursor_pos();
+    assert!(cursor.x < COLS, "cursor column {} is off screen", cursor.x);
+    assert!(cursor.y >= 0 && (cursor.y as usize) < ROWS, "cursor row {} is off screen", cursor.y);
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut terminal = Terminal::new(ROWS, COLS, 160, 128, SCROLLBACK, vec![]);
+    terminal.set_title_reporting(true);
+    let mut host = Host::default();
+
+    // Feed the input in two reads so that sequences split across reads are
+    // exercised as well
+    let (first, second) = data.split_at(data.len() / 2);
+    terminal.advance_bytes(first, &mut host);
+    check_invariants(&terminal);
+    terminal.advance_bytes(second, &mut host);
+    check_invariants(&terminal);
+
+    for format in &[DumpFormat::Text, DumpFormat::Ansi, DumpFormat::Json] {
+        dump(&terminal, *format, true);
+    }
+});
@@ -1,11 +1,16 @@
 use crate::core::hyperlink;
+use crate::pty::ExitStatus;
 use crate::term;
+use crate::term::clipboard::{ClipboardRequest, ClipboardSelection};
 use crate::term::color::RgbColor;
+use crate::term::paste::PasteFilter;
+use anyhow::Context;
 use regex::Regex;
 use serde_derive::*;
 use serde_json::Value;
 use std;
 use std::collections::HashMap;
+use std::path::Path;
 
 #[derive(Default, Debug, Deserialize, Clone)]
 pub struct Theme {
@@ -24,12 +29,51 @@ pub struct Config {
     #[serde(default = "default_hyperlink_rules")]
     pub hyperlink_rules: Vec<hyperlink::Rule>,
     pub ratelimit_output_bytes_per_second: Option<u32>,
+    pub ratelimit_notifications_per_second: Option<u32>,
     #[serde(default)]
     pub font_rules: Vec<StyleRule>,
     pub colors: Option<Palette>,
     pub scrollback_lines: Option<usize>,
     #[serde(default)]
     pub send_composed_key_when_alt_is_pressed: bool,
+    #[serde(default)]
+    pub disable_ligatures_under_cursor: bool,
+    #[serde(default = "default_true")]
+    pub custom_block_glyphs: bool,
+    #[serde(default)]
+    pub disable_blink: bool,
+    #[serde(default = "default_true")]
+    pub bold_brightens_ansi_colors: bool,
+    #[serde(default = "default_minimum_contrast")]
+    pub minimum_contrast: f32,
+    #[serde(default)]
+    pub bell: BellMode,
+    pub bell_command: Option<String>,
+    #[serde(default)]
+    pub osc52_clipboard: ClipboardAccess,
+    #[serde(default)]
+    pub osc52_primary: ClipboardAccess,
+    #[serde(default = "default_osc52_max_bytes")]
+    pub osc52_max_bytes: usize,
+    #[serde(default = "default_true")]
+    pub copy_on_select: bool,
+    #[serde(default = "default_middle_click_paste")]
+    pub middle_click_paste: ClipboardSelection,
+    #[serde(default)]
+    pub enable_title_reporting: bool,
+    #[serde(default)]
+    pub allow_window_ops: Vec<WindowOperation>,
+    #[serde(default)]
+    pub paste_filter: PasteFilter,
+    #[serde(default = "default_true")]
+    pub confirm_multiline_paste: bool,
+    #[serde(default)]
+    pub exit_behavior: ExitBehavior,
+    #[serde(default)]
+    pub record_input: bool,
+    #[serde(default)]
+    pub failed_command_marker: bool,
+    #[serde(default)]
     pub theme: Theme,
 }
 
@@ -42,6 +86,10 @@ fn default_hyperlink_rules() -> Vec<hyperlink::Rule> {
     ]
 }
 
+fn default_true() -> bool {
+    true
+}
+
 fn default_font_size() -> f64 {
     10.0
 }
@@ -50,6 +98,24 @@ fn default_dpi() -> f64 {
     96.0
 }
 
+fn default_minimum_contrast() -> f32 {
+    1.0
+}
+
+fn default_osc52_max_bytes() -> usize {
+    1024 * 1024
+}
+
+#[cfg(target_os = "macos")]
+fn default_middle_click_paste() -> ClipboardSelection {
+    ClipboardSelection::Clipboard
+}
+
+#[cfg(not(target_os = "macos"))]
+fn default_middle_click_paste() -> ClipboardSelection {
+    ClipboardSelection::Primary
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -57,16 +123,116 @@ impl Default for Config {
             dpi: default_dpi(),
             font: TextStyle::default(),
             ratelimit_output_bytes_per_second: None,
+            ratelimit_notifications_per_second: None,
             font_rules: Vec::new(),
             colors: None,
             hyperlink_rules: default_hyperlink_rules(),
             scrollback_lines: None,
             send_composed_key_when_alt_is_pressed: false,
+            disable_ligatures_under_cursor: false,
+            custom_block_glyphs: true,
+            disable_blink: false,
+            bold_brightens_ansi_colors: true,
+            minimum_contrast: default_minimum_contrast(),
+            bell: BellMode::default(),
+            bell_command: None,
+            osc52_clipboard: ClipboardAccess::default(),
+            osc52_primary: ClipboardAccess::default(),
+            osc52_max_bytes: default_osc52_max_bytes(),
+            copy_on_select: true,
+            middle_click_paste: default_middle_click_paste(),
+            enable_title_reporting: false,
+            allow_window_ops: vec![],
+            paste_filter: PasteFilter::default(),
+            confirm_multiline_paste: true,
+            exit_behavior: ExitBehavior::default(),
+            record_input: false,
+            failed_command_marker: false,
             theme: Theme::default(),
         }
     }
 }
 
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BellMode {
+    None,
+    Visual,
+    Urgency,
+    Command,
+}
+
+impl Default for BellMode {
+    fn default() -> Self {
+        BellMode::None
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardAccess {
+    Deny,
+    WriteOnly,
+    ReadWrite,
+    Prompt,
+}
+
+impl Default for ClipboardAccess {
+    fn default() -> Self {
+        ClipboardAccess::WriteOnly
+    }
+}
+
+impl ClipboardAccess {
+    /// Returns whether the request is allowed, or `None` if the user
+    /// needs to be asked.
+    pub fn permits(self, request: &ClipboardRequest) -> Option<bool> {
+        match self {
+            ClipboardAccess::Deny => Some(false),
+            ClipboardAccess::WriteOnly => Some(!request.is_query()),
+            ClipboardAccess::ReadWrite => Some(true),
+            ClipboardAccess::Prompt => None,
+        }
+    }
+}
+
+/// What happens to the window when the program running in it exits.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ExitBehavior {
+    Close,
+    Hold,
+    CloseOnCleanExit,
+}
+
+impl Default for ExitBehavior {
+    fn default() -> Self {
+        ExitBehavior::Close
+    }
+}
+
+impl ExitBehavior {
+    /// Returns whether the window closes once the program exited with
+    /// `status`.
+    pub fn closes(self, status: ExitStatus) -> bool {
+        match self {
+            ExitBehavior::Close => true,
+            ExitBehavior::Hold => false,
+            ExitBehavior::CloseOnCleanExit => status.success(),
+        }
+    }
+}
+
+/// The kinds of XTWINOPS window manipulation that programs running in
+/// the terminal may be allowed to perform.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum WindowOperation {
+    Iconify,
+    Move,
+    Resize,
+    Raise,
+    Lower,
+    Maximize,
+    FullScreen,
+}
+
 #[cfg(target_os = "macos")]
 const FONT_FAMILY: &str = "Menlo";
 
@@ -78,11 +244,89 @@ pub struct FontAttributes {
     pub family: String,
     pub bold: Option<bool>,
     pub italic: Option<bool>,
+    pub harfbuzz_features: Option<Vec<String>>,
+    pub hinting: Option<FontHinting>,
+    pub antialias: Option<FontAntiAliasing>,
+    pub lcd_filter: Option<FontLcdFilter>,
+    pub embolden: Option<bool>,
+    pub gamma: Option<FontGamma>,
 }
 
 impl Default for FontAttributes {
     fn default() -> Self {
-        Self { family: FONT_FAMILY.into(), bold: None, italic: None }
+        Self {
+            family: FONT_FAMILY.into(),
+            bold: None,
+            italic: None,
+            harfbuzz_features: None,
+            hinting: None,
+            antialias: None,
+            lcd_filter: None,
+            embolden: None,
+            gamma: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FontHinting {
+    None,
+    Light,
+    Full,
+}
+
+impl Default for FontHinting {
+    fn default() -> Self {
+        FontHinting::Light
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FontAntiAliasing {
+    None,
+    Greyscale,
+    SubpixelRgb,
+    SubpixelBgr,
+}
+
+impl Default for FontAntiAliasing {
+    fn default() -> Self {
+        FontAntiAliasing::Greyscale
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FontLcdFilter {
+    None,
+    Default,
+    Light,
+    Legacy,
+}
+
+impl Default for FontLcdFilter {
+    fn default() -> Self {
+        FontLcdFilter::Default
+    }
+}
+
+/// The gamma applied to the antialiased coverage of the glyphs; above 1.0
+/// makes the text heavier and below 1.0 lighter.  It is compared by its
+/// bits so that the attributes can still be used as a key.
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(transparent)]
+pub struct FontGamma(pub f32);
+
+impl PartialEq for FontGamma {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for FontGamma {}
+
+impl std::hash::Hash for FontGamma {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state)
     }
 }
 
@@ -144,15 +388,15 @@ impl TextStyle {
         }
 
         #[cfg(target_os = "macos")]
-        font.push(FontAttributes { family: "Apple Color Emoji".into(), bold: None, italic: None });
+        font.push(FontAttributes { family: "Apple Color Emoji".into(), ..Default::default() });
         #[cfg(target_os = "macos")]
-        font.push(FontAttributes { family: "Apple Symbols".into(), bold: None, italic: None });
+        font.push(FontAttributes { family: "Apple Symbols".into(), ..Default::default() });
         #[cfg(target_os = "macos")]
-        font.push(FontAttributes { family: "Zapf Dingbats".into(), bold: None, italic: None });
+        font.push(FontAttributes { family: "Zapf Dingbats".into(), ..Default::default() });
         #[cfg(target_os = "macos")]
-        font.push(FontAttributes { family: "Apple LiGothic".into(), bold: None, italic: None });
+        font.push(FontAttributes { family: "Apple LiGothic".into(), ..Default::default() });
         #[cfg(not(target_os = "macos"))]
-        font.push(FontAttributes { family: "Noto Color Emoji".into(), bold: None, italic: None });
+        font.push(FontAttributes { family: "Noto Color Emoji".into(), ..Default::default() });
 
         font
     }
@@ -175,6 +419,16 @@ impl Config {
         Self::default().compute_extra_defaults(theme)
     }
 
+    /// Reads the configuration from a JSON file, falling back to the
+    /// defaults for anything it leaves out.
+    pub fn load(path: &Path, theme: Theme) -> anyhow::Result<Self> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let cfg: Self =
+            serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        Ok(cfg.compute_extra_defaults(theme))
+    }
+
     fn compute_extra_defaults(&self, theme: Theme) -> Self {
         let mut cfg = self.clone();
         cfg.theme = theme;
@@ -12,6 +12,7 @@ pub struct CellAttributes {
     attributes: u16,
     pub foreground: ColorAttribute,
     pub background: ColorAttribute,
+    pub underline_color: ColorAttribute,
     pub hyperlink: Option<Arc<Hyperlink>>,
 }
 
@@ -76,6 +77,9 @@ pub enum Underline {
     None = 0,
     Single = 1,
     Double = 2,
+    Curly = 3,
+    Dotted = 4,
+    Dashed = 5,
 }
 
 impl Into<bool> for Underline {
@@ -100,13 +104,14 @@ impl Into<bool> for Blink {
 
 impl CellAttributes {
     bitfield!(intensity, set_intensity, Intensity, 0b11, 0);
-    bitfield!(underline, set_underline, Underline, 0b11, 2);
     bitfield!(blink, set_blink, Blink, 0b11, 4);
     bitfield!(italic, set_italic, 6);
     bitfield!(reverse, set_reverse, 7);
     bitfield!(strikethrough, set_strikethrough, 8);
     bitfield!(invisible, set_invisible, 9);
     bitfield!(wrapped, set_wrapped, 10);
+    bitfield!(underline, set_underline, Underline, 0b111, 11);
+    bitfield!(overline, set_overline, 14);
 
     pub fn set_foreground<C: Into<ColorAttribute>>(&mut self, foreground: C) -> &mut Self {
         self.foreground = foreground.into();
@@ -118,6 +123,11 @@ impl CellAttributes {
         self
     }
 
+    pub fn set_underline_color<C: Into<ColorAttribute>>(&mut self, color: C) -> &mut Self {
+        self.underline_color = color.into();
+        self
+    }
+
     pub fn set_hyperlink(&mut self, link: Option<Arc<Hyperlink>>) -> &mut Self {
         self.hyperlink = link;
         self
@@ -128,6 +138,7 @@ impl CellAttributes {
             attributes: self.attributes,
             foreground: self.foreground,
             background: self.background,
+            underline_color: self.underline_color,
             hyperlink: None,
         }
     }
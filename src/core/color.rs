@@ -62,7 +62,7 @@ impl RgbColor {
     }
 
     pub fn from_rgb_str(s: &str) -> Option<RgbColor> {
-        if s.as_bytes()[0] == b'#' && s.len() == 7 {
+        if s.len() == 7 && s.starts_with('#') && s.is_ascii() {
             let mut chars = s.chars().skip(1);
 
             macro_rules! digit {
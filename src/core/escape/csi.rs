@@ -126,15 +126,15 @@ impl DeviceAttributeFlags {
         Ok(())
     }
 
-    fn from_params(params: &[i64]) -> Self {
+    fn from_params(params: &[i64]) -> Result<Self, ()> {
         let mut attributes = Vec::new();
         for p in params {
             match num::FromPrimitive::from_i64(*p) {
                 Some(c) => attributes.push(DeviceAttribute::Code(c)),
-                None => attributes.push(DeviceAttribute::Unspecified(*p as u16)),
+                None => attributes.push(DeviceAttribute::Unspecified(p.to_u16().ok_or(())?)),
             }
         }
-        Self { attributes }
+        Ok(Self { attributes })
     }
 }
 
@@ -263,11 +263,10 @@ pub enum Window {
     },
 }
 
-fn numstr_or_empty(x: &Option<i64>) -> String {
-    match x {
-        Some(x) => format!("{}", x),
-        None => "".to_owned(),
-    }
+/// Encodes optional parameters, stopping at the first omitted one since
+/// only trailing parameters can be left out.
+fn optional_params(params: &[Option<i64>]) -> String {
+    params.iter().take_while(|p| p.is_some()).flatten().map(|p| format!(";{}", p)).collect()
 }
 
 impl Display for Window {
@@ -277,13 +276,13 @@ impl Display for Window {
             Window::Iconify => write!(f, "2t"),
             Window::MoveWindow { x, y } => write!(f, "3;{};{}t", x, y),
             Window::ResizeWindowPixels { width, height } => {
-                write!(f, "4;{};{}t", numstr_or_empty(width), numstr_or_empty(height))
+                write!(f, "4{}t", optional_params(&[*height, *width]))
             }
             Window::RaiseWindow => write!(f, "5t"),
             Window::LowerWindow => write!(f, "6t"),
             Window::RefreshWindow => write!(f, "7t"),
             Window::ResizeWindowCells { width, height } => {
-                write!(f, "8;{};{}t", numstr_or_empty(width), numstr_or_empty(height))
+                write!(f, "8{}t", optional_params(&[*height, *width]))
             }
             Window::RestoreMaximizedWindow => write!(f, "9;0t"),
             Window::MaximizeWindow => write!(f, "9;1t"),
@@ -398,7 +397,7 @@ impl Display for Mode {
                     TerminalMode::Code(mode) => mode.to_u16().ok_or_else(|| FmtError)?,
                     TerminalMode::Unspecified(mode) => *mode,
                 };
-                write!(f, "?{}{}", value, $flag)
+                write!(f, "{}{}", value, $flag)
             }};
         }
         match self {
@@ -432,6 +431,8 @@ pub enum DecPrivateModeCode {
 
     AnyEventMouse = 1003,
 
+    FocusTracking = 1004,
+
     SGRMouse = 1006,
     ClearAndEnableAlternateScreen = 1049,
     EnableAlternateScreen = 47,
@@ -752,9 +753,11 @@ pub enum Sgr {
     Inverse(bool),
     Invisible(bool),
     StrikeThrough(bool),
+    Overline(bool),
     Font(Font),
     Foreground(ColorSpec),
     Background(ColorSpec),
+    UnderlineColor(ColorSpec),
 }
 
 impl Display for Sgr {
@@ -785,6 +788,9 @@ impl Display for Sgr {
             Sgr::Underline(Underline::Single) => code!(UnderlineOn),
             Sgr::Underline(Underline::Double) => code!(UnderlineDouble),
             Sgr::Underline(Underline::None) => code!(UnderlineOff),
+            Sgr::Underline(Underline::Curly) => write!(f, "{}:3m", SgrCode::UnderlineOn as i64)?,
+            Sgr::Underline(Underline::Dotted) => write!(f, "{}:4m", SgrCode::UnderlineOn as i64)?,
+            Sgr::Underline(Underline::Dashed) => write!(f, "{}:5m", SgrCode::UnderlineOn as i64)?,
             Sgr::Blink(Blink::Slow) => code!(BlinkOn),
             Sgr::Blink(Blink::Rapid) => code!(RapidBlinkOn),
             Sgr::Blink(Blink::None) => code!(BlinkOff),
@@ -796,6 +802,8 @@ impl Display for Sgr {
             Sgr::Invisible(false) => code!(InvisibleOff),
             Sgr::StrikeThrough(true) => code!(StrikeThroughOn),
             Sgr::StrikeThrough(false) => code!(StrikeThroughOff),
+            Sgr::Overline(true) => code!(OverlineOn),
+            Sgr::Overline(false) => code!(OverlineOff),
             Sgr::Font(Font::Default) => code!(DefaultFont),
             Sgr::Font(Font::Alternate(1)) => code!(AltFont1),
             Sgr::Font(Font::Alternate(2)) => code!(AltFont2),
@@ -865,6 +873,18 @@ impl Display for Sgr {
                 c.green,
                 c.blue
             )?,
+            Sgr::UnderlineColor(ColorSpec::Default) => code!(UnderlineColorDefault),
+            Sgr::UnderlineColor(ColorSpec::PaletteIndex(idx)) => {
+                write!(f, "{}:5:{}m", SgrCode::UnderlineColor as i64, idx)?
+            }
+            Sgr::UnderlineColor(ColorSpec::TrueColor(c)) => write!(
+                f,
+                "{}:2::{}:{}:{}m",
+                SgrCode::UnderlineColor as i64,
+                c.red,
+                c.green,
+                c.blue
+            )?,
         }
         Ok(())
     }
@@ -883,16 +903,28 @@ struct CSIParser<'a> {
     control: char,
 
     params: Option<&'a [i64]>,
+    num_params: usize,
+    /// `subparams[i]` is true if `params[i]` was separated from
+    /// its predecessor by a colon rather than a semicolon
+    subparams: &'a [bool],
 }
 
 impl CSI {
     pub fn parse<'a>(
         params: &'a [i64],
+        subparams: &'a [bool],
         intermediates: &'a [u8],
         ignored_extra_intermediates: bool,
         control: char,
     ) -> impl Iterator<Item = CSI> + 'a {
-        CSIParser { intermediates, ignored_extra_intermediates, control, params: Some(params) }
+        CSIParser {
+            intermediates,
+            ignored_extra_intermediates,
+            control,
+            params: Some(params),
+            num_params: params.len(),
+            subparams,
+        }
     }
 }
 
@@ -1121,7 +1153,7 @@ impl<'a> CSIParser<'a> {
                 params.len(),
                 params,
                 Device::DeviceAttributes(DeviceAttributes::Vt220(
-                    DeviceAttributeFlags::from_params(&params[1..]),
+                    DeviceAttributeFlags::from_params(&params[1..])?,
                 )),
             ))
         } else if !params.is_empty() && params[0] == 63 {
@@ -1129,7 +1161,7 @@ impl<'a> CSIParser<'a> {
                 params.len(),
                 params,
                 Device::DeviceAttributes(DeviceAttributes::Vt320(
-                    DeviceAttributeFlags::from_params(&params[1..]),
+                    DeviceAttributeFlags::from_params(&params[1..])?,
                 )),
             ))
         } else if !params.is_empty() && params[0] == 64 {
@@ -1137,7 +1169,7 @@ impl<'a> CSIParser<'a> {
                 params.len(),
                 params,
                 Device::DeviceAttributes(DeviceAttributes::Vt420(
-                    DeviceAttributeFlags::from_params(&params[1..]),
+                    DeviceAttributeFlags::from_params(&params[1..])?,
                 )),
             ))
         } else {
@@ -1192,21 +1224,37 @@ impl<'a> CSIParser<'a> {
     }
 
     fn dec(&mut self, params: &'a [i64]) -> Result<DecPrivateMode, ()> {
-        match num::FromPrimitive::from_i64(params[0]) {
-            None => Ok(DecPrivateMode::Unspecified(params[0].to_u16().ok_or(())?)),
+        let mode = *params.first().ok_or(())?;
+        match num::FromPrimitive::from_i64(mode) {
+            None => Ok(DecPrivateMode::Unspecified(mode.to_u16().ok_or(())?)),
             Some(mode) => Ok(self.advance_by(1, params, DecPrivateMode::Code(mode))),
         }
     }
 
     fn terminal_mode(&mut self, params: &'a [i64]) -> Result<TerminalMode, ()> {
-        match num::FromPrimitive::from_i64(params[0]) {
-            None => Ok(TerminalMode::Unspecified(params[0].to_u16().ok_or(())?)),
+        let mode = *params.first().ok_or(())?;
+        match num::FromPrimitive::from_i64(mode) {
+            None => Ok(TerminalMode::Unspecified(mode.to_u16().ok_or(())?)),
             Some(mode) => Ok(self.advance_by(1, params, TerminalMode::Code(mode))),
         }
     }
 
+    fn num_subparams(&self, params: &[i64]) -> usize {
+        let start = self.num_params - params.len();
+        (start + 1..self.num_params)
+            .take_while(|&idx| self.subparams.get(idx).cloned().unwrap_or(false))
+            .count()
+    }
+
     fn parse_sgr_color(&mut self, params: &'a [i64]) -> Result<ColorSpec, ()> {
-        if params.len() >= 5 && params[1] == 2 {
+        if self.num_subparams(params) >= 5 && params[1] == 2 {
+            // ITU T.416 form with a color space id: `38:2:<id>:r:g:b`
+            let red = to_u8(params[3])?;
+            let green = to_u8(params[4])?;
+            let blue = to_u8(params[5])?;
+            let res = RgbColor::new(red, green, blue).into();
+            Ok(self.advance_by(6, params, res))
+        } else if params.len() >= 5 && params[1] == 2 {
             let red = to_u8(params[2])?;
             let green = to_u8(params[3])?;
             let blue = to_u8(params[4])?;
@@ -1230,11 +1278,11 @@ impl<'a> CSIParser<'a> {
                 1 => Ok(Window::DeIconify),
                 2 => Ok(Window::Iconify),
                 3 => Ok(Window::MoveWindow { x: arg1.unwrap_or(0), y: arg2.unwrap_or(0) }),
-                4 => Ok(Window::ResizeWindowPixels { width: arg1, height: arg2 }),
+                4 => Ok(Window::ResizeWindowPixels { width: arg2, height: arg1 }),
                 5 => Ok(Window::RaiseWindow),
                 6 => Ok(Window::LowerWindow),
                 7 => Ok(Window::RefreshWindow),
-                8 => Ok(Window::ResizeWindowCells { width: arg1, height: arg2 }),
+                8 => Ok(Window::ResizeWindowCells { width: arg2, height: arg1 }),
                 9 => match arg1 {
                     Some(0) => Ok(Window::RestoreMaximizedWindow),
                     Some(1) => Ok(Window::MaximizeWindow),
@@ -1266,13 +1314,13 @@ impl<'a> CSIParser<'a> {
                 20 => Ok(Window::ReportIconLabel),
                 21 => Ok(Window::ReportWindowTitle),
                 22 => match arg1 {
-                    Some(0) => Ok(Window::PushIconAndWindowTitle),
+                    None | Some(0) => Ok(Window::PushIconAndWindowTitle),
                     Some(1) => Ok(Window::PushIconTitle),
                     Some(2) => Ok(Window::PushWindowTitle),
                     _ => Err(()),
                 },
                 23 => match arg1 {
-                    Some(0) => Ok(Window::PopIconAndWindowTitle),
+                    None | Some(0) => Ok(Window::PopIconAndWindowTitle),
                     Some(1) => Ok(Window::PopIconTitle),
                     Some(2) => Ok(Window::PopWindowTitle),
                     _ => Err(()),
@@ -1299,7 +1347,22 @@ impl<'a> CSIParser<'a> {
                     SgrCode::IntensityBold => one!(Sgr::Intensity(Intensity::Bold)),
                     SgrCode::IntensityDim => one!(Sgr::Intensity(Intensity::Half)),
                     SgrCode::NormalIntensity => one!(Sgr::Intensity(Intensity::Normal)),
-                    SgrCode::UnderlineOn => one!(Sgr::Underline(Underline::Single)),
+                    SgrCode::UnderlineOn => {
+                        if self.num_subparams(params) > 0 {
+                            let underline = match params[1] {
+                                0 => Underline::None,
+                                1 => Underline::Single,
+                                2 => Underline::Double,
+                                3 => Underline::Curly,
+                                4 => Underline::Dotted,
+                                5 => Underline::Dashed,
+                                _ => return Err(()),
+                            };
+                            Ok(self.advance_by(2, params, Sgr::Underline(underline)))
+                        } else {
+                            one!(Sgr::Underline(Underline::Single))
+                        }
+                    }
                     SgrCode::UnderlineDouble => one!(Sgr::Underline(Underline::Double)),
                     SgrCode::UnderlineOff => one!(Sgr::Underline(Underline::None)),
                     SgrCode::BlinkOn => one!(Sgr::Blink(Blink::Slow)),
@@ -1357,12 +1420,19 @@ impl<'a> CSIParser<'a> {
                         one!(Sgr::Background(AnsiColor::White.into()))
                     }
 
+                    SgrCode::UnderlineColor => {
+                        self.parse_sgr_color(params).map(Sgr::UnderlineColor)
+                    }
+                    SgrCode::UnderlineColorDefault => one!(Sgr::UnderlineColor(ColorSpec::Default)),
+
                     SgrCode::InverseOn => one!(Sgr::Inverse(true)),
                     SgrCode::InverseOff => one!(Sgr::Inverse(false)),
                     SgrCode::InvisibleOn => one!(Sgr::Invisible(true)),
                     SgrCode::InvisibleOff => one!(Sgr::Invisible(false)),
                     SgrCode::StrikeThroughOn => one!(Sgr::StrikeThrough(true)),
                     SgrCode::StrikeThroughOff => one!(Sgr::StrikeThrough(false)),
+                    SgrCode::OverlineOn => one!(Sgr::Overline(true)),
+                    SgrCode::OverlineOff => one!(Sgr::Overline(false)),
                     SgrCode::DefaultFont => one!(Sgr::Font(Font::Default)),
                     SgrCode::AltFont1 => one!(Sgr::Font(Font::Alternate(1))),
                     SgrCode::AltFont2 => one!(Sgr::Font(Font::Alternate(2))),
@@ -1430,6 +1500,8 @@ pub enum SgrCode {
     BackgroundCyan = 46,
     BackgroundWhite = 47,
     BackgroundDefault = 49,
+    OverlineOn = 53,
+    OverlineOff = 55,
 
     ForegroundBrightBlack = 90,
     ForegroundBrightRed = 91,
@@ -1451,6 +1523,8 @@ pub enum SgrCode {
 
     ForegroundColor = 38,
     BackgroundColor = 48,
+    UnderlineColor = 58,
+    UnderlineColorDefault = 59,
 }
 
 impl<'a> Iterator for CSIParser<'a> {
@@ -1473,3 +1547,36 @@ impl<'a> Iterator for CSIParser<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(control: char, params: &[i64], intermediates: &[u8]) -> Vec<CSI> {
+        CSI::parse(params, &[], intermediates, false, control).collect()
+    }
+
+    fn encode(seq: &[CSI]) -> String {
+        seq.iter().map(|csi| csi.to_string()).collect()
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!(encode(&parse('h', &[4], b"")), "\x1b[4h");
+        assert_eq!(encode(&parse('l', &[25], b"?")), "\x1b[?25l");
+        assert!(matches!(parse('h', &[], b"?")[..], [CSI::Unspecified(_)]));
+        assert!(matches!(parse('l', &[], b"")[..], [CSI::Unspecified(_)]));
+    }
+
+    #[test]
+    fn window() {
+        assert_eq!(encode(&parse('t', &[8], b"")), "\x1b[8t");
+        assert_eq!(encode(&parse('t', &[4, 100], b"")), "\x1b[4;100t");
+        assert_eq!(encode(&parse('t', &[8, 24, 80], b"")), "\x1b[8;24;80t");
+    }
+
+    #[test]
+    fn device_attributes() {
+        assert!(matches!(parse('c', &[62, 70000], b"?")[..], [CSI::Unspecified(_)]));
+    }
+}
@@ -33,6 +33,9 @@ pub enum OperatingSystemCommand {
     QuerySelection(Selection),
     SetSelection(Selection, String),
     SystemNotification(String),
+    CurrentWorkingDirectory(String),
+    RxvtExtension(Vec<String>),
+    FinalTermSemanticPrompt(FinalTermSemanticPrompt),
     ChangeColorNumber(Vec<ChangeColorPair>),
     ChangeDynamicColors(DynamicColorNumber, Vec<ColorOrQuery>),
     Unspecified(Vec<Vec<u8>>),
@@ -53,6 +56,30 @@ pub enum DynamicColorNumber {
     HighlightForegroundColor = 19,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinalTermSemanticPrompt {
+    /// `A`: the prompt is about to be drawn
+    PromptStart,
+    /// `B`: the prompt has been drawn and user input follows
+    CommandStart,
+    /// `C`: the command was submitted and its output follows
+    OutputStart,
+    /// `D`: the command finished, optionally with its exit status
+    CommandFinished(Option<i32>),
+}
+
+impl Display for FinalTermSemanticPrompt {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        match self {
+            FinalTermSemanticPrompt::PromptStart => write!(f, "A"),
+            FinalTermSemanticPrompt::CommandStart => write!(f, "B"),
+            FinalTermSemanticPrompt::OutputStart => write!(f, "C"),
+            FinalTermSemanticPrompt::CommandFinished(None) => write!(f, "D"),
+            FinalTermSemanticPrompt::CommandFinished(Some(status)) => write!(f, "D;{}", status),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChangeColorPair {
     pub palette_index: u8,
@@ -160,6 +187,39 @@ impl OperatingSystemCommand {
         }
     }
 
+    fn parse_current_working_directory(osc: &[&[u8]]) -> anyhow::Result<Self> {
+        anyhow::ensure!(osc.len() >= 2, "wrong param count");
+        let uri = str::from_utf8(&osc[1..].join(&b';'))?.to_owned();
+        Ok(OperatingSystemCommand::CurrentWorkingDirectory(uri))
+    }
+
+    fn parse_rxvt_extension(osc: &[&[u8]]) -> anyhow::Result<Self> {
+        anyhow::ensure!(osc.len() >= 2, "wrong param count");
+        let mut params = vec![];
+        for param in &osc[1..] {
+            params.push(String::from_utf8(param.to_vec())?);
+        }
+        Ok(OperatingSystemCommand::RxvtExtension(params))
+    }
+
+    fn parse_semantic_prompt(osc: &[&[u8]]) -> anyhow::Result<Self> {
+        anyhow::ensure!(osc.len() >= 2, "wrong param count");
+        let mark = match osc[1] {
+            b"A" => FinalTermSemanticPrompt::PromptStart,
+            b"B" => FinalTermSemanticPrompt::CommandStart,
+            b"C" => FinalTermSemanticPrompt::OutputStart,
+            b"D" => {
+                let status = match osc.get(2) {
+                    Some(status) => str::from_utf8(status)?.parse().ok(),
+                    None => None,
+                };
+                FinalTermSemanticPrompt::CommandFinished(status)
+            }
+            _ => bail!("unknown OSC 133 mark {:?}", osc[1]),
+        };
+        Ok(OperatingSystemCommand::FinalTermSemanticPrompt(mark))
+    }
+
     fn parse_change_color_number(osc: &[&[u8]]) -> anyhow::Result<Self> {
         let mut pairs = vec![];
         let mut iter = osc.iter();
@@ -228,6 +288,9 @@ impl OperatingSystemCommand {
             SetHyperlink => Ok(OperatingSystemCommand::SetHyperlink(Hyperlink::parse(osc)?)),
             ManipulateSelectionData => Self::parse_selection(osc),
             SystemNotification => single_string!(SystemNotification),
+            SetCurrentWorkingDirectory => Self::parse_current_working_directory(osc),
+            RxvtProprietary => Self::parse_rxvt_extension(osc),
+            FinalTermSemanticPrompt => Self::parse_semantic_prompt(osc),
             ChangeColorNumber => Self::parse_change_color_number(osc),
             SetTextForegroundColor
             | SetTextBackgroundColor
@@ -275,6 +338,7 @@ pub enum OperatingSystemCommandCode {
     SetFont = 50,
     EmacsShell = 51,
     ManipulateSelectionData = 52,
+    FinalTermSemanticPrompt = 133,
     RxvtProprietary = 777,
 }
 
@@ -307,10 +371,13 @@ impl Display for OperatingSystemCommand {
             QuerySelection(s) => write!(f, "52;{};?", s)?,
             SetSelection(s, val) => write!(f, "52;{};{}", s, base64::encode(val))?,
             SystemNotification(s) => write!(f, "9;{}", s)?,
+            CurrentWorkingDirectory(uri) => write!(f, "7;{}", uri)?,
+            RxvtExtension(params) => write!(f, "777;{}", params.join(";"))?,
+            FinalTermSemanticPrompt(mark) => write!(f, "133;{}", mark)?,
             ChangeColorNumber(specs) => {
-                write!(f, "4;")?;
+                write!(f, "4")?;
                 for pair in specs {
-                    write!(f, "{};{}", pair.palette_index, pair.color)?
+                    write!(f, ";{};{}", pair.palette_index, pair.color)?
                 }
             }
             ChangeDynamicColors(first_color, colors) => {
@@ -324,3 +391,36 @@ impl Display for OperatingSystemCommand {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(osc: &str) -> OperatingSystemCommand {
+        let params: Vec<&[u8]> = osc.split(';').map(str::as_bytes).collect();
+        OperatingSystemCommand::parse(&params)
+    }
+
+    #[test]
+    fn change_color_number() {
+        let osc = parse("4;1;#102030;2;?");
+        assert_eq!(osc.to_string(), "\x1b]4;1;#102030;2;?\x07");
+        assert_eq!(
+            osc,
+            OperatingSystemCommand::ChangeColorNumber(vec![
+                ChangeColorPair {
+                    palette_index: 1,
+                    color: ColorOrQuery::Color(RgbColor::new(0x10, 0x20, 0x30)),
+                },
+                ChangeColorPair { palette_index: 2, color: ColorOrQuery::Query },
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_colors() {
+        assert!(matches!(parse("4;1;"), OperatingSystemCommand::Unspecified(_)));
+        assert!(matches!(parse("10;"), OperatingSystemCommand::Unspecified(_)));
+        assert!(matches!(parse("11;#\u{e9}\u{e9}\u{e9}"), OperatingSystemCommand::Unspecified(_)));
+    }
+}
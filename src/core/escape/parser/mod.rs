@@ -4,6 +4,7 @@ use vtparse::{VTActor, VTParser};
 
 pub struct Parser {
     state_machine: VTParser,
+    subparams: SubParams,
 }
 
 impl Default for Parser {
@@ -14,17 +15,110 @@ impl Default for Parser {
 
 impl Parser {
     pub fn new() -> Self {
-        Self { state_machine: VTParser::new() }
+        Self { state_machine: VTParser::new(), subparams: SubParams::default() }
     }
 
     pub fn parse<F: FnMut(Action)>(&mut self, bytes: &[u8], mut callback: F) {
-        let mut perform = Performer { callback: &mut callback };
-        self.state_machine.parse(bytes, &mut perform);
+        let mut perform = Performer { callback: &mut callback, subparams: &mut self.subparams };
+        let mut start = 0;
+        for (idx, &byte) in bytes.iter().enumerate() {
+            match perform.subparams.advance(byte) {
+                SubParamAction::Feed => {}
+                SubParamAction::StartCsi => {
+                    // Dispatch anything pending before forgetting the
+                    // sub-parameters of the previous sequence
+                    self.state_machine.parse(&bytes[start..idx], &mut perform);
+                    perform.subparams.reset();
+                    start = idx;
+                }
+                SubParamAction::Colon => {
+                    self.state_machine.parse(&bytes[start..idx], &mut perform);
+                    self.state_machine.parse(b";", &mut perform);
+                    start = idx + 1;
+                }
+            }
+        }
+        self.state_machine.parse(&bytes[start..], &mut perform);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SubParamState {
+    Ground,
+    Escape,
+    Csi,
+}
+
+enum SubParamAction {
+    Feed,
+    StartCsi,
+    Colon,
+}
+
+/// vtparse doesn't understand colon separated sub-parameters such as
+/// those used by `CSI 4:3 m`, so colons are fed to it as semicolons
+/// and we remember which parameters they introduced.
+struct SubParams {
+    state: SubParamState,
+    /// The continuation bytes still expected by a UTF-8 sequence; these
+    /// may be 0x9b without it being a C1 CSI
+    utf8_remaining: u8,
+    colons: Vec<bool>,
+}
+
+impl Default for SubParams {
+    fn default() -> Self {
+        Self { state: SubParamState::Ground, utf8_remaining: 0, colons: vec![] }
+    }
+}
+
+impl SubParams {
+    fn advance(&mut self, byte: u8) -> SubParamAction {
+        if self.utf8_remaining > 0 {
+            if let 0x80..=0xbf = byte {
+                self.utf8_remaining -= 1;
+                return SubParamAction::Feed;
+            }
+            self.utf8_remaining = 0;
+        }
+        match (self.state, byte) {
+            (_, 0x1b) => self.state = SubParamState::Escape,
+            (SubParamState::Escape, b'[') | (_, 0x9b) => {
+                self.state = SubParamState::Csi;
+                return SubParamAction::StartCsi;
+            }
+            (SubParamState::Csi, b';') => self.colons.push(false),
+            (SubParamState::Csi, b':') => {
+                self.colons.push(true);
+                return SubParamAction::Colon;
+            }
+            (SubParamState::Csi, 0x18) | (SubParamState::Csi, 0x1a) => {
+                self.state = SubParamState::Ground
+            }
+            (SubParamState::Csi, 0x40..=0x7e) => self.state = SubParamState::Ground,
+            (SubParamState::Csi, _) => {}
+            (_, 0xc2..=0xdf) => self.start_utf8(1),
+            (_, 0xe0..=0xef) => self.start_utf8(2),
+            (_, 0xf0..=0xf4) => self.start_utf8(3),
+            _ => self.state = SubParamState::Ground,
+        }
+        SubParamAction::Feed
+    }
+
+    fn start_utf8(&mut self, continuation_bytes: u8) {
+        self.state = SubParamState::Ground;
+        self.utf8_remaining = continuation_bytes;
+    }
+
+    fn reset(&mut self) {
+        self.colons.clear();
+        self.colons.push(false);
     }
 }
 
 struct Performer<'a, F: FnMut(Action) + 'a> {
     callback: &'a mut F,
+    subparams: &'a mut SubParams,
 }
 
 impl<'a, F: FnMut(Action)> VTActor for Performer<'a, F> {
@@ -72,9 +166,13 @@ impl<'a, F: FnMut(Action)> VTActor for Performer<'a, F> {
         ignored_extra_intermediates: bool,
         control: u8,
     ) {
-        for action in
-            CSI::parse(params, intermediates, ignored_extra_intermediates, control as char)
-        {
+        for action in CSI::parse(
+            params,
+            &self.subparams.colons,
+            intermediates,
+            ignored_extra_intermediates,
+            control as char,
+        ) {
             (self.callback)(Action::CSI(action));
         }
     }
@@ -92,3 +190,61 @@ impl<'a, F: FnMut(Action)> VTActor for Performer<'a, F> {
         )));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::cell::Underline;
+    use crate::core::color::{ColorSpec, RgbColor};
+    use crate::core::escape::csi::Sgr;
+
+    fn parse(bytes: &[u8]) -> Vec<Action> {
+        let mut actions = vec![];
+        Parser::new().parse(bytes, |action| actions.push(action));
+        actions
+    }
+
+    fn sgr(sgr: Sgr) -> Action {
+        Action::CSI(CSI::Sgr(sgr))
+    }
+
+    #[test]
+    fn underline_style() {
+        let curly = vec![sgr(Sgr::Underline(Underline::Curly))];
+        assert_eq!(parse(b"\x1b[4:3m"), curly);
+        assert_eq!(parse(b"\x9b4:3m"), curly);
+        assert_eq!(
+            parse(b"\x1b[4:3m\x1b[4;3m"),
+            vec![
+                sgr(Sgr::Underline(Underline::Curly)),
+                sgr(Sgr::Underline(Underline::Single)),
+                sgr(Sgr::Italic(true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn underline_color() {
+        let rgb = vec![sgr(Sgr::UnderlineColor(RgbColor::new(1, 2, 3).into()))];
+        assert_eq!(parse(b"\x1b[58:2::1:2:3m"), rgb);
+        assert_eq!(parse(b"\x9b58:2::1:2:3m"), rgb);
+
+        let indexed = vec![sgr(Sgr::UnderlineColor(ColorSpec::PaletteIndex(9)))];
+        assert_eq!(parse(b"\x1b[58;5;9m"), indexed);
+        assert_eq!(parse(b"\x9b58;5;9m"), indexed);
+    }
+
+    #[test]
+    fn utf8_is_not_csi() {
+        // U+011B is encoded as c4 9b
+        assert_eq!(
+            parse("\u{11b}4:3".as_bytes()),
+            vec![
+                Action::Print('\u{11b}'),
+                Action::Print('4'),
+                Action::Print(':'),
+                Action::Print('3')
+            ]
+        );
+    }
+}
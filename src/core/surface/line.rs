@@ -18,10 +18,25 @@ bitflags! {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SemanticType {
+    Output,
+    Prompt,
+    Input,
+}
+
+impl Default for SemanticType {
+    fn default() -> Self {
+        SemanticType::Output
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Line {
     bits: LineBits,
     cells: Vec<Cell>,
+    semantic_type: SemanticType,
+    exit_code: Option<i32>,
 }
 
 pub enum DoubleClickRange {
@@ -34,7 +49,7 @@ impl Line {
         let mut cells = Vec::with_capacity(width);
         cells.resize(width, Cell::default());
         let bits = LineBits::DIRTY;
-        Self { bits, cells }
+        Self { bits, cells, semantic_type: SemanticType::default(), exit_code: None }
     }
 
     pub fn from_text(s: &str, attrs: &CellAttributes) -> Line {
@@ -49,7 +64,20 @@ impl Line {
             }
         }
 
-        Line { cells, bits: LineBits::DIRTY }
+        Line {
+            cells,
+            bits: LineBits::DIRTY,
+            semantic_type: SemanticType::default(),
+            exit_code: None,
+        }
+    }
+
+    pub fn from_cells(cells: Vec<Cell>) -> Line {
+        let mut bits = LineBits::DIRTY;
+        if cells.iter().any(|cell| cell.attrs().hyperlink.is_some()) {
+            bits |= LineBits::HAS_HYPERLINK;
+        }
+        Line { cells, bits, semantic_type: SemanticType::default(), exit_code: None }
     }
 
     pub fn resize_and_clear(&mut self, width: usize) {
@@ -57,6 +85,8 @@ impl Line {
         self.cells.clear();
         self.cells.resize(width, blank);
         self.bits = LineBits::DIRTY;
+        self.semantic_type = SemanticType::default();
+        self.exit_code = None;
     }
 
     pub fn resize(&mut self, width: usize) {
@@ -79,6 +109,29 @@ impl Line {
         self.bits &= !LineBits::DIRTY;
     }
 
+    #[inline]
+    pub fn semantic_type(&self) -> SemanticType {
+        self.semantic_type
+    }
+
+    pub fn set_semantic_type(&mut self, semantic_type: SemanticType) {
+        if self.semantic_type != semantic_type {
+            self.semantic_type = semantic_type;
+            self.exit_code = None;
+            self.bits |= LineBits::DIRTY;
+        }
+    }
+
+    #[inline]
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn set_exit_code(&mut self, exit_code: Option<i32>) {
+        self.exit_code = exit_code;
+        self.bits |= LineBits::DIRTY;
+    }
+
     pub fn invalidate_implicit_hyperlinks(&mut self) {
         if (self.bits & (LineBits::SCANNED_IMPLICIT_HYPERLINKS | LineBits::HAS_IMPLICIT_HYPERLINKS))
             == LineBits::NONE
@@ -53,6 +53,35 @@ impl FontSet {
     }
 }
 
+pub struct CharSet {
+    cset: *mut FcCharSet,
+}
+
+impl Drop for CharSet {
+    fn drop(&mut self) {
+        unsafe {
+            FcCharSetDestroy(self.cset);
+        }
+    }
+}
+
+impl CharSet {
+    pub fn new() -> Result<CharSet, Error> {
+        unsafe {
+            let cset = FcCharSetCreate();
+            ensure!(!cset.is_null(), "FcCharSetCreate failed");
+            Ok(CharSet { cset })
+        }
+    }
+
+    pub fn add(&mut self, c: char) -> Result<(), Error> {
+        unsafe {
+            ensure!(FcCharSetAddChar(self.cset, c as u32) != 0, "failed to add {:?} to charset", c);
+            Ok(())
+        }
+    }
+}
+
 #[repr(C)]
 pub enum MatchKind {
     Pattern = FcMatchPattern as isize,
@@ -140,6 +169,17 @@ impl Pattern {
         }
     }
 
+    pub fn add_charset(&mut self, charset: &CharSet) -> Result<(), Error> {
+        let key = CString::new("charset")?;
+        unsafe {
+            ensure!(
+                FcPatternAddCharSet(self.pat, key.as_ptr(), charset.cset) != 0,
+                "failed to set charset property"
+            );
+            Ok(())
+        }
+    }
+
     pub fn family(&mut self, family: &str) -> Result<(), Error> {
         self.add_string("family", family)
     }
@@ -203,6 +243,25 @@ impl Pattern {
         self.get_string("file")
     }
 
+    /// The index of the face within its file.
+    pub fn get_index(&self) -> Result<u32, Error> {
+        self.get_integer("index").map(|index| index as u32)
+    }
+
+    pub fn get_integer(&self, key: &str) -> Result<i32, Error> {
+        unsafe {
+            let key = CString::new(key)?;
+            let mut ival: i32 = 0;
+            let res =
+                FcResultWrap(FcPatternGetInteger(self.pat, key.as_ptr(), 0, &mut ival as *mut _));
+            if !res.succeeded() {
+                Err(res.as_err())
+            } else {
+                Ok(ival)
+            }
+        }
+    }
+
     #[allow(dead_code)]
     pub fn get_double(&self, key: &str) -> Result<f64, Error> {
         unsafe {
@@ -218,6 +277,24 @@ impl Pattern {
         }
     }
 
+    pub fn has_char(&self, c: char) -> Result<bool, Error> {
+        unsafe {
+            let key = CString::new("charset")?;
+            let mut cset: *mut FcCharSet = ptr::null_mut();
+            let res = FcResultWrap(FcPatternGetCharSet(
+                self.pat,
+                key.as_ptr(),
+                0,
+                &mut cset as *mut *mut FcCharSet,
+            ));
+            if !res.succeeded() {
+                Err(res.as_err())
+            } else {
+                Ok(FcCharSetHasChar(cset, c as u32) != 0)
+            }
+        }
+    }
+
     pub fn get_string(&self, key: &str) -> Result<String, Error> {
         unsafe {
             let key = CString::new(key)?;
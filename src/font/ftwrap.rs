@@ -17,6 +17,10 @@ fn ft_result<T>(err: FT_Error, t: T) -> anyhow::Result<T> {
     }
 }
 
+extern "C" {
+    fn FT_GlyphSlot_Embolden(slot: FT_GlyphSlot);
+}
+
 pub fn compute_load_flags_for_mode(render_mode: FT_Render_Mode) -> i32 {
     FT_LOAD_COLOR as i32 | (render_mode as i32) << 16
 }
@@ -36,39 +40,43 @@ impl Drop for Face {
 
 impl Face {
     pub fn set_font_size(&mut self, size: f64, dpi: u32) -> anyhow::Result<(f64, f64)> {
-        let size = (size * 64.0) as FT_F26Dot6;
+        let pixel_size = size * f64::from(dpi) / 72.0;
+        let scalable =
+            unsafe { ((*self.face).face_flags as u32 & FT_FACE_FLAG_SCALABLE as u32) != 0 };
+        if !scalable {
+            return self.select_fixed_size(pixel_size);
+        }
 
-        let (cell_width, cell_height) = match self.set_char_size(size, size, dpi, dpi) {
-            Ok(_) => self.cell_metrics(),
-            Err(err) => {
-                let sizes = unsafe {
-                    let rec = &(*self.face);
-                    std::slice::from_raw_parts(rec.available_sizes, rec.num_fixed_sizes as usize)
-                };
-                if sizes.is_empty() {
-                    return Err(err);
-                }
+        let size = (size * 64.0) as FT_F26Dot6;
+        match self.set_char_size(size, size, dpi, dpi) {
+            Ok(_) => Ok(self.cell_metrics()),
+            Err(err) => self.select_fixed_size(pixel_size).map_err(|_| err),
+        }
+    }
 
-                let mut best = 0;
-                let mut best_size = 0;
-                let mut cell_width = 0;
-                let mut cell_height = 0;
-
-                for (idx, info) in sizes.iter().enumerate() {
-                    let size = best_size.max(info.height);
-                    if size > best_size {
-                        best = idx;
-                        best_size = size;
-                        cell_width = info.width;
-                        cell_height = info.height;
-                    }
-                }
-                self.select_size(best)?;
-                (f64::from(cell_width), f64::from(cell_height))
-            }
+    fn select_fixed_size(&mut self, pixel_size: f64) -> anyhow::Result<(f64, f64)> {
+        let sizes = unsafe {
+            let rec = &(*self.face);
+            std::slice::from_raw_parts(rec.available_sizes, rec.num_fixed_sizes as usize)
         };
 
-        Ok((cell_width, cell_height))
+        // Prefer the smallest strike that is at least as large as the requested
+        // size so that it only ever needs to be scaled down.
+        let best = sizes
+            .iter()
+            .enumerate()
+            .filter(|(_, info)| info.y_ppem as f64 / 64.0 >= pixel_size)
+            .min_by_key(|(_, info)| info.y_ppem)
+            .or_else(|| sizes.iter().enumerate().max_by_key(|(_, info)| info.y_ppem))
+            .map(|(idx, info)| (idx, f64::from(info.width), f64::from(info.height)));
+
+        match best {
+            Some((idx, cell_width, cell_height)) => {
+                self.select_size(idx)?;
+                Ok((cell_width, cell_height))
+            }
+            None => bail!("font has no scalable outlines and no fixed sizes"),
+        }
     }
 
     pub fn set_char_size(
@@ -107,10 +115,14 @@ impl Face {
         glyph_index: FT_UInt,
         load_flags: FT_Int32,
         render_mode: FT_Render_Mode,
+        embolden: bool,
     ) -> anyhow::Result<&FT_GlyphSlotRec_> {
         unsafe {
             let res = FT_Load_Glyph(self.face, glyph_index, load_flags);
             if succeeded(res) {
+                if embolden {
+                    FT_GlyphSlot_Embolden((*self.face).glyph);
+                }
                 let render = FT_Render_Glyph((*self.face).glyph, render_mode);
                 if !succeeded(render) {
                     bail!("FT_Render_Glyph failed: {:?}", render);
@@ -31,8 +31,9 @@ impl FontLocator for FontConfigFontLocator {
             for (idx, pat) in font_list.iter().enumerate() {
                 pattern.render_prepare(&pat)?;
                 let file = pat.get_file()?;
+                let index = pat.get_index().unwrap_or(0);
 
-                let handle = FontDataHandle::OnDisk { path: file.into(), index: 0 };
+                let handle = FontDataHandle::OnDisk { path: file.into(), index };
 
                 if idx == 0 {
                     fonts.push(handle);
@@ -46,4 +47,39 @@ impl FontLocator for FontConfigFontLocator {
 
         Ok(fonts)
     }
+
+    fn locate_fallback_for_codepoints(
+        &self,
+        codepoints: &[char],
+    ) -> anyhow::Result<Vec<(FontDataHandle, Vec<char>)>> {
+        let mut charset = fcwrap::CharSet::new()?;
+        for &c in codepoints {
+            charset.add(c)?;
+        }
+
+        let mut pattern = FontPattern::new()?;
+        pattern.add_charset(&charset)?;
+        pattern.config_substitute(fcwrap::MatchKind::Pattern)?;
+        pattern.default_substitute();
+
+        let font_list = pattern.sort(true)?;
+
+        let mut fonts = vec![];
+        let mut uncovered = codepoints.to_vec();
+        for pat in font_list.iter() {
+            let (covered, rest): (Vec<char>, Vec<char>) =
+                uncovered.iter().partition(|&&c| pat.has_char(c).unwrap_or(false));
+            uncovered = rest;
+            if !covered.is_empty() {
+                let file = pat.get_file()?;
+                let index = pat.get_index().unwrap_or(0);
+                fonts.push((FontDataHandle::OnDisk { path: file.into(), index }, covered));
+            }
+            if uncovered.is_empty() {
+                break;
+            }
+        }
+
+        Ok(fonts)
+    }
 }
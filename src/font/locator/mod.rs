@@ -10,6 +10,7 @@ pub mod font_config;
 #[cfg(target_os = "macos")]
 pub mod font_loader;
 
+#[derive(Clone, PartialEq)]
 pub enum FontDataHandle {
     OnDisk { path: PathBuf, index: u32 },
     Memory { data: Vec<u8>, index: u32 },
@@ -18,6 +19,15 @@ pub enum FontDataHandle {
 pub trait FontLocator {
     fn load_fonts(&self, fonts_selection: &[FontAttributes])
         -> anyhow::Result<Vec<FontDataHandle>>;
+
+    /// Finds fonts covering `codepoints`, each with the codepoints that it
+    /// was picked for.
+    fn locate_fallback_for_codepoints(
+        &self,
+        _codepoints: &[char],
+    ) -> anyhow::Result<Vec<(FontDataHandle, Vec<char>)>> {
+        Ok(vec![])
+    }
 }
 
 #[derive(Debug, Deserialize, Clone, Copy)]
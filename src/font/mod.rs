@@ -1,6 +1,7 @@
 use anyhow::{anyhow, Error};
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::rc::Rc;
 use std::sync::Arc;
 
@@ -13,21 +14,74 @@ pub mod shaper;
 #[cfg(all(unix, not(target_os = "macos")))]
 pub mod fcwrap;
 
-use crate::font::locator::{FontLocator, FontLocatorSelection};
+use crate::font::locator::{FontDataHandle, FontLocator, FontLocatorSelection};
 pub use crate::font::rasterizer::RasterizedGlyph;
 use crate::font::rasterizer::{FontRasterizer, FontRasterizerSelection};
 pub use crate::font::shaper::{FallbackIdx, FontMetrics, GlyphInfo};
 use crate::font::shaper::{FontShaper, FontShaperSelection};
 
-use super::config::{Config, TextStyle};
+use super::config::{Config, FontAttributes, TextStyle};
 use crate::term::CellAttributes;
 
+struct FallbackCache {
+    locator: Rc<dyn FontLocator>,
+    /// The fonts picked for each codepoint asked about so far, empty when
+    /// no font covers it, so that the locator is asked about it only once.
+    by_codepoint: RefCell<HashMap<char, Vec<FontDataHandle>>>,
+}
+
+impl FallbackCache {
+    fn new(locator: Rc<dyn FontLocator>) -> Self {
+        Self { locator, by_codepoint: RefCell::new(HashMap::new()) }
+    }
+
+    fn fonts_for(&self, codepoints: &[char]) -> anyhow::Result<Vec<FontDataHandle>> {
+        let mut uncached: Vec<char> = {
+            let cache = self.by_codepoint.borrow();
+            codepoints.iter().filter(|c| !cache.contains_key(c)).cloned().collect()
+        };
+        uncached.sort_unstable();
+        uncached.dedup();
+        if !uncached.is_empty() {
+            let located = self.locator.locate_fallback_for_codepoints(&uncached)?;
+            let mut cache = self.by_codepoint.borrow_mut();
+            for &c in &uncached {
+                cache.insert(c, vec![]);
+            }
+            for (handle, covered) in located {
+                for c in covered {
+                    cache.entry(c).or_default().push(handle.clone());
+                }
+            }
+        }
+
+        let cache = self.by_codepoint.borrow();
+        let mut fonts: Vec<FontDataHandle> = vec![];
+        for handle in codepoints.iter().flat_map(|c| &cache[c]) {
+            if !fonts.contains(handle) {
+                fonts.push(handle.clone());
+            }
+        }
+        Ok(fonts)
+    }
+}
+
+/// The attributes for the fonts found to cover missing glyphs: rendered
+/// like the primary font, but without the harfbuzz features configured for
+/// it, which are meant for its family only.
+fn fallback_attributes(primary: &FontAttributes) -> FontAttributes {
+    FontAttributes { harfbuzz_features: None, ..primary.clone() }
+}
+
 pub struct LoadedFont {
-    rasterizers: Vec<Box<dyn FontRasterizer>>,
+    rasterizers: RefCell<Vec<Box<dyn FontRasterizer>>>,
     shaper: Box<dyn FontShaper>,
     metrics: FontMetrics,
     font_size: f64,
     dpi: u32,
+    handles: RefCell<Vec<FontDataHandle>>,
+    attributes: FontAttributes,
+    fallback: Rc<FallbackCache>,
 }
 
 impl LoadedFont {
@@ -36,7 +90,60 @@ impl LoadedFont {
     }
 
     pub fn shape(&self, text: &str) -> anyhow::Result<Vec<GlyphInfo>> {
-        self.shaper.shape(text, self.font_size, self.dpi)
+        self.do_shape(text, true)
+    }
+
+    /// Shapes `text` with ligatures everywhere but in the byte range
+    /// `plain`, which is shaped on its own; the clusters of the glyphs stay
+    /// relative to the whole of `text`.
+    pub fn shape_without_ligatures_in(
+        &self,
+        text: &str,
+        plain: Range<usize>,
+    ) -> anyhow::Result<Vec<GlyphInfo>> {
+        let parts = [
+            (0, plain.start, true),
+            (plain.start, plain.end, false),
+            (plain.end, text.len(), true),
+        ];
+        let mut glyphs = vec![];
+        for &(start, end, ligatures) in &parts {
+            if start == end {
+                continue;
+            }
+            for mut info in self.do_shape(&text[start..end], ligatures)? {
+                info.cluster += start as u32;
+                glyphs.push(info);
+            }
+        }
+        Ok(glyphs)
+    }
+
+    fn do_shape(&self, text: &str, ligatures: bool) -> anyhow::Result<Vec<GlyphInfo>> {
+        let mut no_glyphs = vec![];
+        let glyphs =
+            self.shaper.shape(text, self.font_size, self.dpi, ligatures, &mut no_glyphs)?;
+        if no_glyphs.is_empty() || !self.load_fallbacks(&no_glyphs)? {
+            return Ok(glyphs);
+        }
+        self.shaper.shape(text, self.font_size, self.dpi, ligatures, &mut vec![])
+    }
+
+    fn load_fallbacks(&self, no_glyphs: &[char]) -> anyhow::Result<bool> {
+        let attributes = fallback_attributes(&self.attributes);
+        let mut added = false;
+        for handle in self.fallback.fonts_for(no_glyphs)? {
+            if self.handles.borrow().contains(&handle) {
+                continue;
+            }
+            let rasterizer =
+                FontRasterizerSelection::get_default().new_rasterizer(&handle, &attributes)?;
+            self.shaper.add_font(&handle, &attributes)?;
+            self.rasterizers.borrow_mut().push(rasterizer);
+            self.handles.borrow_mut().push(handle);
+            added = true;
+        }
+        Ok(added)
     }
 
     pub fn rasterize_glyph(
@@ -44,8 +151,8 @@ impl LoadedFont {
         glyph_pos: u32,
         fallback: FallbackIdx,
     ) -> anyhow::Result<RasterizedGlyph> {
-        let rasterizer = self
-            .rasterizers
+        let rasterizers = self.rasterizers.borrow();
+        let rasterizer = rasterizers
             .get(fallback)
             .ok_or_else(|| anyhow!("no such fallback index: {}", fallback))?;
         rasterizer.rasterize_glyph(glyph_pos, self.font_size, self.dpi)
@@ -58,15 +165,18 @@ pub struct FontConfiguration {
     dpi_scale: RefCell<f64>,
     font_scale: RefCell<f64>,
     config: Arc<Config>,
-    locator: Box<dyn FontLocator>,
+    locator: Rc<dyn FontLocator>,
+    fallback: Rc<FallbackCache>,
 }
 
 impl FontConfiguration {
     pub fn new(config: Arc<Config>) -> Self {
-        let locator = FontLocatorSelection::get_default().new_locator();
+        let locator: Rc<dyn FontLocator> = FontLocatorSelection::get_default().new_locator().into();
+        let fallback = Rc::new(FallbackCache::new(Rc::clone(&locator)));
         Self {
             fonts: RefCell::new(HashMap::new()),
             locator,
+            fallback,
             metrics: RefCell::new(None),
             font_scale: RefCell::new(1.0),
             dpi_scale: RefCell::new(1.0),
@@ -82,18 +192,45 @@ impl FontConfiguration {
         }
 
         let attributes = style.font_with_fallback();
-        let handles = self.locator.load_fonts(&attributes)?;
+        let mut handles = vec![];
+        let mut handle_attrs = vec![];
+        let mut fallback_handles = vec![];
+        let mut fallback_attrs = vec![];
+        for attr in &attributes {
+            let loaded = self.locator.load_fonts(std::slice::from_ref(attr))?;
+            for (idx, handle) in loaded.into_iter().enumerate() {
+                if idx == 0 {
+                    handles.push(handle);
+                    handle_attrs.push(attr);
+                } else {
+                    fallback_handles.push(handle);
+                    fallback_attrs.push(attr);
+                }
+            }
+        }
+        handles.append(&mut fallback_handles);
+        handle_attrs.append(&mut fallback_attrs);
+
         let mut rasterizers = vec![];
-        for handle in &handles {
-            rasterizers.push(FontRasterizerSelection::get_default().new_rasterizer(&handle)?);
+        for (handle, attr) in handles.iter().zip(&handle_attrs) {
+            rasterizers.push(FontRasterizerSelection::get_default().new_rasterizer(handle, attr)?);
         }
-        let shaper = FontShaperSelection::get_default().new_shaper(&handles)?;
+        let shaper = FontShaperSelection::get_default().new_shaper(&handles, &handle_attrs)?;
 
         let font_size = self.config.font_size * *self.font_scale.borrow();
         let dpi = *self.dpi_scale.borrow() as u32 * self.config.dpi as u32;
         let metrics = shaper.metrics(font_size, dpi)?;
 
-        let loaded = Rc::new(LoadedFont { rasterizers, shaper, metrics, font_size, dpi });
+        let loaded = Rc::new(LoadedFont {
+            rasterizers: RefCell::new(rasterizers),
+            shaper,
+            metrics,
+            font_size,
+            dpi,
+            handles: RefCell::new(handles),
+            attributes: handle_attrs.first().map(|attr| (*attr).clone()).unwrap_or_default(),
+            fallback: Rc::clone(&self.fallback),
+        });
 
         fonts.insert(style.clone(), Rc::clone(&loaded));
 
@@ -156,3 +293,67 @@ impl FontConfiguration {
         &self.config.font
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::FontHinting;
+    use std::cell::Cell;
+
+    /// Covers 'a' and 'b' with one font each and counts the lookups.
+    #[derive(Default)]
+    struct StubLocator {
+        lookups: Cell<usize>,
+    }
+
+    fn handle(index: u32) -> FontDataHandle {
+        FontDataHandle::Memory { data: vec![], index }
+    }
+
+    impl FontLocator for StubLocator {
+        fn load_fonts(&self, _: &[FontAttributes]) -> anyhow::Result<Vec<FontDataHandle>> {
+            Ok(vec![])
+        }
+
+        fn locate_fallback_for_codepoints(
+            &self,
+            codepoints: &[char],
+        ) -> anyhow::Result<Vec<(FontDataHandle, Vec<char>)>> {
+            self.lookups.set(self.lookups.get() + 1);
+            Ok(codepoints
+                .iter()
+                .filter_map(|&c| match c {
+                    'a' => Some((handle(1), vec![c])),
+                    'b' => Some((handle(2), vec![c])),
+                    _ => None,
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn fallback_cache() {
+        let locator = Rc::new(StubLocator::default());
+        let cache = FallbackCache::new(Rc::clone(&locator) as Rc<dyn FontLocator>);
+
+        assert!(cache.fonts_for(&['a', 'x']).unwrap() == vec![handle(1)]);
+        assert!(cache.fonts_for(&['x']).unwrap().is_empty());
+        assert!(cache.fonts_for(&['a']).unwrap() == vec![handle(1)]);
+        assert_eq!(locator.lookups.get(), 1);
+
+        assert!(cache.fonts_for(&['b', 'a', 'x']).unwrap() == vec![handle(2), handle(1)]);
+        assert_eq!(locator.lookups.get(), 2);
+    }
+
+    #[test]
+    fn fallback_fonts_leave_out_the_configured_features() {
+        let primary = FontAttributes {
+            harfbuzz_features: Some(vec!["ss01".to_string()]),
+            hinting: Some(FontHinting::Full),
+            ..FontAttributes::default()
+        };
+        let fallback = fallback_attributes(&primary);
+        assert_eq!(fallback.harfbuzz_features, None);
+        assert_eq!(fallback.hinting, Some(FontHinting::Full));
+    }
+}
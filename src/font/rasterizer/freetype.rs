@@ -1,3 +1,4 @@
+use crate::config::{FontAntiAliasing, FontAttributes, FontHinting, FontLcdFilter};
 use crate::font::locator::FontDataHandle;
 use crate::font::rasterizer::FontRasterizer;
 use crate::font::{ftwrap, RasterizedGlyph};
@@ -9,6 +10,11 @@ use std::slice;
 
 pub struct FreeTypeRasterizer {
     has_color: bool,
+    hinting: FontHinting,
+    antialias: FontAntiAliasing,
+    embolden: bool,
+    /// Maps the coverage that FreeType produces to the gamma corrected one.
+    gamma_table: [u8; 256],
     face: RefCell<ftwrap::Face>,
     _lib: ftwrap::Library,
 }
@@ -22,13 +28,12 @@ impl FontRasterizer for FreeTypeRasterizer {
     ) -> anyhow::Result<RasterizedGlyph> {
         self.face.borrow_mut().set_font_size(size, dpi)?;
 
-        let render_mode = ftwrap::FT_Render_Mode::FT_RENDER_MODE_LIGHT;
-
-        let load_flags = ftwrap::compute_load_flags_for_mode(render_mode);
+        let (load_flags, render_mode) = load_flags_and_render_mode(self.hinting, self.antialias);
 
         let mut face = self.face.borrow_mut();
         let descender = unsafe { (*(*face.face).size).metrics.descender as f64 / 64.0 };
-        let ft_glyph = face.load_and_render_glyph(glyph_pos, load_flags, render_mode)?;
+        let ft_glyph =
+            face.load_and_render_glyph(glyph_pos, load_flags, render_mode, self.embolden)?;
 
         let mode: ftwrap::FT_Pixel_Mode =
             unsafe { mem::transmute(u32::from(ft_glyph.bitmap.pixel_mode)) };
@@ -51,6 +56,39 @@ impl FontRasterizer for FreeTypeRasterizer {
     }
 }
 
+/// The flags to load glyphs with and the mode to render them in for the
+/// given options.  The shaper loads glyphs with the same flags so that the
+/// advances it computes match the glyphs that are drawn.
+pub fn load_flags_and_render_mode(
+    hinting: FontHinting,
+    antialias: FontAntiAliasing,
+) -> (i32, ftwrap::FT_Render_Mode) {
+    use ftwrap::FT_Render_Mode::*;
+
+    let render_mode = match (antialias, hinting) {
+        (FontAntiAliasing::None, _) => FT_RENDER_MODE_MONO,
+        (FontAntiAliasing::Greyscale, FontHinting::Light) => FT_RENDER_MODE_LIGHT,
+        (FontAntiAliasing::Greyscale, _) => FT_RENDER_MODE_NORMAL,
+        (FontAntiAliasing::SubpixelRgb, _) | (FontAntiAliasing::SubpixelBgr, _) => {
+            FT_RENDER_MODE_LCD
+        }
+    };
+
+    // Full hinting targets the mode that the glyph is rendered in, while
+    // light hinting only snaps vertically whatever the glyph is rendered
+    // in, which FreeType allows for the LCD and mono modes as well.
+    let load_target = match hinting {
+        FontHinting::Light => FT_RENDER_MODE_LIGHT,
+        _ => render_mode,
+    };
+    let mut load_flags = ftwrap::compute_load_flags_for_mode(load_target);
+    if hinting == FontHinting::None {
+        load_flags |= ftwrap::FT_LOAD_NO_HINTING as i32;
+    }
+
+    (load_flags, render_mode)
+}
+
 impl FreeTypeRasterizer {
     fn rasterize_mono(
         &self,
@@ -92,6 +130,7 @@ impl FreeTypeRasterizer {
             bearing_x: PixelLength::new(ft_glyph.bitmap_left as f64),
             bearing_y: PixelLength::new(ft_glyph.bitmap_top as f64),
             has_color: false,
+            subpixel: false,
         }
     }
 
@@ -109,7 +148,7 @@ impl FreeTypeRasterizer {
             let src_offset = y * pitch;
             let dest_offset = y * width * 4;
             for x in 0..width {
-                let gray = data[src_offset + x];
+                let gray = self.gamma_table[data[src_offset + x] as usize];
 
                 rgba[dest_offset + (x * 4)] = gray;
                 rgba[dest_offset + (x * 4) + 1] = gray;
@@ -124,6 +163,7 @@ impl FreeTypeRasterizer {
             bearing_x: PixelLength::new(ft_glyph.bitmap_left as f64),
             bearing_y: PixelLength::new(ft_glyph.bitmap_top as f64),
             has_color: false,
+            subpixel: false,
         }
     }
 
@@ -141,10 +181,13 @@ impl FreeTypeRasterizer {
             let src_offset = y * pitch as usize;
             let dest_offset = y * width * 4;
             for x in 0..width {
-                let blue = data[src_offset + (x * 3)];
-                let green = data[src_offset + (x * 3) + 1];
-                let red = data[src_offset + (x * 3) + 2];
-                let alpha = red | green | blue;
+                let mut red = self.gamma_table[data[src_offset + (x * 3)] as usize];
+                let green = self.gamma_table[data[src_offset + (x * 3) + 1] as usize];
+                let mut blue = self.gamma_table[data[src_offset + (x * 3) + 2] as usize];
+                if self.antialias == FontAntiAliasing::SubpixelBgr {
+                    std::mem::swap(&mut red, &mut blue);
+                }
+                let alpha = red.max(green).max(blue);
                 rgba[dest_offset + (x * 4)] = red;
                 rgba[dest_offset + (x * 4) + 1] = green;
                 rgba[dest_offset + (x * 4) + 2] = blue;
@@ -158,7 +201,8 @@ impl FreeTypeRasterizer {
             width,
             bearing_x: PixelLength::new(ft_glyph.bitmap_left as f64),
             bearing_y: PixelLength::new(ft_glyph.bitmap_top as f64),
-            has_color: self.has_color,
+            has_color: false,
+            subpixel: true,
         }
     }
 
@@ -254,15 +298,109 @@ impl FreeTypeRasterizer {
             ),
 
             has_color: self.has_color,
+            subpixel: false,
         }
     }
 
-    pub fn from_locator(handle: &FontDataHandle) -> anyhow::Result<Self> {
-        let lib = ftwrap::Library::new()?;
+    pub fn from_locator(
+        handle: &FontDataHandle,
+        attributes: &FontAttributes,
+    ) -> anyhow::Result<Self> {
+        let mut lib = ftwrap::Library::new()?;
+        if let Some(filter) = attributes.lcd_filter {
+            let filter = match filter {
+                FontLcdFilter::None => ftwrap::FT_LcdFilter::FT_LCD_FILTER_NONE,
+                FontLcdFilter::Default => ftwrap::FT_LcdFilter::FT_LCD_FILTER_DEFAULT,
+                FontLcdFilter::Light => ftwrap::FT_LcdFilter::FT_LCD_FILTER_LIGHT,
+                FontLcdFilter::Legacy => ftwrap::FT_LcdFilter::FT_LCD_FILTER_LEGACY,
+            };
+            lib.set_lcd_filter(filter)?;
+        }
         let face = lib.face_from_locator(handle)?;
         let has_color = unsafe {
             (((*face.face).face_flags as u32) & (ftwrap::FT_FACE_FLAG_COLOR as u32)) != 0
         };
-        Ok(Self { _lib: lib, face: RefCell::new(face), has_color })
+        Ok(Self {
+            _lib: lib,
+            face: RefCell::new(face),
+            has_color,
+            hinting: attributes.hinting.unwrap_or_default(),
+            antialias: attributes.antialias.unwrap_or_default(),
+            embolden: attributes.embolden.unwrap_or(false),
+            gamma_table: gamma_table(attributes.gamma.map(|gamma| gamma.0).unwrap_or(1.0)),
+        })
+    }
+}
+
+fn gamma_table(gamma: f32) -> [u8; 256] {
+    let gamma = if gamma > 0.0 { gamma } else { 1.0 };
+    let mut table = [0u8; 256];
+    for (idx, value) in table.iter_mut().enumerate() {
+        *value = (255.0 * (idx as f32 / 255.0).powf(1.0 / gamma)).round() as u8;
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gamma() {
+        let identity = gamma_table(1.0);
+        assert!(identity.iter().enumerate().all(|(idx, &value)| value as usize == idx));
+
+        let heavier = gamma_table(2.0);
+        assert_eq!((heavier[0], heavier[255]), (0, 255));
+        assert!(heavier[64] > 64);
+        assert!(gamma_table(0.5)[64] < 64);
+        assert_eq!(gamma_table(0.0)[64], 64);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ANTIALIAS: [FontAntiAliasing; 4] = [
+        FontAntiAliasing::None,
+        FontAntiAliasing::Greyscale,
+        FontAntiAliasing::SubpixelRgb,
+        FontAntiAliasing::SubpixelBgr,
+    ];
+
+    fn load_target(load_flags: i32) -> i32 {
+        (load_flags >> 16) & 0xf
+    }
+
+    #[test]
+    fn hinting_applies_to_every_render_mode() {
+        use ftwrap::FT_Render_Mode::*;
+
+        for &antialias in &ANTIALIAS {
+            let (none, none_mode) = load_flags_and_render_mode(FontHinting::None, antialias);
+            let (light, light_mode) = load_flags_and_render_mode(FontHinting::Light, antialias);
+            let (full, full_mode) = load_flags_and_render_mode(FontHinting::Full, antialias);
+
+            let expected_mode = match antialias {
+                FontAntiAliasing::None => FT_RENDER_MODE_MONO,
+                FontAntiAliasing::Greyscale => FT_RENDER_MODE_NORMAL,
+                _ => FT_RENDER_MODE_LCD,
+            };
+            assert_eq!(full_mode as i32, expected_mode as i32, "{:?}", antialias);
+            assert_eq!(none_mode as i32, expected_mode as i32, "{:?}", antialias);
+            if antialias != FontAntiAliasing::Greyscale {
+                assert_eq!(light_mode as i32, expected_mode as i32, "{:?}", antialias);
+            }
+
+            assert_eq!(load_target(light), FT_RENDER_MODE_LIGHT as i32, "{:?}", antialias);
+            assert_eq!(load_target(full), expected_mode as i32, "{:?}", antialias);
+            assert_ne!(light, full, "{:?}", antialias);
+
+            let no_hinting = ftwrap::FT_LOAD_NO_HINTING as i32;
+            assert_ne!(none & no_hinting, 0, "{:?}", antialias);
+            assert_eq!(light & no_hinting, 0, "{:?}", antialias);
+            assert_eq!(full & no_hinting, 0, "{:?}", antialias);
+        }
     }
 }
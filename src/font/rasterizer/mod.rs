@@ -1,3 +1,4 @@
+use crate::config::FontAttributes;
 use crate::font::locator::FontDataHandle;
 use crate::window::PixelLength;
 use anyhow::Error;
@@ -13,6 +14,7 @@ pub struct RasterizedGlyph {
     pub bearing_x: PixelLength,
     pub bearing_y: PixelLength,
     pub has_color: bool,
+    pub subpixel: bool,
 }
 
 pub trait FontRasterizer {
@@ -52,9 +54,12 @@ impl FontRasterizerSelection {
     pub fn new_rasterizer(
         self,
         handle: &FontDataHandle,
+        attributes: &FontAttributes,
     ) -> anyhow::Result<Box<dyn FontRasterizer>> {
         match self {
-            Self::FreeType => Ok(Box::new(freetype::FreeTypeRasterizer::from_locator(handle)?)),
+            Self::FreeType => {
+                Ok(Box::new(freetype::FreeTypeRasterizer::from_locator(handle, attributes)?))
+            }
         }
     }
 }
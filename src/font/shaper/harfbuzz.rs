@@ -1,6 +1,8 @@
+use crate::config::FontAttributes;
 use crate::font::ftwrap;
 use crate::font::hbwrap as harfbuzz;
 use crate::font::locator::FontDataHandle;
+use crate::font::rasterizer::freetype::load_flags_and_render_mode;
 use crate::font::shaper::{FallbackIdx, FontMetrics, FontShaper, GlyphInfo};
 use crate::window::PixelLength;
 use anyhow::bail;
@@ -31,26 +33,54 @@ fn make_glyphinfo(
 struct FontPair {
     face: ftwrap::Face,
     font: harfbuzz::Font,
+    features: Vec<harfbuzz::hb_feature_t>,
+}
+
+const DEFAULT_FEATURES: &[&str] = &["kern", "liga", "clig"];
+const NO_LIGATURE_FEATURES: &[&str] = &["-liga", "-clig", "-dlig", "-calt"];
+
+/// The features to shape with: the defaults followed by those configured
+/// for the font, which come later so that they can turn a default off.
+fn feature_names(configured: Option<&Vec<String>>) -> Vec<&str> {
+    DEFAULT_FEATURES
+        .iter()
+        .copied()
+        .chain(configured.into_iter().flatten().map(String::as_str))
+        .collect()
 }
 
 pub struct HarfbuzzShaper {
-    fonts: Vec<RefCell<FontPair>>,
-    _lib: ftwrap::Library,
+    fonts: RefCell<Vec<RefCell<FontPair>>>,
+    lib: ftwrap::Library,
 }
 
 impl HarfbuzzShaper {
-    pub fn new(handles: &[FontDataHandle]) -> anyhow::Result<Self> {
+    pub fn new(handles: &[FontDataHandle], attributes: &[&FontAttributes]) -> anyhow::Result<Self> {
         let lib = ftwrap::Library::new()?;
         let mut fonts = vec![];
-        for handle in handles {
-            let face = lib.face_from_locator(handle)?;
-            let mut font = harfbuzz::Font::new(face.face);
-            let render_mode = ftwrap::FT_Render_Mode::FT_RENDER_MODE_LIGHT;
-            let load_flags = ftwrap::compute_load_flags_for_mode(render_mode);
-            font.set_load_flags(load_flags);
-            fonts.push(RefCell::new(FontPair { face, font }));
+        for (handle, attributes) in handles.iter().zip(attributes.iter()) {
+            fonts.push(RefCell::new(Self::load_font_pair(&lib, handle, attributes)?));
         }
-        Ok(Self { fonts, _lib: lib })
+        Ok(Self { fonts: RefCell::new(fonts), lib })
+    }
+
+    fn load_font_pair(
+        lib: &ftwrap::Library,
+        handle: &FontDataHandle,
+        attributes: &FontAttributes,
+    ) -> anyhow::Result<FontPair> {
+        let face = lib.face_from_locator(handle)?;
+        let mut font = harfbuzz::Font::new(face.face);
+        let (load_flags, _) = load_flags_and_render_mode(
+            attributes.hinting.unwrap_or_default(),
+            attributes.antialias.unwrap_or_default(),
+        );
+        font.set_load_flags(load_flags);
+        let features = feature_names(attributes.harfbuzz_features.as_ref())
+            .into_iter()
+            .map(harfbuzz::feature_from_string)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(FontPair { face, font, features })
     }
 
     fn do_shape(
@@ -59,13 +89,9 @@ impl HarfbuzzShaper {
         s: &str,
         font_size: f64,
         dpi: u32,
+        ligatures: bool,
+        no_glyphs: &mut Vec<char>,
     ) -> anyhow::Result<Vec<GlyphInfo>> {
-        let features = vec![
-            harfbuzz::feature_from_string("kern")?,
-            harfbuzz::feature_from_string("liga")?,
-            harfbuzz::feature_from_string("clig")?,
-        ];
-
         let mut buf = harfbuzz::Buffer::new()?;
         buf.set_script(harfbuzz::HB_SCRIPT_LATIN);
         buf.set_direction(harfbuzz::HB_DIRECTION_LTR);
@@ -73,10 +99,16 @@ impl HarfbuzzShaper {
         buf.add_str(s);
 
         {
-            match self.fonts.get(font_idx) {
+            match self.fonts.borrow().get(font_idx) {
                 Some(pair) => {
                     let mut pair = pair.borrow_mut();
                     pair.face.set_font_size(font_size, dpi)?;
+                    let mut features = pair.features.clone();
+                    if !ligatures {
+                        for f in NO_LIGATURE_FEATURES {
+                            features.push(harfbuzz::feature_from_string(f)?);
+                        }
+                    }
                     pair.font.shape(&mut buf, Some(features.as_slice()));
                 }
                 None => {
@@ -125,15 +157,18 @@ impl HarfbuzzShaper {
                 }
             } else if let Some(start_pos) = first_fallback_pos {
                 let substr = &s[start_pos..pos];
-                let mut shape = match self.do_shape(font_idx + 1, substr, font_size, dpi) {
-                    Ok(shape) => Ok(shape),
-                    Err(_) => {
-                        if font_idx == 0 && s == "?" {
-                            bail!("unable to find any usable glyphs for `?` in font_idx 0");
+                let mut shape =
+                    match self.do_shape(font_idx + 1, substr, font_size, dpi, ligatures, no_glyphs)
+                    {
+                        Ok(shape) => Ok(shape),
+                        Err(_) => {
+                            if font_idx == 0 && s == "?" {
+                                bail!("unable to find any usable glyphs for `?` in font_idx 0");
+                            }
+                            no_glyphs.extend(substr.chars());
+                            self.do_shape(0, "?", font_size, dpi, ligatures, no_glyphs)
                         }
-                        self.do_shape(0, "?", font_size, dpi)
-                    }
-                }?;
+                    }?;
 
                 for mut info in &mut shape {
                     info.cluster += start_pos as u32;
@@ -148,7 +183,8 @@ impl HarfbuzzShaper {
 
                     cluster.push(make_glyphinfo(text, font_idx, info, &positions[i]));
                 } else {
-                    cluster.append(&mut self.do_shape(0, "?", font_size, dpi)?);
+                    cluster
+                        .append(&mut self.do_shape(0, "?", font_size, dpi, ligatures, no_glyphs)?);
                 }
             }
         }
@@ -156,15 +192,17 @@ impl HarfbuzzShaper {
         if let Some(start_pos) = first_fallback_pos {
             let substr = &s[start_pos..];
             if false {}
-            let mut shape = match self.do_shape(font_idx + 1, substr, font_size, dpi) {
-                Ok(shape) => Ok(shape),
-                Err(_) => {
-                    if font_idx == 0 && s == "?" {
-                        bail!("unable to find any usable glyphs for `?` in font_idx 0");
+            let mut shape =
+                match self.do_shape(font_idx + 1, substr, font_size, dpi, ligatures, no_glyphs) {
+                    Ok(shape) => Ok(shape),
+                    Err(_) => {
+                        if font_idx == 0 && s == "?" {
+                            bail!("unable to find any usable glyphs for `?` in font_idx 0");
+                        }
+                        no_glyphs.extend(substr.chars());
+                        self.do_shape(0, "?", font_size, dpi, ligatures, no_glyphs)
                     }
-                    self.do_shape(0, "?", font_size, dpi)
-                }
-            }?;
+                }?;
 
             for mut info in &mut shape {
                 info.cluster += start_pos as u32;
@@ -177,12 +215,26 @@ impl HarfbuzzShaper {
 }
 
 impl FontShaper for HarfbuzzShaper {
-    fn shape(&self, text: &str, size: f64, dpi: u32) -> anyhow::Result<Vec<GlyphInfo>> {
-        self.do_shape(0, text, size, dpi)
+    fn shape(
+        &self,
+        text: &str,
+        size: f64,
+        dpi: u32,
+        ligatures: bool,
+        no_glyphs: &mut Vec<char>,
+    ) -> anyhow::Result<Vec<GlyphInfo>> {
+        self.do_shape(0, text, size, dpi, ligatures, no_glyphs)
+    }
+
+    fn add_font(&self, handle: &FontDataHandle, attributes: &FontAttributes) -> anyhow::Result<()> {
+        let pair = Self::load_font_pair(&self.lib, handle, attributes)?;
+        self.fonts.borrow_mut().push(RefCell::new(pair));
+        Ok(())
     }
 
     fn metrics(&self, size: f64, dpi: u32) -> anyhow::Result<FontMetrics> {
-        let mut pair = self.fonts[0].borrow_mut();
+        let fonts = self.fonts.borrow();
+        let mut pair = fonts[0].borrow_mut();
         let (cell_width, cell_height) = pair.face.set_font_size(size, dpi)?;
         let y_scale = unsafe { (*(*pair.face.face).size).metrics.y_scale as f64 / 65536.0 };
         Ok(FontMetrics {
@@ -201,3 +253,15 @@ impl FontShaper for HarfbuzzShaper {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn configured_features_extend_the_defaults() {
+        assert_eq!(feature_names(None), DEFAULT_FEATURES);
+        let configured = vec!["ss01".to_string(), "-liga".to_string()];
+        assert_eq!(feature_names(Some(&configured)), ["kern", "liga", "clig", "ss01", "-liga"]);
+    }
+}
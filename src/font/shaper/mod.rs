@@ -1,3 +1,4 @@
+use crate::config::FontAttributes;
 use crate::font::locator::FontDataHandle;
 use crate::window::PixelLength;
 use anyhow::{anyhow, Error};
@@ -32,8 +33,17 @@ pub struct FontMetrics {
 }
 
 pub trait FontShaper {
-    fn shape(&self, text: &str, size: f64, dpi: u32) -> anyhow::Result<Vec<GlyphInfo>>;
+    fn shape(
+        &self,
+        text: &str,
+        size: f64,
+        dpi: u32,
+        ligatures: bool,
+        no_glyphs: &mut Vec<char>,
+    ) -> anyhow::Result<Vec<GlyphInfo>>;
     fn metrics(&self, size: f64, dpi: u32) -> anyhow::Result<FontMetrics>;
+    /// Adds a fallback font, shaped with the features from `attributes`.
+    fn add_font(&self, handle: &FontDataHandle, attributes: &FontAttributes) -> anyhow::Result<()>;
 }
 
 #[derive(Debug, Deserialize, Clone, Copy)]
@@ -61,9 +71,13 @@ impl FontShaperSelection {
         vec!["Harfbuzz"]
     }
 
-    pub fn new_shaper(self, handles: &[FontDataHandle]) -> anyhow::Result<Box<dyn FontShaper>> {
+    pub fn new_shaper(
+        self,
+        handles: &[FontDataHandle],
+        attributes: &[&FontAttributes],
+    ) -> anyhow::Result<Box<dyn FontShaper>> {
         match self {
-            Self::Harfbuzz => Ok(Box::new(harfbuzz::HarfbuzzShaper::new(handles)?)),
+            Self::Harfbuzz => Ok(Box::new(harfbuzz::HarfbuzzShaper::new(handles, attributes)?)),
         }
     }
 }
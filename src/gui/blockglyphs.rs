@@ -0,0 +1,500 @@
+use super::utilsprites::RenderMetrics;
+use crate::window::bitmaps::{BitmapImage, Image};
+use crate::window::Color;
+
+const NONE: u8 = 0;
+const LIGHT: u8 = 1;
+const HEAVY: u8 = 2;
+const DOUBLE: u8 = 3;
+
+const SAMPLES: usize = 4;
+
+pub fn is_block_glyph(c: char) -> bool {
+    match c {
+        '\u{2500}'..='\u{259f}' | '\u{2800}'..='\u{28ff}' | '\u{e0b0}'..='\u{e0b7}' => true,
+        _ => false,
+    }
+}
+
+pub fn render_block_glyph(c: char, metrics: &RenderMetrics) -> Option<Image> {
+    let mut canvas = Canvas::new(metrics);
+
+    match c as u32 {
+        0x2504..=0x250b => {
+            let idx = c as u32 - 0x2504;
+            let weight = if idx % 2 == 0 { LIGHT } else { HEAVY };
+            let (vertical, dashes) =
+                [(false, 3), (true, 3), (false, 4), (true, 4)][idx as usize / 2];
+            canvas.dashed(weight, vertical, dashes);
+        }
+        0x254c..=0x254f => {
+            let idx = c as u32 - 0x254c;
+            let weight = if idx % 2 == 0 { LIGHT } else { HEAVY };
+            canvas.dashed(weight, idx >= 2, 2);
+        }
+        0x256d => canvas.arc(1., 1.),
+        0x256e => canvas.arc(-1., 1.),
+        0x256f => canvas.arc(-1., -1.),
+        0x2570 => canvas.arc(1., -1.),
+        0x2571 => canvas.diagonal(true, false),
+        0x2572 => canvas.diagonal(false, true),
+        0x2573 => canvas.diagonal(true, true),
+        0x2500..=0x257f => {
+            let arms = LINES[(c as u32 - 0x2500) as usize];
+            canvas.lines(arms);
+        }
+        0x2580..=0x259f => canvas.block(c),
+        0x2800..=0x28ff => canvas.braille((c as u32 - 0x2800) as u8),
+        0xe0b0..=0xe0b7 => canvas.powerline(c),
+        _ => return None,
+    }
+
+    Some(canvas.into_image())
+}
+
+// [up, right, down, left] for U+2500..U+257F; dashes, arcs and diagonals
+// are drawn separately and have no entry here.
+#[rustfmt::skip]
+const LINES: [[u8; 4]; 128] = [
+    // 2500
+    [0, 1, 0, 1], [0, 2, 0, 2], [1, 0, 1, 0], [2, 0, 2, 0],
+    [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0],
+    [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0],
+    [0, 1, 1, 0], [0, 2, 1, 0], [0, 1, 2, 0], [0, 2, 2, 0],
+    // 2510
+    [0, 0, 1, 1], [0, 0, 1, 2], [0, 0, 2, 1], [0, 0, 2, 2],
+    [1, 1, 0, 0], [1, 2, 0, 0], [2, 1, 0, 0], [2, 2, 0, 0],
+    [1, 0, 0, 1], [1, 0, 0, 2], [2, 0, 0, 1], [2, 0, 0, 2],
+    [1, 1, 1, 0], [1, 2, 1, 0], [2, 1, 1, 0], [1, 1, 2, 0],
+    // 2520
+    [2, 1, 2, 0], [2, 2, 1, 0], [1, 2, 2, 0], [2, 2, 2, 0],
+    [1, 0, 1, 1], [1, 0, 1, 2], [2, 0, 1, 1], [1, 0, 2, 1],
+    [2, 0, 2, 1], [2, 0, 1, 2], [1, 0, 2, 2], [2, 0, 2, 2],
+    [0, 1, 1, 1], [0, 1, 1, 2], [0, 2, 1, 1], [0, 2, 1, 2],
+    // 2530
+    [0, 1, 2, 1], [0, 1, 2, 2], [0, 2, 2, 1], [0, 2, 2, 2],
+    [1, 1, 0, 1], [1, 1, 0, 2], [1, 2, 0, 1], [1, 2, 0, 2],
+    [2, 1, 0, 1], [2, 1, 0, 2], [2, 2, 0, 1], [2, 2, 0, 2],
+    [1, 1, 1, 1], [1, 1, 1, 2], [1, 2, 1, 1], [1, 2, 1, 2],
+    // 2540
+    [2, 1, 1, 1], [1, 1, 2, 1], [2, 1, 2, 1], [2, 1, 1, 2],
+    [2, 2, 1, 1], [1, 1, 2, 2], [1, 2, 2, 1], [2, 2, 1, 2],
+    [1, 2, 2, 2], [2, 1, 2, 2], [2, 2, 2, 1], [2, 2, 2, 2],
+    [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0],
+    // 2550
+    [0, 3, 0, 3], [3, 0, 3, 0], [0, 3, 1, 0], [0, 1, 3, 0],
+    [0, 3, 3, 0], [0, 0, 1, 3], [0, 0, 3, 1], [0, 0, 3, 3],
+    [1, 3, 0, 0], [3, 1, 0, 0], [3, 3, 0, 0], [1, 0, 0, 3],
+    [3, 0, 0, 1], [3, 0, 0, 3], [1, 3, 1, 0], [3, 1, 3, 0],
+    // 2560
+    [3, 3, 3, 0], [1, 0, 1, 3], [3, 0, 3, 1], [3, 0, 3, 3],
+    [0, 3, 1, 3], [0, 1, 3, 1], [0, 3, 3, 3], [1, 3, 0, 3],
+    [3, 1, 0, 1], [3, 3, 0, 3], [1, 3, 1, 3], [3, 1, 3, 1],
+    [3, 3, 3, 3], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0],
+    // 2570
+    [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0],
+    [0, 0, 0, 1], [1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0],
+    [0, 0, 0, 2], [2, 0, 0, 0], [0, 2, 0, 0], [0, 0, 2, 0],
+    [0, 2, 0, 1], [1, 0, 2, 0], [0, 1, 0, 2], [2, 0, 1, 0],
+];
+
+struct Canvas {
+    width: isize,
+    height: isize,
+    light: isize,
+    alpha: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(metrics: &RenderMetrics) -> Self {
+        let width = metrics.cell_size.width.max(1);
+        let height = metrics.cell_size.height.max(1);
+        Self {
+            width,
+            height,
+            light: metrics.underline_height.max(1),
+            alpha: vec![0; (width * height) as usize],
+        }
+    }
+
+    fn into_image(self) -> Image {
+        let mut image = Image::new(self.width as usize, self.height as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let alpha = self.alpha[(y * self.width + x) as usize];
+                *image.pixel_mut(x as usize, y as usize) = Color::rgba(0xff, 0xff, 0xff, alpha).0;
+            }
+        }
+        image
+    }
+
+    fn put(&mut self, x: isize, y: isize, alpha: u8) {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            let pixel = &mut self.alpha[(y * self.width + x) as usize];
+            *pixel = (*pixel).max(alpha);
+        }
+    }
+
+    fn fill_rect(&mut self, x0: isize, y0: isize, x1: isize, y1: isize) {
+        for y in y0.max(0)..y1.min(self.height) {
+            for x in x0.max(0)..x1.min(self.width) {
+                self.put(x, y, 0xff);
+            }
+        }
+    }
+
+    fn fill_coverage<F: Fn(f32, f32) -> bool>(&mut self, inside: F) {
+        let step = 1.0 / SAMPLES as f32;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut hits = 0;
+                for sy in 0..SAMPLES {
+                    for sx in 0..SAMPLES {
+                        let fx = x as f32 + (sx as f32 + 0.5) * step;
+                        let fy = y as f32 + (sy as f32 + 0.5) * step;
+                        if inside(fx, fy) {
+                            hits += 1;
+                        }
+                    }
+                }
+                if hits > 0 {
+                    self.put(x, y, (hits * 0xff / (SAMPLES * SAMPLES)) as u8);
+                }
+            }
+        }
+    }
+
+    fn thickness(&self, weight: u8) -> isize {
+        match weight {
+            LIGHT => self.light,
+            HEAVY => self.light * 2,
+            DOUBLE => self.light * 3,
+            _ => 0,
+        }
+    }
+
+    fn span(&self, weight: u8, dim: isize) -> (isize, isize) {
+        let t = self.thickness(weight.max(LIGHT));
+        let start = (dim - t) / 2;
+        (start, start + t)
+    }
+
+    fn lines(&mut self, arms: [u8; 4]) {
+        let [up, right, down, left] = arms;
+        let (w, h, l) = (self.width, self.height, self.light);
+        let (bx0, bx1) = self.span(up.max(down), w);
+        let (by0, by1) = self.span(left.max(right), h);
+        let vdouble = up == DOUBLE || down == DOUBLE;
+        let hdouble = left == DOUBLE || right == DOUBLE;
+
+        for &(weight, dir) in &[(right, 1isize), (left, -1)] {
+            match weight {
+                NONE => {}
+                DOUBLE => {
+                    let (y0, _) = self.span(DOUBLE, h);
+                    for &(stroke, side) in &[(y0, up), (y0 + 2 * l, down)] {
+                        let inner = vdouble && side != NONE;
+                        if dir > 0 {
+                            let start = if inner { bx1 - l } else { bx0 };
+                            self.fill_rect(start, stroke, w, stroke + l);
+                        } else {
+                            let end = if inner { bx0 + l } else { bx1 };
+                            self.fill_rect(0, stroke, end, stroke + l);
+                        }
+                    }
+                }
+                _ => {
+                    let (y0, y1) = self.span(weight, h);
+                    let tee = vdouble && up != NONE && down != NONE;
+                    if dir > 0 {
+                        let start = if tee && left == NONE { bx1 - l } else { bx0 };
+                        self.fill_rect(start, y0, w, y1);
+                    } else {
+                        let end = if tee && right == NONE { bx0 + l } else { bx1 };
+                        self.fill_rect(0, y0, end, y1);
+                    }
+                }
+            }
+        }
+
+        for &(weight, dir) in &[(down, 1isize), (up, -1)] {
+            match weight {
+                NONE => {}
+                DOUBLE => {
+                    let (x0, _) = self.span(DOUBLE, w);
+                    for &(stroke, side) in &[(x0, left), (x0 + 2 * l, right)] {
+                        let inner = hdouble && side != NONE;
+                        if dir > 0 {
+                            let start = if inner { by1 - l } else { by0 };
+                            self.fill_rect(stroke, start, stroke + l, h);
+                        } else {
+                            let end = if inner { by0 + l } else { by1 };
+                            self.fill_rect(stroke, 0, stroke + l, end);
+                        }
+                    }
+                }
+                _ => {
+                    let (x0, x1) = self.span(weight, w);
+                    let tee = hdouble && left != NONE && right != NONE;
+                    if dir > 0 {
+                        let start = if tee && up == NONE { by1 - l } else { by0 };
+                        self.fill_rect(x0, start, x1, h);
+                    } else {
+                        let end = if tee && down == NONE { by0 + l } else { by1 };
+                        self.fill_rect(x0, 0, x1, end);
+                    }
+                }
+            }
+        }
+    }
+
+    fn dashed(&mut self, weight: u8, vertical: bool, dashes: isize) {
+        let (w, h) = (self.width, self.height);
+        let len = if vertical { h } else { w };
+        let gap = (len / dashes / 3).max(1);
+        let (t0, t1) = self.span(weight, if vertical { w } else { h });
+        for i in 0..dashes {
+            let start = i * len / dashes + gap / 2;
+            let end = (i + 1) * len / dashes - (gap - gap / 2);
+            if vertical {
+                self.fill_rect(t0, start, t1, end);
+            } else {
+                self.fill_rect(start, t0, end, t1);
+            }
+        }
+    }
+
+    fn arc(&mut self, sx: f32, sy: f32) {
+        let (w, h) = (self.width as f32, self.height as f32);
+        let (bx0, bx1) = self.span(LIGHT, self.width);
+        let (by0, by1) = self.span(LIGHT, self.height);
+        let half = self.light as f32 / 2.;
+        let cx = bx0 as f32 + half;
+        let cy = by0 as f32 + half;
+        let rx = if sx > 0. { w - cx } else { cx };
+        let ry = if sy > 0. { h - cy } else { cy };
+        let r = rx.min(ry);
+        let (ax, ay) = (cx + sx * r, cy + sy * r);
+        let (bx0, bx1, by0, by1) = (bx0 as f32, bx1 as f32, by0 as f32, by1 as f32);
+
+        self.fill_coverage(|x, y| {
+            let in_quadrant = (x - ax) * sx <= 0. && (y - ay) * sy <= 0.;
+            let dist = ((x - ax).powi(2) + (y - ay).powi(2)).sqrt();
+            (in_quadrant && (dist - r).abs() <= half)
+                || ((x - ax) * sx >= 0. && y >= by0 && y < by1)
+                || ((y - ay) * sy >= 0. && x >= bx0 && x < bx1)
+        });
+    }
+
+    fn diagonal(&mut self, rising: bool, falling: bool) {
+        let (w, h) = (self.width as f32, self.height as f32);
+        let half = self.light as f32 / 2.;
+        let len = (w * w + h * h).sqrt();
+        self.fill_coverage(|x, y| {
+            (rising && (h * x + w * y - w * h).abs() / len <= half)
+                || (falling && (h * x - w * y).abs() / len <= half)
+        });
+    }
+
+    fn block(&mut self, c: char) {
+        let (w, h) = (self.width, self.height);
+        let (hw, hh) = (w / 2, h / 2);
+        let eighth_h = |n: isize| h - h * n / 8;
+        let eighth_w = |n: isize| w * n / 8;
+
+        match c {
+            '\u{2580}' => self.fill_rect(0, 0, w, hh),
+            '\u{2581}'..='\u{2588}' => {
+                let n = (c as u32 - 0x2580) as isize;
+                self.fill_rect(0, eighth_h(n), w, h);
+            }
+            '\u{2589}'..='\u{258f}' => {
+                let n = (0x2590 - c as u32) as isize;
+                self.fill_rect(0, 0, eighth_w(n), h);
+            }
+            '\u{2590}' => self.fill_rect(hw, 0, w, h),
+            '\u{2591}' => self.fill_shade(0x40),
+            '\u{2592}' => self.fill_shade(0x80),
+            '\u{2593}' => self.fill_shade(0xc0),
+            '\u{2594}' => self.fill_rect(0, 0, w, h - eighth_h(1)),
+            '\u{2595}' => self.fill_rect(w - eighth_w(1), 0, w, h),
+            _ => {
+                // Quadrants: upper left, upper right, lower left, lower right
+                let quads = match c {
+                    '\u{2596}' => [false, false, true, false],
+                    '\u{2597}' => [false, false, false, true],
+                    '\u{2598}' => [true, false, false, false],
+                    '\u{2599}' => [true, false, true, true],
+                    '\u{259a}' => [true, false, false, true],
+                    '\u{259b}' => [true, true, true, false],
+                    '\u{259c}' => [true, true, false, true],
+                    '\u{259d}' => [false, true, false, false],
+                    '\u{259e}' => [false, true, true, false],
+                    _ => [false, true, true, true],
+                };
+                let rects = [(0, 0, hw, hh), (hw, 0, w, hh), (0, hh, hw, h), (hw, hh, w, h)];
+                for (&on, &(x0, y0, x1, y1)) in quads.iter().zip(rects.iter()) {
+                    if on {
+                        self.fill_rect(x0, y0, x1, y1);
+                    }
+                }
+            }
+        }
+    }
+
+    fn fill_shade(&mut self, alpha: u8) {
+        for pixel in self.alpha.iter_mut() {
+            *pixel = (*pixel).max(alpha);
+        }
+    }
+
+    fn braille(&mut self, bits: u8) {
+        let (w, h) = (self.width as f32, self.height as f32);
+        let (col_w, row_h) = (w / 2., h / 4.);
+        let radius = col_w.min(row_h) * 0.3;
+        // Dots 1-3 and 4-6 fill the first three rows, dots 7 and 8 the last.
+        let positions = [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2), (0, 3), (1, 3)];
+        let dots: Vec<(f32, f32)> = positions
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| bits & (1 << *bit) != 0)
+            .map(|(_, &(col, row))| ((col as f32 + 0.5) * col_w, (row as f32 + 0.5) * row_h))
+            .collect();
+        self.fill_coverage(|x, y| {
+            dots.iter().any(|&(dx, dy)| (x - dx).powi(2) + (y - dy).powi(2) <= radius * radius)
+        });
+    }
+
+    fn powerline(&mut self, c: char) {
+        let (w, h) = (self.width as f32, self.height as f32);
+        let half = self.light as f32 / 2.;
+        let mid = h / 2.;
+        let len = (w * w + mid * mid).sqrt();
+        let ellipse = |x: f32, y: f32, cx: f32, shrink: f32| {
+            let (rx, ry) = (w - shrink, mid - shrink);
+            rx > 0. && ry > 0. && ((x - cx) / rx).powi(2) + ((y - mid) / ry).powi(2) <= 1.
+        };
+
+        match c {
+            '\u{e0b0}' => self.fill_coverage(|x, y| x <= w * (1. - (y / mid - 1.).abs())),
+            '\u{e0b2}' => self.fill_coverage(|x, y| x >= w * (y / mid - 1.).abs()),
+            '\u{e0b1}' => self.fill_coverage(|x, y| {
+                (mid * x - w * y).abs() / len <= half
+                    || (mid * x + w * y - w * h).abs() / len <= half
+            }),
+            '\u{e0b3}' => self.fill_coverage(|x, y| {
+                (mid * x + w * y - w * mid).abs() / len <= half
+                    || (mid * x - w * y + w * mid).abs() / len <= half
+            }),
+            '\u{e0b4}' => self.fill_coverage(|x, y| ellipse(x, y, 0., 0.)),
+            '\u{e0b6}' => self.fill_coverage(|x, y| ellipse(x, y, w, 0.)),
+            '\u{e0b5}' => {
+                self.fill_coverage(|x, y| ellipse(x, y, 0., 0.) && !ellipse(x, y, 0., half * 2.))
+            }
+            _ => self.fill_coverage(|x, y| ellipse(x, y, w, 0.) && !ellipse(x, y, w, half * 2.)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn canvas() -> Canvas {
+        Canvas { width: 8, height: 16, light: 1, alpha: vec![0; 8 * 16] }
+    }
+
+    fn block(c: char) -> Canvas {
+        let mut canvas = canvas();
+        canvas.block(c);
+        canvas
+    }
+
+    fn braille(c: char) -> Canvas {
+        let mut canvas = canvas();
+        canvas.braille((c as u32 - 0x2800) as u8);
+        canvas
+    }
+
+    fn covered(canvas: &Canvas, x: isize, y: isize) -> bool {
+        canvas.alpha[(y * canvas.width + x) as usize] != 0
+    }
+
+    #[test]
+    fn block_glyphs() {
+        for &c in &['\u{2500}', '\u{259f}', '\u{2800}', '\u{28ff}', '\u{e0b0}', '\u{e0b7}'] {
+            assert!(is_block_glyph(c), "{:?}", c);
+        }
+        for &c in &['a', '\u{24ff}', '\u{25a0}', '\u{27ff}', '\u{2900}', '\u{e0b8}'] {
+            assert!(!is_block_glyph(c), "{:?}", c);
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let arms = |c: char| LINES[(c as u32 - 0x2500) as usize];
+        assert_eq!(arms('\u{2500}'), [NONE, LIGHT, NONE, LIGHT]);
+        assert_eq!(arms('\u{2503}'), [HEAVY, NONE, HEAVY, NONE]);
+        assert_eq!(arms('\u{250c}'), [NONE, LIGHT, LIGHT, NONE]);
+        assert_eq!(arms('\u{253c}'), [LIGHT, LIGHT, LIGHT, LIGHT]);
+        assert_eq!(arms('\u{254b}'), [HEAVY, HEAVY, HEAVY, HEAVY]);
+        assert_eq!(arms('\u{2550}'), [NONE, DOUBLE, NONE, DOUBLE]);
+        assert_eq!(arms('\u{2552}'), [NONE, DOUBLE, LIGHT, NONE]);
+        assert_eq!(arms('\u{256c}'), [DOUBLE, DOUBLE, DOUBLE, DOUBLE]);
+        assert_eq!(arms('\u{2574}'), [NONE, NONE, NONE, LIGHT]);
+        assert_eq!(arms('\u{257f}'), [HEAVY, NONE, LIGHT, NONE]);
+
+        // Only the dashes, arcs and diagonals, which are drawn separately,
+        // are left empty
+        for (idx, entry) in LINES.iter().enumerate() {
+            let separate = match 0x2500 + idx as u32 {
+                0x2504..=0x250b | 0x254c..=0x254f | 0x256d..=0x2573 => true,
+                _ => false,
+            };
+            assert_eq!(*entry == [NONE; 4], separate, "U+{:X}", 0x2500 + idx);
+        }
+    }
+
+    #[test]
+    fn quadrants() {
+        let c = block('\u{259a}');
+        assert!(covered(&c, 1, 1) && covered(&c, 6, 14));
+        assert!(!covered(&c, 6, 1) && !covered(&c, 1, 14));
+
+        let c = block('\u{259f}');
+        assert!(!covered(&c, 1, 1));
+        assert!(covered(&c, 6, 1) && covered(&c, 1, 14) && covered(&c, 6, 14));
+    }
+
+    #[test]
+    fn eighths() {
+        let c = block('\u{2581}');
+        assert!(covered(&c, 0, 15) && covered(&c, 0, 14) && !covered(&c, 0, 13));
+
+        let c = block('\u{2584}');
+        assert!(covered(&c, 0, 8) && !covered(&c, 0, 7));
+
+        let c = block('\u{258f}');
+        assert!(covered(&c, 0, 0) && !covered(&c, 1, 0));
+
+        let c = block('\u{2594}');
+        assert!(covered(&c, 0, 1) && !covered(&c, 0, 2));
+    }
+
+    #[test]
+    fn braille_dots() {
+        // Dot centres on an 8x16 cell, in the order of their bits
+        let dots = [(2, 2), (2, 6), (2, 10), (6, 2), (6, 6), (6, 10), (2, 14), (6, 14)];
+
+        let c = braille('\u{28ff}');
+        assert!(dots.iter().all(|&(x, y)| covered(&c, x, y)));
+        assert!(!covered(&c, 4, 4));
+
+        for bit in 0..8 {
+            let c = braille(std::char::from_u32(0x2800 + (1 << bit)).unwrap());
+            for (idx, &(x, y)) in dots.iter().enumerate() {
+                assert_eq!(covered(&c, x, y), idx == bit, "bit {} dot {}", bit, idx);
+            }
+        }
+    }
+}
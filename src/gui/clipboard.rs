@@ -0,0 +1,71 @@
+use crate::term::clipboard::{Clipboard, ClipboardContents, ClipboardSelection};
+use crate::window::{Connection, ConnectionOps};
+use anyhow::anyhow;
+use clipboard::{ClipboardContext, ClipboardProvider};
+use std::sync::Mutex;
+
+pub struct SystemClipboard {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    clipboard: Option<ClipboardContext>,
+}
+
+impl Inner {
+    fn new() -> Self {
+        Self { clipboard: None }
+    }
+
+    fn clipboard(&mut self) -> anyhow::Result<&mut ClipboardContext> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(ClipboardContext::new().map_err(|e| anyhow!("{}", e))?);
+        }
+        Ok(self.clipboard.as_mut().unwrap())
+    }
+}
+
+impl SystemClipboard {
+    pub fn new() -> Self {
+        Self { inner: Mutex::new(Inner::new()) }
+    }
+}
+
+fn gui_connection() -> anyhow::Result<std::rc::Rc<Connection>> {
+    Connection::get()
+        .ok_or_else(|| anyhow!("the primary selection is only available on the gui thread"))
+}
+
+impl Clipboard for SystemClipboard {
+    fn get_contents(&self, selection: ClipboardSelection, done: ClipboardContents) {
+        if selection == ClipboardSelection::Primary {
+            return match gui_connection() {
+                Ok(conn) => conn.get_primary_selection(done),
+                Err(err) => done(Err(err)),
+            };
+        }
+        let contents = self
+            .inner
+            .lock()
+            .unwrap()
+            .clipboard()
+            .and_then(|clip| clip.get_contents().map_err(|e| anyhow!("{}", e)));
+        done(contents)
+    }
+
+    fn set_contents(
+        &self,
+        selection: ClipboardSelection,
+        data: Option<String>,
+    ) -> anyhow::Result<()> {
+        if selection == ClipboardSelection::Primary {
+            gui_connection()?.set_primary_selection(data);
+            return Ok(());
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let clip = inner.clipboard()?;
+        clip.set_contents(data.unwrap_or_else(|| "".into())).map_err(|e| anyhow!("{}", e))?;
+
+        clip.get_contents().map(|_| ()).map_err(|e| anyhow!("{}", e))
+    }
+}
@@ -1,3 +1,5 @@
+use super::blockglyphs::render_block_glyph;
+use super::utilsprites::RenderMetrics;
 use crate::config::TextStyle;
 use crate::font::{FontConfiguration, GlyphInfo};
 use crate::window::bitmaps::atlas::{Atlas, Sprite};
@@ -18,6 +20,7 @@ pub struct GlyphKey {
 
 pub struct CachedGlyph<T: Texture2d> {
     pub has_color: bool,
+    pub subpixel: bool,
     pub x_offset: PixelLength,
     pub y_offset: PixelLength,
     pub bearing_x: PixelLength,
@@ -28,6 +31,7 @@ pub struct CachedGlyph<T: Texture2d> {
 
 pub struct GlyphCache<T: Texture2d> {
     glyph_cache: HashMap<GlyphKey, Rc<CachedGlyph<T>>>,
+    block_glyph_cache: HashMap<char, Rc<CachedGlyph<T>>>,
     pub atlas: Atlas<T>,
     fonts: Rc<FontConfiguration>,
 }
@@ -47,7 +51,12 @@ impl GlyphCache<SrgbTexture2d> {
         )?);
         let atlas = Atlas::new(&surface).expect("failed to create new texture atlas");
 
-        Ok(Self { fonts: Rc::clone(fonts), glyph_cache: HashMap::new(), atlas })
+        Ok(Self {
+            fonts: Rc::clone(fonts),
+            glyph_cache: HashMap::new(),
+            block_glyph_cache: HashMap::new(),
+            atlas,
+        })
     }
 }
 
@@ -69,6 +78,36 @@ impl<T: Texture2d> GlyphCache<T> {
         Ok(glyph)
     }
 
+    pub fn cached_block_glyph(
+        &mut self,
+        c: char,
+        metrics: &RenderMetrics,
+    ) -> anyhow::Result<Option<Rc<CachedGlyph<T>>>> {
+        if let Some(entry) = self.block_glyph_cache.get(&c) {
+            return Ok(Some(Rc::clone(entry)));
+        }
+
+        let image = match render_block_glyph(c, metrics) {
+            Some(image) => image,
+            None => return Ok(None),
+        };
+        let tex = self.atlas.allocate(&image)?;
+
+        // Position the sprite so that it covers the cell exactly
+        let glyph = Rc::new(CachedGlyph {
+            has_color: false,
+            subpixel: false,
+            texture: Some(tex),
+            x_offset: PixelLength::zero(),
+            y_offset: PixelLength::zero(),
+            bearing_x: PixelLength::zero(),
+            bearing_y: PixelLength::new(metrics.cell_size.height as f64) + metrics.descender,
+            scale: 1.0,
+        });
+        self.block_glyph_cache.insert(c, Rc::clone(&glyph));
+        Ok(Some(glyph))
+    }
+
     #[allow(clippy::float_cmp)]
     fn load_glyph(
         &mut self,
@@ -96,6 +135,7 @@ impl<T: Texture2d> GlyphCache<T> {
         let glyph = if glyph.width == 0 || glyph.height == 0 {
             CachedGlyph {
                 has_color: glyph.has_color,
+                subpixel: glyph.subpixel,
                 texture: None,
                 x_offset: info.x_offset * scale,
                 y_offset: info.y_offset * scale,
@@ -123,6 +163,7 @@ impl<T: Texture2d> GlyphCache<T> {
 
             CachedGlyph {
                 has_color: glyph.has_color,
+                subpixel: glyph.subpixel,
                 texture: Some(tex),
                 x_offset,
                 y_offset,
@@ -18,6 +18,8 @@ pub mod renderstate;
 
 pub struct Header {
     pub offset: usize,
+    pub current_dir: Option<String>,
+    pub prompt: Option<String>,
     sys: System,
     count: u32,
 }
@@ -25,7 +27,7 @@ pub struct Header {
 impl Header {
     pub fn new() -> Self {
         let sys = System::new();
-        Self { offset: 2, count: 0, sys }
+        Self { offset: 2, current_dir: None, prompt: None, count: 0, sys }
     }
 
     pub fn paint(
@@ -160,22 +162,36 @@ impl Header {
             quad.set_texture(texture_rect);
             quad.set_texture_adjust(left, top, right, bottom);
             quad.set_has_color(glyph.has_color);
+            quad.set_subpixel(glyph.subpixel);
         }
 
         Ok(())
     }
 
     fn compute_header_text(&self, number_of_vertices: usize) -> String {
+        let cols = (number_of_vertices / VERTICES_PER_CELL) as i32;
+        if let Some(prompt) = self.prompt.as_ref() {
+            return format!(" {:width$}", prompt, width = cols.max(1) as usize - 1);
+        }
+
         let now: DateTime<Local> = Local::now();
         let current_time = now.format("%H:%M:%S").to_string();
-        let cpu_load =
+        let mut cpu_load =
             format!("CPU:{}%", self.sys.get_global_processor_info().get_cpu_usage().round());
-        let indent = std::cmp::max(
-            0,
-            (number_of_vertices / VERTICES_PER_CELL) as i32
-                - (current_time.len() + cpu_load.len()) as i32
-                - 2,
-        );
+
+        if let Some(dir) = self.current_dir.as_ref() {
+            let room = cols - (current_time.len() + cpu_load.len()) as i32 - 6;
+            let len = dir.chars().count() as i32;
+            if room > 1 && len <= room {
+                cpu_load = format!("{}  {}", cpu_load, dir);
+            } else if room > 1 {
+                let tail: String = dir.chars().skip((len - room + 1) as usize).collect();
+                cpu_load = format!("{}  …{}", cpu_load, tail);
+            }
+        }
+
+        let indent =
+            std::cmp::max(0, cols - (current_time.len() + cpu_load.chars().count()) as i32 - 2);
 
         format!(" {}{:indent$}{} ", cpu_load, "", current_time, indent = indent as usize)
     }
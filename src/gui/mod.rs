@@ -1,16 +1,51 @@
 use crate::font::FontConfiguration;
 use crate::mux::Mux;
 use crate::window::*;
+use std::ffi::OsString;
+use std::path::PathBuf;
 use std::rc::Rc;
 
+mod blockglyphs;
+mod clipboard;
 mod glyphcache;
 mod header;
+mod notify;
 mod quad;
 mod renderstate;
 mod spritesheet;
 mod utilsprites;
 mod window;
 
+pub use self::clipboard::SystemClipboard;
+
+/// The launch options that a window passes on to the windows it spawns;
+/// these get a shell of their own rather than the window's program, its
+/// title or its recording.
+#[derive(Debug, Clone)]
+pub struct WindowOptions {
+    /// The window class that window managers use to pick rules and icons.
+    pub class: String,
+    pub theme: String,
+    pub config: Option<PathBuf>,
+}
+
+impl WindowOptions {
+    /// The command line arguments for these options.
+    pub fn args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![
+            "--class".into(),
+            self.class.clone().into(),
+            "--theme".into(),
+            self.theme.clone().into(),
+        ];
+        if let Some(config) = &self.config {
+            args.push("--config".into());
+            args.push(config.clone().into());
+        }
+        args
+    }
+}
+
 pub struct GuiFrontEnd {
     connection: Rc<Connection>,
 }
@@ -30,7 +65,12 @@ impl GuiFrontEnd {
 
 pub trait FrontEnd {
     fn run_forever(&self) -> anyhow::Result<()>;
-    fn spawn_new_window(&self, fontconfig: &Rc<FontConfiguration>) -> anyhow::Result<()>;
+    /// Opens a window on the mux's tab.
+    fn spawn_new_window(
+        &self,
+        fontconfig: &Rc<FontConfiguration>,
+        options: &WindowOptions,
+    ) -> anyhow::Result<()>;
 }
 
 impl FrontEnd for GuiFrontEnd {
@@ -45,7 +85,11 @@ impl FrontEnd for GuiFrontEnd {
         self.connection.run_message_loop()
     }
 
-    fn spawn_new_window(&self, fontconfig: &Rc<FontConfiguration>) -> anyhow::Result<()> {
-        window::TermWindow::new_window(fontconfig)
+    fn spawn_new_window(
+        &self,
+        fontconfig: &Rc<FontConfiguration>,
+        options: &WindowOptions,
+    ) -> anyhow::Result<()> {
+        window::TermWindow::new_window(fontconfig, options)
     }
 }
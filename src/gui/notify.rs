@@ -0,0 +1,144 @@
+use crate::core::ratelim::RateLimiter;
+use anyhow::anyhow;
+use dbus::arg::PropMap;
+use dbus::blocking::Connection;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+}
+
+pub trait NotificationBackend {
+    fn show(&self, notification: &Notification) -> anyhow::Result<()>;
+}
+
+/// Sends notifications to the freedesktop notification daemon over the
+/// session bus.  The calls are made from a thread of their own so that a
+/// slow or missing daemon cannot hold up the gui.
+pub struct DbusBackend {
+    sender: Sender<Notification>,
+}
+
+impl DbusBackend {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel::<Notification>();
+        thread::spawn(move || {
+            let mut conn = None;
+            for notification in receiver {
+                if conn.is_none() {
+                    conn = Connection::new_session().ok();
+                }
+                let sent = match conn.as_ref() {
+                    Some(conn) => send_notification(conn, &notification),
+                    None => continue,
+                };
+                // Connect afresh for the next one in case the bus went away.
+                if sent.is_err() {
+                    conn = None;
+                }
+            }
+        });
+        Self { sender }
+    }
+}
+
+fn send_notification(conn: &Connection, notification: &Notification) -> Result<(), dbus::Error> {
+    let proxy = conn.with_proxy(
+        "org.freedesktop.Notifications",
+        "/org/freedesktop/Notifications",
+        Duration::from_secs(5),
+    );
+    let _: (u32,) = proxy.method_call(
+        "org.freedesktop.Notifications",
+        "Notify",
+        (
+            "miro",
+            0u32,
+            "",
+            notification.title.as_str(),
+            notification.body.as_str(),
+            Vec::<&str>::new(),
+            PropMap::new(),
+            -1i32,
+        ),
+    )?;
+    Ok(())
+}
+
+impl NotificationBackend for DbusBackend {
+    fn show(&self, notification: &Notification) -> anyhow::Result<()> {
+        self.sender
+            .send(notification.clone())
+            .map_err(|_| anyhow!("the notification thread has exited"))
+    }
+}
+
+pub struct Notifier {
+    backend: Box<dyn NotificationBackend>,
+    lim: RateLimiter,
+}
+
+impl Notifier {
+    pub fn new(backend: Box<dyn NotificationBackend>, per_second: u32) -> Self {
+        Self { backend, lim: RateLimiter::new(per_second) }
+    }
+
+    /// Shows the notification unless the window has focus or the rate
+    /// limit has been exceeded. Returns whether it was shown.
+    pub fn notify(&mut self, notification: &Notification, focused: bool) -> anyhow::Result<bool> {
+        if focused || !self.lim.non_blocking_admittance_check(1) {
+            return Ok(false);
+        }
+        self.backend.show(notification)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct StubBackend {
+        shown: Rc<RefCell<Vec<Notification>>>,
+    }
+
+    impl NotificationBackend for StubBackend {
+        fn show(&self, notification: &Notification) -> anyhow::Result<()> {
+            self.shown.borrow_mut().push(notification.clone());
+            Ok(())
+        }
+    }
+
+    fn notification(body: &str) -> Notification {
+        Notification { title: "miro".to_string(), body: body.to_string() }
+    }
+
+    #[test]
+    fn suppressed_while_focused() {
+        let backend = StubBackend::default();
+        let shown = Rc::clone(&backend.shown);
+        let mut notifier = Notifier::new(Box::new(backend), 1);
+
+        assert!(!notifier.notify(&notification("hidden"), true).unwrap());
+        assert!(notifier.notify(&notification("shown"), false).unwrap());
+        assert_eq!(*shown.borrow(), vec![notification("shown")]);
+    }
+
+    #[test]
+    fn rate_limited() {
+        let backend = StubBackend::default();
+        let shown = Rc::clone(&backend.shown);
+        let mut notifier = Notifier::new(Box::new(backend), 1);
+
+        assert!(notifier.notify(&notification("first"), false).unwrap());
+        assert!(!notifier.notify(&notification("second"), false).unwrap());
+        assert_eq!(*shown.borrow(), vec![notification("first")]);
+    }
+}
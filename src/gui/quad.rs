@@ -15,11 +15,14 @@ pub struct Vertex {
     pub adjust: (f32, f32),
     pub tex: (f32, f32),
     pub underline: (f32, f32),
+    pub underline_color: (f32, f32, f32, f32),
+    pub overline: (f32, f32),
     pub bg_color: (f32, f32, f32, f32),
     pub cursor: (f32, f32),
     pub cursor_color: (f32, f32, f32, f32),
     pub fg_color: (f32, f32, f32, f32),
     pub has_color: f32,
+    pub subpixel: f32,
 }
 
 glium::implement_vertex!(
@@ -28,11 +31,14 @@ glium::implement_vertex!(
     adjust,
     tex,
     underline,
+    underline_color,
+    overline,
     cursor,
     cursor_color,
     bg_color,
     fg_color,
-    has_color
+    has_color,
+    subpixel
 );
 
 #[derive(Copy, Clone, Debug, Default)]
@@ -55,6 +61,7 @@ glium::implement_vertex!(RectVertex, position, color);
 pub struct Quads {
     pub cols: usize,
     pub row_starts: Vec<usize>,
+    pub gutter_starts: Vec<usize>,
 }
 
 pub struct MappedQuads<'a> {
@@ -78,6 +85,16 @@ impl<'a> MappedQuads<'a> {
         Ok(Quad { vert: &mut self.mapping[start..start + VERTICES_PER_CELL] })
     }
 
+    pub fn gutter<'b>(&'b mut self, y: usize) -> anyhow::Result<Quad<'b>> {
+        let start = *self
+            .quads
+            .gutter_starts
+            .get(y)
+            .ok_or_else(|| anyhow::anyhow!("line {} has no gutter in the vertex buffer", y))?;
+
+        Ok(Quad { vert: &mut self.mapping[start..start + VERTICES_PER_CELL] })
+    }
+
     pub fn cols(&self) -> usize {
         self.quads.cols * VERTICES_PER_CELL
     }
@@ -116,6 +133,13 @@ impl<'a> Quad<'a> {
         }
     }
 
+    pub fn set_subpixel(&mut self, subpixel: bool) {
+        let subpixel = if subpixel { 1. } else { 0. };
+        for v in self.vert.iter_mut() {
+            v.subpixel = subpixel;
+        }
+    }
+
     pub fn set_fg_color(&mut self, color: Color) {
         let color = color.to_tuple_rgba();
         for v in self.vert.iter_mut() {
@@ -137,6 +161,20 @@ impl<'a> Quad<'a> {
         self.vert[V_BOT_RIGHT].underline = (coords.max_x(), coords.max_y());
     }
 
+    pub fn set_underline_color(&mut self, color: Color) {
+        let color = color.to_tuple_rgba();
+        for v in self.vert.iter_mut() {
+            v.underline_color = color;
+        }
+    }
+
+    pub fn set_overline(&mut self, coords: TextureRect) {
+        self.vert[V_TOP_LEFT].overline = (coords.min_x(), coords.min_y());
+        self.vert[V_TOP_RIGHT].overline = (coords.max_x(), coords.min_y());
+        self.vert[V_BOT_LEFT].overline = (coords.min_x(), coords.max_y());
+        self.vert[V_BOT_RIGHT].overline = (coords.max_x(), coords.max_y());
+    }
+
     pub fn set_cursor(&mut self, coords: TextureRect) {
         self.vert[V_TOP_LEFT].cursor = (coords.min_x(), coords.min_y());
         self.vert[V_TOP_RIGHT].cursor = (coords.max_x(), coords.min_y());
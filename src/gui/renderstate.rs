@@ -137,10 +137,11 @@ impl RenderState {
     ) -> anyhow::Result<(VertexBuffer<Vertex>, IndexBuffer<u32>, Quads)> {
         let cell_width = metrics.cell_size.width as f32;
         let cell_height = metrics.cell_size.height as f32;
+        let gutter_width = metrics.gutter_width as f32;
         let mut verts = Vec::new();
         let mut indices = Vec::new();
 
-        let num_cols = width as usize / cell_width as usize;
+        let num_cols = (width - gutter_width).max(0.) as usize / cell_width as usize;
         let num_rows = height as usize / cell_height as usize;
         let mut quads = Quads::default();
         quads.cols = num_cols;
@@ -167,8 +168,16 @@ impl RenderState {
         for y in 0..num_rows {
             let y_pos = (height / -2.0) + (y as f32 * cell_height);
 
+            let gutter = define_quad(
+                width / -2.0,
+                y_pos,
+                (width / -2.0) + gutter_width,
+                y_pos + cell_height,
+            );
+            quads.gutter_starts.push(gutter as usize);
+
             for x in 0..num_cols {
-                let x_pos = (width / -2.0) + (x as f32 * cell_width);
+                let x_pos = (width / -2.0) + gutter_width + (x as f32 * cell_width);
 
                 let idx = define_quad(x_pos, y_pos, x_pos + cell_width, y_pos + cell_height);
                 if x == 0 {
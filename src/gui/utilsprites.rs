@@ -15,10 +15,13 @@ pub struct RenderMetrics {
     pub underline_height: IntPixelLength,
     pub strike_row: IntPixelLength,
     pub cell_size: Size,
+    /// The width of the column left of the cells that marks the prompts
+    /// of failed commands, or zero when the marker is disabled.
+    pub gutter_width: IntPixelLength,
 }
 
 impl RenderMetrics {
-    pub fn new(fonts: &Rc<FontConfiguration>) -> Self {
+    pub fn new(fonts: &Rc<FontConfiguration>, gutter: bool) -> Self {
         let metrics = fonts.default_font_metrics().expect("failed to get font metrics!?");
 
         let (cell_height, cell_width) =
@@ -38,6 +41,7 @@ impl RenderMetrics {
             descender_plus_two,
             strike_row,
             cell_size: Size::new(cell_width as isize, cell_height as isize),
+            gutter_width: if gutter { (cell_width as isize / 4).max(2) } else { 0 },
             underline_height,
         }
     }
@@ -49,10 +53,13 @@ pub struct UtilSprites<T: Texture2d> {
     pub double_underline: Sprite<T>,
     pub cursor_box: Sprite<T>,
     pub strike_through: Sprite<T>,
-    pub single_and_strike: Sprite<T>,
     pub cursor_i_beam: Sprite<T>,
-    pub double_and_strike: Sprite<T>,
     pub cursor_underline: Sprite<T>,
+    pub curly_underline: Sprite<T>,
+    pub dotted_underline: Sprite<T>,
+    pub dashed_underline: Sprite<T>,
+    pub overline: Sprite<T>,
+    pub overline_and_strike: Sprite<T>,
 }
 
 impl<T: Texture2d> UtilSprites<T> {
@@ -131,6 +138,59 @@ impl<T: Texture2d> UtilSprites<T> {
             }
         };
 
+        let draw_curly = |buffer: &mut Image| {
+            let thickness = metrics.underline_height.max(1) as f64;
+            let half = thickness / 2.0;
+            let top = metrics.descender_row as f64 + half;
+            let bottom = (metrics.cell_size.height as f64 - half).max(top + 1.0);
+            let center = (top + bottom) / 2.0;
+            let amplitude = (bottom - top) / 2.0;
+            // One full period per cell so that adjacent cells join up
+            let freq = 2.0 * std::f64::consts::PI / metrics.cell_size.width as f64;
+
+            for x in 0..metrics.cell_size.width {
+                let fx = x as f64 + 0.5;
+                let wave_y = center - amplitude * (fx * freq).cos();
+                let slope = amplitude * freq * (fx * freq).sin();
+                let scale = (1.0 + slope * slope).sqrt();
+                for y in 0..metrics.cell_size.height {
+                    let distance = ((y as f64 + 0.5) - wave_y).abs() / scale;
+                    let coverage = (half + 0.5 - distance).max(0.0).min(1.0);
+                    if coverage > 0.0 {
+                        *buffer.pixel_mut(x as usize, y as usize) =
+                            Color::rgba(0xff, 0xff, 0xff, (coverage * 255.0) as u8).0;
+                    }
+                }
+            }
+        };
+
+        let draw_dotted = |buffer: &mut Image| {
+            let size = metrics.underline_height.max(1);
+            let num_dots = (metrics.cell_size.width / (size * 2)).max(1);
+            for i in 0..num_dots {
+                let center = (2 * i + 1) * metrics.cell_size.width / (2 * num_dots);
+                let rect = Rect::new(
+                    Point::new(center - size / 2, metrics.descender_row),
+                    Size::new(size, size),
+                );
+                buffer.clear_rect(rect, white);
+            }
+        };
+
+        let draw_dashed = |buffer: &mut Image| {
+            let half_width = metrics.cell_size.width / 2;
+            let gap = (metrics.cell_size.width / 6).max(1);
+            for i in 0..2 {
+                let start = i * half_width + gap / 2;
+                let end = (i + 1) * half_width - (gap - gap / 2);
+                let rect = Rect::new(
+                    Point::new(start, metrics.descender_row),
+                    Size::new(end - start, metrics.underline_height),
+                );
+                buffer.clear_rect(rect, white);
+            }
+        };
+
         buffer.clear_rect(cell_rect, black);
         draw_single(&mut buffer);
         let single_underline = glyph_cache.atlas.allocate(&buffer)?;
@@ -144,14 +204,29 @@ impl<T: Texture2d> UtilSprites<T> {
         let strike_through = glyph_cache.atlas.allocate(&buffer)?;
 
         buffer.clear_rect(cell_rect, black);
-        draw_single(&mut buffer);
-        draw_strike(&mut buffer);
-        let single_and_strike = glyph_cache.atlas.allocate(&buffer)?;
+        draw_curly(&mut buffer);
+        let curly_underline = glyph_cache.atlas.allocate(&buffer)?;
 
         buffer.clear_rect(cell_rect, black);
-        draw_double(&mut buffer);
+        draw_dotted(&mut buffer);
+        let dotted_underline = glyph_cache.atlas.allocate(&buffer)?;
+
+        buffer.clear_rect(cell_rect, black);
+        draw_dashed(&mut buffer);
+        let dashed_underline = glyph_cache.atlas.allocate(&buffer)?;
+
+        buffer.clear_rect(cell_rect, black);
+        buffer.clear_rect(
+            Rect::new(
+                cell_rect.origin,
+                Size::new(metrics.cell_size.width, metrics.underline_height),
+            ),
+            white,
+        );
+        let overline = glyph_cache.atlas.allocate(&buffer)?;
+
         draw_strike(&mut buffer);
-        let double_and_strike = glyph_cache.atlas.allocate(&buffer)?;
+        let overline_and_strike = glyph_cache.atlas.allocate(&buffer)?;
 
         let border_width = (metrics.underline_height as f64 * metrics.cell_size.width as f64
             / metrics.cell_size.height as f64)
@@ -241,33 +316,41 @@ impl<T: Texture2d> UtilSprites<T> {
             single_underline,
             double_underline,
             strike_through,
-            single_and_strike,
-            double_and_strike,
             cursor_box,
             cursor_i_beam,
             cursor_underline,
+            curly_underline,
+            dotted_underline,
+            dashed_underline,
+            overline,
+            overline_and_strike,
         })
     }
 
-    pub fn select_sprite(
-        &self,
-        is_highlited_hyperlink: bool,
-        is_strike_through: bool,
-        underline: Underline,
-    ) -> &Sprite<T> {
-        match (is_highlited_hyperlink, is_strike_through, underline) {
-            (true, false, Underline::None) => &self.single_underline,
-            (true, false, Underline::Single) => &self.double_underline,
-            (true, false, Underline::Double) => &self.single_underline,
-            (true, true, Underline::None) => &self.strike_through,
-            (true, true, Underline::Single) => &self.single_and_strike,
-            (true, true, Underline::Double) => &self.double_and_strike,
-            (false, false, Underline::None) => &self.white_space,
-            (false, false, Underline::Single) => &self.single_underline,
-            (false, false, Underline::Double) => &self.double_underline,
-            (false, true, Underline::None) => &self.strike_through,
-            (false, true, Underline::Single) => &self.single_and_strike,
-            (false, true, Underline::Double) => &self.double_and_strike,
+    /// The sprite for the underline layer, which is drawn in the
+    /// underline color.
+    pub fn select_sprite(&self, is_highlited_hyperlink: bool, underline: Underline) -> &Sprite<T> {
+        match (is_highlited_hyperlink, underline) {
+            (true, Underline::None) => &self.single_underline,
+            (true, Underline::Single) => &self.double_underline,
+            (true, Underline::Double) => &self.single_underline,
+            (false, Underline::None) => &self.white_space,
+            (false, Underline::Single) => &self.single_underline,
+            (false, Underline::Double) => &self.double_underline,
+            (_, Underline::Curly) => &self.curly_underline,
+            (_, Underline::Dotted) => &self.dotted_underline,
+            (_, Underline::Dashed) => &self.dashed_underline,
+        }
+    }
+
+    /// The sprite for the overline layer, which is drawn in the text color
+    /// and so also carries the strike through.
+    pub fn overline_sprite(&self, overline: bool, is_strike_through: bool) -> &Sprite<T> {
+        match (overline, is_strike_through) {
+            (false, false) => &self.white_space,
+            (true, false) => &self.overline,
+            (false, true) => &self.strike_through,
+            (true, true) => &self.overline_and_strike,
         }
     }
 
@@ -1,16 +1,21 @@
+use super::blockglyphs::is_block_glyph;
 use super::header::Header;
+use super::notify::{DbusBackend, Notification, Notifier};
 use super::quad::*;
 use super::renderstate::RenderState;
 use super::utilsprites::RenderMetrics;
+use super::WindowOptions;
+use crate::config::{BellMode, Config, WindowOperation};
 use crate::core::color::RgbColor;
+use crate::core::escape::csi;
 use crate::core::promise;
 use crate::core::surface::CursorShape;
 use crate::font::FontConfiguration;
-use crate::mux::tab::Tab;
+use crate::mux::tab::{home_file, Tab};
 use crate::mux::Mux;
 use crate::pty::PtySize;
 use crate::term;
-use crate::term::clipboard::{Clipboard, SystemClipboard};
+use crate::term::clipboard::{Clipboard, ClipboardRequest, ClipboardSelection};
 use crate::term::color::ColorPalette;
 use crate::term::keyassignment::{KeyAssignment, KeyMap};
 use crate::term::Terminal;
@@ -22,13 +27,17 @@ use crate::window::bitmaps::Texture2d;
 use crate::window::*;
 use glium::{uniform, Surface};
 use std::any::Any;
-use std::cell::Ref;
+use std::cell::{Cell, Ref};
+use std::io::Write;
 use std::ops::Range;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 const ATLAS_SIZE: usize = 4096;
+const VISUAL_BELL_DURATION: Duration = Duration::from_millis(150);
+const STATUS_DURATION: Duration = Duration::from_secs(5);
 
 #[derive(Debug, Clone, Copy)]
 struct RowsAndCols {
@@ -48,6 +57,17 @@ pub struct TermWindow {
     terminal_size: PtySize,
     header: Header,
     focused: Option<Instant>,
+    ime_preedit: Option<Preedit>,
+    blinking_text: Cell<bool>,
+    current_dir: Option<PathBuf>,
+    notifier: Notifier,
+    visual_bell: Option<Instant>,
+    pending_clipboard_request: Option<ClipboardRequest>,
+    pending_paste: Option<String>,
+    /// A message shown in the header for a while, such as where an export
+    /// was saved.
+    status: Option<(String, Instant)>,
+    options: WindowOptions,
 }
 
 struct Host<'a> {
@@ -73,6 +93,22 @@ impl<'a> term::TerminalHost for Host<'a> {
         let link = link.clone();
         promise::spawn(async move { if let Err(_) = open::that(link.uri()) {} });
     }
+
+    fn alert(&mut self, alert: term::Alert) {
+        Mux::get().unwrap().push_alert(alert);
+    }
+
+    fn clipboard_request(&mut self, request: ClipboardRequest) {
+        Mux::get().unwrap().clipboard_request(request);
+    }
+
+    fn window_request(&mut self, request: csi::Window) {
+        Mux::get().unwrap().push_window_request(request);
+    }
+
+    fn paste_selection(&mut self, selection: ClipboardSelection) {
+        Mux::get().unwrap().paste_selection(selection);
+    }
 }
 
 impl WindowCallbacks for TermWindow {
@@ -104,6 +140,19 @@ impl WindowCallbacks for TermWindow {
 
     fn focus_change(&mut self, focused: bool) {
         self.focused = if focused { Some(Instant::now()) } else { None };
+        if focused {
+            if let Some(window) = self.window.as_ref() {
+                window.set_urgent(false);
+            }
+        }
+        let mux = Mux::get().unwrap();
+        let tab = mux.get_tab();
+        tab.focus_changed(focused).ok();
+        tab.renderer().make_all_lines_dirty();
+    }
+
+    fn ime_preedit(&mut self, preedit: Option<Preedit>) {
+        self.ime_preedit = preedit;
         let mux = Mux::get().unwrap();
         let tab = mux.get_tab();
         tab.renderer().make_all_lines_dirty();
@@ -128,7 +177,8 @@ impl WindowCallbacks for TermWindow {
         let mux = Mux::get().unwrap();
         let tab = mux.get_tab();
 
-        let x = (event.x as isize / self.render_metrics.cell_size.width) as usize;
+        let x = ((event.x as isize - self.render_metrics.gutter_width).max(0)
+            / self.render_metrics.cell_size.width) as usize;
         let y = (event.y as isize / self.render_metrics.cell_size.height) as i64;
 
         let adjusted_y = y.saturating_sub(self.header.offset as i64);
@@ -286,6 +336,39 @@ impl WindowCallbacks for TermWindow {
         let tab = mux.get_tab();
         let modifiers = window_mods_to_termwiz_mods(key.modifiers);
 
+        if let Some(request) = self.pending_clipboard_request.take() {
+            match key.key {
+                window::KeyCode::Char('y') | window::KeyCode::Char('Y') => {
+                    mux.perform_clipboard_request(&request);
+                }
+                window::KeyCode::Char('n')
+                | window::KeyCode::Char('N')
+                | window::KeyCode::Char('\u{1b}') => {}
+                _ => self.pending_clipboard_request = Some(request),
+            }
+            return true;
+        }
+
+        if let Some(text) = self.pending_paste.take() {
+            match key.key {
+                window::KeyCode::Char('y') | window::KeyCode::Char('Y') => {
+                    tab.trickle_paste(text).ok();
+                }
+                window::KeyCode::Char('n')
+                | window::KeyCode::Char('N')
+                | window::KeyCode::Char('\u{1b}') => {}
+                _ => self.pending_paste = Some(text),
+            }
+            return true;
+        }
+
+        if let window::KeyCode::Char('\r') = key.key {
+            if tab.is_held() {
+                mux.respawn().ok();
+                return true;
+            }
+        }
+
         if let Some(key) = &key.raw_key {
             if let Key::Code(key) = win_key_code_to_termwiz_key_code(&key) {
                 if let Some(assignment) = self.keys.lookup(key, modifiers) {
@@ -329,6 +412,8 @@ impl WindowCallbacks for TermWindow {
 
         self.update_text_cursor(&tab);
         self.update_title();
+        self.process_alerts(&tab);
+        self.process_window_requests(&tab);
 
         if let Err(err) = self.paint_screen(&tab, frame) {
             if let Some(&OutOfTextureSpace { size }) = err.downcast_ref::<OutOfTextureSpace>() {
@@ -344,12 +429,16 @@ impl WindowCallbacks for TermWindow {
 }
 
 impl TermWindow {
-    pub fn new_window(fontconfig: &Rc<FontConfiguration>) -> anyhow::Result<()> {
+    pub fn new_window(
+        fontconfig: &Rc<FontConfiguration>,
+        options: &WindowOptions,
+    ) -> anyhow::Result<()> {
         let mux = Mux::get().unwrap();
         let tab = mux.get_tab();
         let (physical_rows, physical_cols) = tab.renderer().physical_dimensions();
+        let title = tab.get_title();
 
-        let render_metrics = RenderMetrics::new(fontconfig);
+        let render_metrics = RenderMetrics::new(fontconfig, mux.config().failed_command_marker);
 
         let terminal_size = PtySize {
             rows: physical_rows as u16,
@@ -361,29 +450,42 @@ impl TermWindow {
         let header = Header::new();
 
         let dimensions = Dimensions {
-            pixel_width: (terminal_size.cols * render_metrics.cell_size.width as u16) as usize,
+            pixel_width: render_metrics.gutter_width as usize
+                + terminal_size.cols as usize * render_metrics.cell_size.width as usize,
             pixel_height: (header.offset + terminal_size.rows as usize)
                 * render_metrics.cell_size.height as usize,
             dpi: 96,
         };
 
         Window::new_window(
-            "miro",
-            "miro",
+            &options.class,
+            &title,
             dimensions.pixel_width,
             dimensions.pixel_height,
             Box::new(Self {
                 focused: None,
+                ime_preedit: None,
                 window: None,
                 fonts: Rc::clone(fontconfig),
                 render_metrics,
                 dimensions,
                 render_state: None,
-                clipboard: Arc::new(SystemClipboard::new()),
+                clipboard: Arc::clone(mux.clipboard()),
                 keys: KeyMap::new(),
                 header,
                 frame_count: 0,
+                blinking_text: Cell::new(false),
+                current_dir: None,
+                notifier: Notifier::new(
+                    Box::new(DbusBackend::new()),
+                    mux.config().ratelimit_notifications_per_second.unwrap_or(1),
+                ),
+                visual_bell: None,
+                pending_clipboard_request: None,
+                pending_paste: None,
+                status: None,
                 terminal_size,
+                options: options.clone(),
             }),
         )?;
 
@@ -393,21 +495,244 @@ impl TermWindow {
     fn update_title(&mut self) {
         let mux = Mux::get().unwrap();
         let tab = mux.get_tab();
-        let title = tab.get_title();
+        if self.frame_count % (FPS / 2) == 0 {
+            self.current_dir = tab.get_current_dir();
+        }
+        let mut title = match self.current_dir_label() {
+            Some(dir) => format!("{} - {}", tab.get_title(), dir),
+            None => tab.get_title(),
+        };
+        if tab.is_recording() {
+            title.push_str(" [REC]");
+        }
 
         if let Some(window) = self.window.as_ref() {
             window.set_title(&title);
         }
     }
 
+    fn process_alerts(&mut self, tab: &Ref<Tab>) {
+        let mux = Mux::get().unwrap();
+        if self.pending_clipboard_request.is_none() {
+            self.pending_clipboard_request = mux.take_clipboard_request();
+        }
+
+        for text in mux.take_pastes() {
+            self.paste(tab, text).ok();
+        }
+
+        for alert in mux.take_alerts() {
+            match alert {
+                term::Alert::Bell => self.ring_bell(tab),
+                term::Alert::ToastNotification { title, body } => {
+                    let notification =
+                        Notification { title: title.unwrap_or_else(|| tab.get_title()), body };
+                    self.notifier.notify(&notification, self.focused.is_some()).ok();
+                }
+            }
+        }
+
+        if let Some(start) = self.visual_bell {
+            if start.elapsed() >= VISUAL_BELL_DURATION {
+                self.visual_bell = None;
+                tab.renderer().make_all_lines_dirty();
+            }
+        }
+    }
+
+    /// Pastes `text`, first asking the user to confirm if it spans several
+    /// lines and the program has not enabled bracketed paste.
+    fn paste(&mut self, tab: &Ref<Tab>, text: String) -> anyhow::Result<()> {
+        let newlines = &['\r', '\n'][..];
+        let multiline = text.trim_end_matches(newlines).contains(newlines);
+        if Mux::get().unwrap().config().confirm_multiline_paste
+            && multiline
+            && !tab.renderer().bracketed_paste_enabled()
+        {
+            self.pending_paste = Some(text);
+            return Ok(());
+        }
+        tab.trickle_paste(text)
+    }
+
+    /// Answers the XTWINOPS reports that need to know about the window and
+    /// performs the manipulations permitted by `allow_window_ops`.
+    fn process_window_requests(&mut self, tab: &Ref<Tab>) {
+        let mux = Mux::get().unwrap();
+        let requests = mux.take_window_requests();
+        let window = match self.window.as_ref() {
+            Some(window) if !requests.is_empty() => window.clone(),
+            _ => return,
+        };
+        let config = Arc::clone(mux.config());
+        let allowed = |op| config.allow_window_ops.contains(&op);
+        let cell_width = self.render_metrics.cell_size.width as usize;
+        let cell_height = self.render_metrics.cell_size.height as usize;
+        let header_height = self.header.offset * cell_height;
+
+        let mut writer = tab.writer();
+        for request in requests {
+            match request {
+                csi::Window::ReportWindowState => {
+                    let state = if window.is_iconified() { 2 } else { 1 };
+                    write!(writer, "\x1b[{}t", state).ok();
+                }
+                csi::Window::ReportWindowPosition | csi::Window::ReportTextAreaPosition => {
+                    if let Some(pos) = window.get_window_position() {
+                        let (x, y) = if request == csi::Window::ReportTextAreaPosition {
+                            (
+                                pos.x + self.render_metrics.gutter_width,
+                                pos.y + header_height as isize,
+                            )
+                        } else {
+                            (pos.x, pos.y)
+                        };
+                        write!(writer, "\x1b[3;{};{}t", x, y).ok();
+                    }
+                }
+                csi::Window::ReportWindowSizePixels => {
+                    let (width, height) =
+                        (self.dimensions.pixel_width, self.dimensions.pixel_height);
+                    write!(writer, "\x1b[4;{};{}t", height, width).ok();
+                }
+                csi::Window::ReportScreenSizePixels => {
+                    if let Some(size) = window.get_screen_size() {
+                        write!(writer, "\x1b[5;{};{}t", size.height, size.width).ok();
+                    }
+                }
+                csi::Window::ReportScreenSizeCells => {
+                    if let Some(size) = window.get_screen_size() {
+                        let rows = size.height as usize / cell_height.max(1);
+                        let cols = size.width as usize / cell_width.max(1);
+                        write!(writer, "\x1b[9;{};{}t", rows, cols).ok();
+                    }
+                }
+                csi::Window::Iconify if allowed(WindowOperation::Iconify) => {
+                    window.change_state(WindowStateChange::Iconify)
+                }
+                csi::Window::DeIconify if allowed(WindowOperation::Iconify) => {
+                    window.change_state(WindowStateChange::DeIconify)
+                }
+                csi::Window::MoveWindow { x, y } if allowed(WindowOperation::Move) => {
+                    window.set_window_position(x as isize, y as isize)
+                }
+                csi::Window::ResizeWindowPixels { width, height }
+                    if allowed(WindowOperation::Resize) =>
+                {
+                    let cols = width.filter(|&w| w > 0).map(|w| w as usize / cell_width.max(1));
+                    let rows = height.filter(|&h| h > 0).map(|h| h as usize / cell_height.max(1));
+                    self.resize_to_cells(&window, rows, cols);
+                }
+                csi::Window::ResizeWindowCells { width, height }
+                    if allowed(WindowOperation::Resize) =>
+                {
+                    let cols = width.filter(|&w| w > 0).map(|w| w as usize);
+                    let rows = height.filter(|&h| h > 0).map(|h| h as usize);
+                    self.resize_to_cells(&window, rows, cols);
+                }
+                csi::Window::RaiseWindow if allowed(WindowOperation::Raise) => {
+                    window.change_state(WindowStateChange::Raise)
+                }
+                csi::Window::LowerWindow if allowed(WindowOperation::Lower) => {
+                    window.change_state(WindowStateChange::Lower)
+                }
+                csi::Window::MaximizeWindow
+                | csi::Window::MaximizeWindowVertically
+                | csi::Window::MaximizeWindowHorizontally
+                    if allowed(WindowOperation::Maximize) =>
+                {
+                    window.change_state(WindowStateChange::Maximize {
+                        horizontal: request != csi::Window::MaximizeWindowVertically,
+                        vertical: request != csi::Window::MaximizeWindowHorizontally,
+                    })
+                }
+                csi::Window::RestoreMaximizedWindow if allowed(WindowOperation::Maximize) => {
+                    window.change_state(WindowStateChange::RestoreMaximized)
+                }
+                csi::Window::ChangeToFullScreenMode if allowed(WindowOperation::FullScreen) => {
+                    window.change_state(WindowStateChange::FullScreen)
+                }
+                csi::Window::UndoFullScreenMode if allowed(WindowOperation::FullScreen) => {
+                    window.change_state(WindowStateChange::UndoFullScreen)
+                }
+                csi::Window::ToggleFullScreen if allowed(WindowOperation::FullScreen) => {
+                    window.change_state(WindowStateChange::ToggleFullScreen)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Resizes the window so that the terminal has the given number of rows
+    /// and columns, keeping the current value of any that are `None`.
+    fn resize_to_cells(&self, window: &Window, rows: Option<usize>, cols: Option<usize>) {
+        let rows = rows.unwrap_or(self.terminal_size.rows as usize);
+        let cols = cols.unwrap_or(self.terminal_size.cols as usize);
+        window.set_inner_size(
+            self.render_metrics.gutter_width as usize
+                + cols * self.render_metrics.cell_size.width as usize,
+            (rows + self.header.offset) * self.render_metrics.cell_size.height as usize,
+        );
+    }
+
+    fn ring_bell(&mut self, tab: &Ref<Tab>) {
+        let config = Arc::clone(Mux::get().unwrap().config());
+        match config.bell {
+            BellMode::None => {}
+            BellMode::Visual => {
+                self.visual_bell = Some(Instant::now());
+                tab.renderer().make_all_lines_dirty();
+            }
+            BellMode::Urgency => {
+                if self.focused.is_none() {
+                    if let Some(window) = self.window.as_ref() {
+                        window.set_urgent(true);
+                    }
+                }
+            }
+            BellMode::Command => {
+                if let Some(command) = config.bell_command.as_ref() {
+                    if let Ok(mut child) =
+                        std::process::Command::new("sh").arg("-c").arg(command).spawn()
+                    {
+                        std::thread::spawn(move || child.wait());
+                    }
+                }
+            }
+        }
+    }
+
+    fn current_dir_label(&self) -> Option<String> {
+        let dir = self.current_dir.as_ref()?;
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        match home.as_ref().and_then(|home| dir.strip_prefix(home).ok()) {
+            Some(rel) if rel.as_os_str().is_empty() => Some("~".to_string()),
+            Some(rel) => Some(format!("~/{}", rel.display())),
+            None => Some(dir.display().to_string()),
+        }
+    }
+
+    fn spawn_window(&self) -> anyhow::Result<()> {
+        let mut cmd = std::process::Command::new(std::env::current_exe()?);
+        cmd.args(self.options.args());
+        if let Some(dir) = self.current_dir.as_ref().filter(|dir| dir.is_dir()) {
+            cmd.arg("--cwd").arg(dir);
+        }
+        let mut child = cmd.spawn()?;
+        std::thread::spawn(move || child.wait());
+        Ok(())
+    }
+
     fn update_text_cursor(&mut self, tab: &Ref<Tab>) {
         let term = tab.renderer();
         let cursor = term.cursor_pos();
         if let Some(win) = self.window.as_ref() {
             let r = Rect::new(
                 Point::new(
-                    cursor.x.max(0) as isize * self.render_metrics.cell_size.width,
-                    cursor.y.max(0) as isize * self.render_metrics.cell_size.height,
+                    self.render_metrics.gutter_width
+                        + cursor.x.max(0) as isize * self.render_metrics.cell_size.width,
+                    (cursor.y.max(0) as isize + self.header.offset as isize)
+                        * self.render_metrics.cell_size.height,
                 ),
                 self.render_metrics.cell_size,
             );
@@ -423,18 +748,47 @@ impl TermWindow {
         use KeyAssignment::*;
         match assignment {
             ToggleFullScreen => {}
-            Copy => {}
+            Copy => {
+                let text = tab.renderer().get_selection_text();
+                if !text.is_empty() {
+                    self.clipboard.set_contents(ClipboardSelection::Clipboard, Some(text))?;
+                }
+            }
             Paste => {
-                tab.trickle_paste(self.clipboard.get_contents()?)?;
+                Mux::get().unwrap().paste_selection(ClipboardSelection::Clipboard);
             }
             DecreaseFontSize => self.decrease_font_size(),
             IncreaseFontSize => self.increase_font_size(),
             ResetFontSize => self.reset_font_size(),
+            SpawnWindow => self.spawn_window()?,
             Hide => {
                 if let Some(w) = self.window.as_ref() {
                     w.hide();
                 }
             }
+            ScrollToPreviousPrompt => tab.renderer().scroll_to_prompt(-1),
+            ScrollToNextPrompt => tab.renderer().scroll_to_prompt(1),
+            ToggleRecording => {
+                if tab.is_recording() {
+                    tab.stop_recording();
+                } else {
+                    let record_input = Mux::get().unwrap().config().record_input;
+                    let (path, _) = home_file("cast")?;
+                    tab.start_recording(&path, record_input)?;
+                    self.status =
+                        Some((format!("Recording to {}", path.display()), Instant::now()));
+                }
+            }
+            CopyLastCommandOutput => {
+                if let Some(text) = tab.renderer().last_command_output() {
+                    self.clipboard.set_contents(ClipboardSelection::Clipboard, Some(text))?;
+                }
+            }
+            ExportScrollback(format) | ExportSelection(format) => {
+                let selection = matches!(assignment, ExportSelection(_));
+                let path = tab.export(*format, selection, None)?;
+                self.status = Some((format!("Exported to {}", path.display()), Instant::now()));
+            }
         };
         Ok(())
     }
@@ -463,7 +817,8 @@ impl TermWindow {
 
     fn apply_scale_change(&mut self, dimensions: &Dimensions, font_scale: f64) {
         self.fonts.change_scaling(font_scale, dimensions.dpi as f64 / 96.);
-        self.render_metrics = RenderMetrics::new(&self.fonts);
+        let gutter = Mux::get().unwrap().config().failed_command_marker;
+        self.render_metrics = RenderMetrics::new(&self.fonts, gutter);
         let gl_state = self.render_state.as_mut().unwrap();
         gl_state
             .header
@@ -504,10 +859,11 @@ impl TermWindow {
 
             let pixel_height = rows * self.render_metrics.cell_size.height as u16;
 
-            let pixel_width = cols * self.render_metrics.cell_size.width as u16;
+            let pixel_width = self.render_metrics.gutter_width as usize
+                + cols as usize * self.render_metrics.cell_size.width as usize;
 
             let dims = Dimensions {
-                pixel_width: pixel_width as usize,
+                pixel_width,
                 pixel_height: pixel_height as usize,
                 dpi: dimensions.dpi,
             };
@@ -516,13 +872,15 @@ impl TermWindow {
         } else {
             let rows = (dimensions.pixel_height / self.render_metrics.cell_size.height as usize)
                 .saturating_sub(self.header.offset);
-            let cols = dimensions.pixel_width / self.render_metrics.cell_size.width as usize;
+            let cols =
+                dimensions.pixel_width.saturating_sub(self.render_metrics.gutter_width as usize)
+                    / self.render_metrics.cell_size.width as usize;
 
             let size = PtySize {
                 rows: rows as u16,
                 cols: cols as u16,
-                pixel_height: dimensions.pixel_height as u16,
-                pixel_width: dimensions.pixel_width as u16,
+                pixel_height: (rows * self.render_metrics.cell_size.height as usize) as u16,
+                pixel_width: (cols * self.render_metrics.cell_size.width as usize) as u16,
             };
 
             (size, *dimensions)
@@ -564,10 +922,34 @@ impl TermWindow {
 
     fn paint_screen(&mut self, tab: &Ref<Tab>, frame: &mut glium::Frame) -> anyhow::Result<()> {
         self.frame_count += 1;
+        if self.frame_count % (FPS / 6) == 0 && self.blinking_text.replace(false) {
+            tab.renderer().make_all_lines_dirty();
+        }
         let palette = tab.palette();
         let gl_state = self.render_state.as_ref().unwrap();
         self.clear(&palette, frame);
         self.paint_term(tab, &gl_state, &palette, frame)?;
+        self.header.current_dir = self.current_dir_label();
+        self.header.prompt = self
+            .pending_clipboard_request
+            .as_ref()
+            .map(|request| {
+                let action = if request.is_query() { "read" } else { "write" };
+                format!("Allow the program to {} the clipboard? [y/n]", action)
+            })
+            .or_else(|| {
+                self.pending_paste.as_ref().map(|text| {
+                    format!("Paste {} lines into the terminal? [y/n]", text.lines().count())
+                })
+            })
+            .or_else(|| {
+                let status = tab.exit_status().filter(|_| tab.is_held())?;
+                Some(format!("The program {}. Press Enter to restart it.", status))
+            })
+            .or_else(|| {
+                let (status, since) = self.status.as_ref()?;
+                Some(status.clone()).filter(|_| since.elapsed() < STATUS_DURATION)
+            });
         self.header.paint(
             &gl_state,
             &palette,
@@ -589,6 +971,7 @@ impl TermWindow {
         frame: &mut glium::Frame,
     ) -> anyhow::Result<()> {
         let mut term = tab.renderer();
+        let config = Arc::clone(Mux::get().unwrap().config());
 
         let mut vb = gl_state.glyph_vertex_buffer.borrow_mut();
         let mut quads = gl_state.quads.map(&mut vb);
@@ -600,22 +983,35 @@ impl TermWindow {
 
         let empty_line = Line::from("");
         for i in 0..=self.header.offset - 1 {
-            self.render_screen_line(i, &empty_line, 0..0, &cursor, &*term, &palette, &mut quads)?;
-        }
-
-        let dirty_lines = term.get_dirty_lines();
-        for (line_idx, line, selrange) in dirty_lines {
             self.render_screen_line(
-                line_idx + self.header.offset,
-                &line,
-                selrange,
+                i,
+                &empty_line,
+                0..0,
                 &cursor,
                 &*term,
                 &palette,
+                &config,
                 &mut quads,
             )?;
         }
 
+        let dirty_lines = term.get_dirty_lines();
+        for (line_idx, line, selrange) in dirty_lines {
+            let line_idx = line_idx + self.header.offset;
+            let preedit_line;
+            let (line, cursor) = match self.ime_preedit.as_ref() {
+                Some(preedit) if line_idx as i64 == cursor.y => {
+                    let (line, x) = line_with_preedit(&line, cursor.x, preedit);
+                    preedit_line = line;
+                    (&preedit_line, CursorPosition { x, y: cursor.y })
+                }
+                _ => (&*line, cursor),
+            };
+            self.render_screen_line(
+                line_idx, line, selrange, &cursor, &*term, &palette, &config, &mut quads,
+            )?;
+        }
+
         let tex = gl_state.glyph_cache.borrow().atlas.texture();
         let projection = euclid::Transform3D::<f32, f32, f32>::ortho(
             -(self.dimensions.pixel_width as f32) / 2.0,
@@ -661,6 +1057,7 @@ impl TermWindow {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn render_screen_line(
         &self,
         line_idx: usize,
@@ -669,14 +1066,28 @@ impl TermWindow {
         cursor: &CursorPosition,
         terminal: &Terminal,
         palette: &ColorPalette,
+        config: &Config,
         quads: &mut MappedQuads,
     ) -> anyhow::Result<()> {
         let gl_state = self.render_state.as_ref().unwrap();
         let (_num_rows, num_cols) = terminal.physical_dimensions();
 
         let current_highlight = terminal.current_highlight();
+        let disable_ligatures_under_cursor = config.disable_ligatures_under_cursor;
+        let custom_block_glyphs = config.custom_block_glyphs;
+        let disable_blink = config.disable_blink;
+        let bold_brightens_ansi_colors = config.bold_brightens_ansi_colors;
         let cursor_border_color = rgbcolor_to_window_color(palette.cursor_border);
 
+        let failed_command = line.exit_code().map(|code| code != 0).unwrap_or(false);
+        let failed_command_color = rgbcolor_to_window_color(
+            palette.resolve_fg(term::color::ColorAttribute::PaletteIndex(1)),
+        );
+        let set_cursor_layer = |quad: &mut Quad, cursor_shape: CursorShape| {
+            quad.set_cursor(gl_state.util_sprites.cursor_sprite(cursor_shape).texture_coords());
+            quad.set_cursor_color(cursor_border_color);
+        };
+
         let cell_clusters = line.cluster();
         let mut last_cell_idx = 0;
         for cluster in cell_clusters {
@@ -696,10 +1107,12 @@ impl TermWindow {
                         palette.resolve_fg(attrs.foreground)
                     }
                 }
-                term::color::ColorAttribute::PaletteIndex(idx) if idx < 8 => {
-                    let idx =
-                        if attrs.intensity() == term::Intensity::Bold { idx + 8 } else { idx };
-                    palette.resolve_fg(term::color::ColorAttribute::PaletteIndex(idx))
+                term::color::ColorAttribute::PaletteIndex(idx)
+                    if idx < 8
+                        && bold_brightens_ansi_colors
+                        && attrs.intensity() == term::Intensity::Bold =>
+                {
+                    palette.resolve_fg(term::color::ColorAttribute::PaletteIndex(idx + 8))
                 }
                 _ => palette.resolve_fg(attrs.foreground),
             };
@@ -712,20 +1125,67 @@ impl TermWindow {
                     std::mem::swap(&mut fg, &mut bg);
                 }
 
+                if attrs.intensity() == term::Intensity::Half {
+                    fg = blend_rgb(fg, bg, 0.5);
+                }
+
                 (fg, bg)
             };
 
+            let blink_visible = match attrs.blink() {
+                term::Blink::None => true,
+                _ if disable_blink => true,
+                blink => {
+                    self.blinking_text.set(true);
+                    let period = if blink == term::Blink::Rapid { FPS / 3 } else { FPS };
+                    self.frame_count % period < period / 2
+                }
+            };
+            let fg_color = if blink_visible { fg_color } else { bg_color };
+
             let glyph_color = rgbcolor_to_window_color(fg_color);
             let bg_color = rgbcolor_to_window_color(bg_color);
+            let underline_color = match attrs.underline_color {
+                term::color::ColorAttribute::Default => None,
+                _ if !blink_visible => None,
+                color => Some(rgbcolor_to_window_color(palette.resolve_fg(color))),
+            };
 
             let glyph_info = {
                 let font = self.fonts.resolve_font(style)?;
-                font.shape(&cluster.text)?
+                // The bytes of the grapheme in the cursor cell, if it is
+                // part of this cluster
+                let cursor_bytes = if disable_ligatures_under_cursor && line_idx as i64 == cursor.y
+                {
+                    let mut bytes = cluster
+                        .byte_to_cell_idx
+                        .iter()
+                        .enumerate()
+                        .filter(|&(_, &cell_idx)| cell_idx == cursor.x)
+                        .map(|(byte, _)| byte);
+                    bytes.next().map(|start| start..bytes.last().unwrap_or(start) + 1)
+                } else {
+                    None
+                };
+                match cursor_bytes {
+                    Some(bytes) => font.shape_without_ligatures_in(&cluster.text, bytes)?,
+                    None => font.shape(&cluster.text)?,
+                }
             };
 
             for info in &glyph_info {
                 let cell_idx = cluster.byte_to_cell_idx[info.cluster as usize];
-                let glyph = gl_state.glyph_cache.borrow_mut().cached_glyph(info, style)?;
+                let block_glyph = match cluster.text[info.cluster as usize..].chars().next() {
+                    Some(c) if custom_block_glyphs && is_block_glyph(c) => gl_state
+                        .glyph_cache
+                        .borrow_mut()
+                        .cached_block_glyph(c, &self.render_metrics)?,
+                    _ => None,
+                };
+                let glyph = match block_glyph {
+                    Some(glyph) => glyph,
+                    None => gl_state.glyph_cache.borrow_mut().cached_glyph(info, style)?,
+                };
 
                 let left = (glyph.x_offset + glyph.bearing_x).get() as f32;
                 let top = ((PixelLength::new(self.render_metrics.cell_size.height as f64)
@@ -735,7 +1195,11 @@ impl TermWindow {
 
                 let underline_tex_rect = gl_state
                     .util_sprites
-                    .select_sprite(is_highlited_hyperlink, attrs.strikethrough(), attrs.underline())
+                    .select_sprite(is_highlited_hyperlink, attrs.underline())
+                    .texture_coords();
+                let overline_tex_rect = gl_state
+                    .util_sprites
+                    .overline_sprite(attrs.overline(), attrs.strikethrough())
                     .texture_coords();
 
                 for glyph_idx in 0..info.num_cells as usize {
@@ -754,6 +1218,7 @@ impl TermWindow {
                         glyph_color,
                         bg_color,
                         palette,
+                        config.minimum_contrast,
                     );
 
                     let texture =
@@ -783,17 +1248,34 @@ impl TermWindow {
                     quad.set_texture(texture_rect);
                     quad.set_texture_adjust(left, top, right, bottom);
                     quad.set_underline(underline_tex_rect);
+                    quad.set_underline_color(underline_color.unwrap_or(glyph_color));
+                    quad.set_overline(overline_tex_rect);
                     quad.set_has_color(glyph.has_color);
-                    quad.set_cursor(
-                        gl_state.util_sprites.cursor_sprite(cursor_shape).texture_coords(),
-                    );
-                    quad.set_cursor_color(cursor_border_color);
+                    quad.set_subpixel(glyph.subpixel);
+                    set_cursor_layer(&mut quad, cursor_shape);
                 }
             }
         }
 
         let white_space = gl_state.util_sprites.white_space.texture_coords();
 
+        // The gutter is left to the window background unless the line is the
+        // prompt of a failed command
+        let mut gutter = quads.gutter(line_idx)?;
+        gutter.set_bg_color(if failed_command {
+            failed_command_color
+        } else {
+            Color::rgba(0, 0, 0, 0)
+        });
+        gutter.set_fg_color(failed_command_color);
+        gutter.set_texture(white_space);
+        gutter.set_texture_adjust(0., 0., 0., 0.);
+        gutter.set_underline(white_space);
+        gutter.set_overline(white_space);
+        gutter.set_cursor(white_space);
+        gutter.set_has_color(false);
+        gutter.set_subpixel(false);
+
         for cell_idx in last_cell_idx + 1..num_cols {
             let (glyph_color, bg_color, cursor_shape) = self.compute_cell_fg_bg(
                 line_idx,
@@ -803,6 +1285,7 @@ impl TermWindow {
                 rgbcolor_to_window_color(palette.foreground),
                 rgbcolor_to_window_color(palette.background),
                 palette,
+                config.minimum_contrast,
             );
 
             let mut quad = quads.cell(cell_idx, line_idx)?;
@@ -812,9 +1295,10 @@ impl TermWindow {
             quad.set_texture(white_space);
             quad.set_texture_adjust(0., 0., 0., 0.);
             quad.set_underline(white_space);
+            quad.set_overline(white_space);
             quad.set_has_color(false);
-            quad.set_cursor(gl_state.util_sprites.cursor_sprite(cursor_shape).texture_coords());
-            quad.set_cursor_color(cursor_border_color);
+            quad.set_subpixel(false);
+            set_cursor_layer(&mut quad, cursor_shape);
         }
 
         Ok(())
@@ -830,6 +1314,7 @@ impl TermWindow {
         fg_color: Color,
         bg_color: Color,
         palette: &ColorPalette,
+        minimum_contrast: f32,
     ) -> (Color, Color, CursorShape) {
         let selected = selection.contains(&cell_idx);
 
@@ -851,6 +1336,15 @@ impl TermWindow {
             _ => (fg_color, bg_color),
         };
 
+        let (fg_color, bg_color) =
+            if self.visual_bell.is_some() { (bg_color, fg_color) } else { (fg_color, bg_color) };
+
+        let fg_color = if minimum_contrast > 1.0 && fg_color.0 != bg_color.0 {
+            fg_color.ensure_contrast(bg_color, minimum_contrast)
+        } else {
+            fg_color
+        };
+
         (fg_color, bg_color, cursor_shape)
     }
 
@@ -861,10 +1355,49 @@ impl TermWindow {
     }
 }
 
+/// Overlays the preedit text on the line at `x`, returning the line and
+/// the column that the cursor goes to for the preedit caret.
+fn line_with_preedit(line: &Line, x: usize, preedit: &Preedit) -> (Line, usize) {
+    let attrs = line.cells().get(x).map(|cell| cell.attrs().clone()).unwrap_or_default();
+    let text: String = preedit.text.iter().map(|&(c, _)| c).collect();
+
+    let mut line = line.clone();
+    let mut x = x;
+    let mut cursor = None;
+    let mut idx = 0;
+    for cell in Line::from_text(&text, &attrs).visible_cells().map(|(_, cell)| cell) {
+        if cursor.is_none() && idx >= preedit.caret {
+            cursor = Some(x);
+        }
+        let feedback = preedit.text.get(idx).map(|&(_, f)| f).unwrap_or_default();
+        idx += cell.str().chars().count();
+
+        let mut attrs = cell.attrs().clone();
+        if feedback.is_empty() || feedback.contains(PreeditFeedback::UNDERLINE) {
+            attrs.set_underline(term::Underline::Single);
+        }
+        if feedback.contains(PreeditFeedback::REVERSE) {
+            attrs.set_reverse(!attrs.reverse());
+        }
+        if feedback.contains(PreeditFeedback::HIGHLIGHT) {
+            attrs.set_intensity(term::Intensity::Bold);
+        }
+        let width = cell.width();
+        line.set_cell(x, term::Cell::new_grapheme(cell.str(), attrs));
+        x += width;
+    }
+    (line, cursor.unwrap_or(x))
+}
+
 fn rgbcolor_to_window_color(color: RgbColor) -> Color {
     Color::rgba(color.red, color.green, color.blue, 0xff)
 }
 
+fn blend_rgb(fg: RgbColor, bg: RgbColor, amount: f32) -> RgbColor {
+    let blend = |f: u8, b: u8| (f as f32 * amount + b as f32 * (1.0 - amount)).round() as u8;
+    RgbColor::new(blend(fg.red, bg.red), blend(fg.green, bg.green), blend(fg.blue, bg.blue))
+}
+
 fn window_mods_to_termwiz_mods(modifiers: window::Modifiers) -> crate::core::input::Modifiers {
     let mut result = crate::core::input::Modifiers::NONE;
     if modifiers.contains(window::Modifiers::SHIFT) {
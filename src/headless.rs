@@ -0,0 +1,148 @@
+use crate::config::Config;
+use crate::core::escape::csi::Window;
+use crate::core::hyperlink::Hyperlink;
+use crate::pty::{unix, PtySize, PtySystem};
+use crate::term::clipboard::{Clipboard, ClipboardRequest, ClipboardSelection};
+use crate::term::dump::{self, DumpFormat};
+use crate::term::scrollback::ScrollbackStats;
+use crate::term::{Alert, Terminal, TerminalHost};
+use anyhow::bail;
+use std::io::Read;
+use std::process::Command;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long to wait for more output once the command has exited, in case
+/// something it started is still holding the pty open.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+pub struct Outcome {
+    pub dump: String,
+    /// Whether the command exited successfully, or `None` if it was killed
+    /// after running past the timeout.
+    pub success: Option<bool>,
+    pub stats: ScrollbackStats,
+}
+
+/// Answers the terminal's queries on the pty and ignores everything that
+/// would need a window.
+struct Host<'a> {
+    writer: &'a mut dyn std::io::Write,
+}
+
+impl<'a> TerminalHost for Host<'a> {
+    fn writer(&mut self) -> &mut dyn std::io::Write {
+        &mut self.writer
+    }
+
+    fn get_clipboard(&mut self) -> anyhow::Result<Arc<dyn Clipboard>> {
+        bail!("there is no clipboard in headless mode")
+    }
+
+    fn set_title(&mut self, _title: &str) {}
+
+    fn click_link(&mut self, _link: &Arc<Hyperlink>) {}
+
+    fn alert(&mut self, _alert: Alert) {}
+
+    fn clipboard_request(&mut self, _request: ClipboardRequest) {}
+
+    fn window_request(&mut self, _request: Window) {}
+
+    fn paste_selection(&mut self, _selection: ClipboardSelection) {}
+}
+
+/// Runs `command` on a pty of the given size without a window until it
+/// exits or `timeout` passes, and dumps the resulting screen.  `export`
+/// renders the scrollback and screen with the wrapped lines joined, as
+/// the export key assignments do, instead.
+pub fn run(
+    config: &Config,
+    mut command: Command,
+    size: PtySize,
+    timeout: Duration,
+    format: DumpFormat,
+    scrollback: bool,
+    export: bool,
+) -> anyhow::Result<Outcome> {
+    command.env("TERM", "xterm-256color");
+    let pair = unix::UnixPtySystem.openpty(size)?;
+    let mut child = pair.slave.spawn_command(command)?;
+    drop(pair.slave);
+    let mut master = pair.master;
+
+    let mut terminal = Terminal::new(
+        size.rows as usize,
+        size.cols as usize,
+        size.pixel_width as usize,
+        size.pixel_height as usize,
+        config.scrollback_lines.unwrap_or(3500),
+        config.hyperlink_rules.clone(),
+    );
+    terminal.set_title_reporting(config.enable_title_reporting);
+
+    let mut reader = master.try_clone_reader()?;
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        let mut buf = [0; 32 * 1024];
+        while let Ok(size) = reader.read(&mut buf) {
+            if size == 0 || tx.send(buf[..size].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let timeout = Instant::now() + timeout;
+    let mut deadline = timeout;
+    let mut status = None;
+    let mut eof = false;
+    loop {
+        if status.is_none() {
+            status = child.try_wait()?;
+            if status.is_some() {
+                deadline = timeout.min(Instant::now() + DRAIN_TIMEOUT);
+            }
+        }
+
+        let now = Instant::now();
+        if (status.is_some() && eof) || now >= deadline {
+            break;
+        }
+        let wait = (deadline - now).min(POLL_INTERVAL);
+        if eof {
+            std::thread::sleep(wait);
+            continue;
+        }
+
+        match rx.recv_timeout(wait) {
+            Ok(data) => {
+                terminal.advance_bytes(&data, &mut Host { writer: &mut master });
+                if status.is_some() {
+                    deadline = timeout.min(Instant::now() + DRAIN_TIMEOUT);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => eof = true,
+        }
+    }
+
+    if status.is_none() {
+        child.kill().ok();
+        child.wait().ok();
+    }
+
+    let dump = if export {
+        let mut out = vec![];
+        dump::export(&terminal, format, false, &mut out)?;
+        String::from_utf8(out)?
+    } else {
+        dump::dump(&terminal, format, scrollback)
+    };
+    Ok(Outcome {
+        dump,
+        success: status.map(|status| status.success()),
+        stats: terminal.screen().stats(),
+    })
+}
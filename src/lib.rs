@@ -0,0 +1,12 @@
+//! Everything but the command line lives in the library so that the fuzz
+//! targets under `fuzz/` can drive the parser and the terminal directly.
+
+pub mod config;
+pub mod core;
+pub mod font;
+pub mod gui;
+pub mod headless;
+pub mod mux;
+pub mod pty;
+pub mod term;
+pub mod window;
@@ -1,32 +1,162 @@
-use clap::{crate_description, crate_name, crate_version, App, AppSettings, Arg};
+use anyhow::bail;
+use clap::{
+    crate_description, crate_name, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand,
+};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::config::Theme;
-use crate::font::FontConfiguration;
-use crate::mux::Mux;
-use crate::pty::PtySize;
-use crate::term::color::RgbColor;
-
-mod config;
-mod core;
-mod font;
-mod gui;
-mod mux;
-mod pty;
-mod term;
-mod window;
-
-fn run(theme: Theme) -> anyhow::Result<()> {
-    let config = Arc::new(config::Config::default_config(theme));
-    let fontconfig = Rc::new(FontConfiguration::new(Arc::clone(&config)));
+use miro::config::{self, Theme};
+use miro::font::FontConfiguration;
+use miro::mux::{self, control, Mux};
+use miro::pty::asciicast::Cast;
+use miro::pty::{self, PtySize};
+use miro::term::color::RgbColor;
+use miro::{gui, headless};
+
+/// How to launch the windowed terminal, from the command line.
+struct Launch<'a> {
+    command: Command,
+    size: PtySize,
+    title: Option<&'a str>,
+    window: gui::WindowOptions,
+    record: Option<&'a Path>,
+}
+
+fn run(config: config::Config, mut launch: Launch) -> anyhow::Result<()> {
+    let config = Arc::new(config);
+    let socket = match control::listen() {
+        Ok(socket) => {
+            launch.command.env(control::SOCKET_ENV, &socket);
+            Some(socket)
+        }
+        Err(err) => {
+            eprintln!("miro export is unavailable: {:#}", err);
+            None
+        }
+    };
+    let clipboard = Arc::new(gui::SystemClipboard::new());
+    let mux = mux::Mux::new(&config, launch.size, launch.command, clipboard)?;
+    if let Some(title) = launch.title {
+        mux.get_tab().renderer().set_title(title);
+    }
+    if let Some(path) = launch.record {
+        mux.get_tab().start_recording(path, config.record_input)?;
+    }
+    let result = run_mux(&config, mux, &launch.window);
+    if let Some(socket) = socket {
+        control::remove(&socket);
+    }
+    result
+}
+
+/// Asks the window that this shell runs in to export its scrollback, and
+/// prints where the export went.
+fn export(matches: &ArgMatches) -> anyhow::Result<()> {
+    let socket = match std::env::var_os(control::SOCKET_ENV) {
+        Some(socket) => socket,
+        None => bail!(
+            "miro export is unavailable: {} is not set, so this is not running in a miro \
+             window that accepts exports",
+            control::SOCKET_ENV
+        ),
+    };
+    let path = match matches.value_of("output") {
+        Some(path) => Some(std::env::current_dir()?.join(path)),
+        None => None,
+    };
+    let request = control::ExportRequest {
+        format: matches.value_of("format").unwrap().parse()?,
+        selection: matches.is_present("selection"),
+        path,
+    };
+    let path = control::export(Path::new(&socket), &request)?;
+    println!("{}", path.display());
+    Ok(())
+}
+
+fn play(
+    config: config::Config,
+    window: &gui::WindowOptions,
+    path: &Path,
+    speed: f64,
+) -> anyhow::Result<()> {
+    let config = Arc::new(config);
+    let cast = Cast::load(path)?;
+    let clipboard = Arc::new(gui::SystemClipboard::new());
+    run_mux(&config, mux::Mux::new_playback(&config, cast, speed, clipboard), window)
+}
+
+/// Builds the command to run from its program and arguments, or the
+/// user's shell if there are none.
+fn build_command<'a>(args: Option<impl Iterator<Item = &'a str>>) -> anyhow::Result<Command> {
+    Ok(match args {
+        Some(mut args) => {
+            let mut command = Command::new(args.next().unwrap());
+            command.args(args);
+            command
+        }
+        None => Command::new(pty::get_shell()?),
+    })
+}
+
+fn headless(mut config: config::Config, matches: &ArgMatches) -> anyhow::Result<()> {
+    if let Some(lines) = matches.value_of("scrollback-lines") {
+        config.scrollback_lines = Some(lines.parse()?);
+    }
+    let size = PtySize {
+        rows: matches.value_of("rows").unwrap().parse()?,
+        cols: matches.value_of("cols").unwrap().parse()?,
+        ..PtySize::default()
+    };
+    let timeout = Duration::from_secs_f64(matches.value_of("timeout").unwrap().parse()?);
+    let format = matches.value_of("format").unwrap().parse()?;
+    let command = build_command(matches.values_of("command"))?;
+
+    let outcome = headless::run(
+        &config,
+        command,
+        size,
+        timeout,
+        format,
+        matches.is_present("scrollback"),
+        matches.is_present("export"),
+    )?;
+    match matches.value_of("output") {
+        Some(path) => std::fs::write(path, &outcome.dump)?,
+        None => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(outcome.dump.as_bytes())?;
+            stdout.flush()?;
+        }
+    }
+    if matches.is_present("stats") {
+        eprintln!("{}", outcome.stats);
+    }
+
+    std::process::exit(match outcome.success {
+        Some(true) => 0,
+        Some(false) => 1,
+        None => 124,
+    })
+}
+
+fn run_mux(
+    config: &Arc<config::Config>,
+    mux: Mux,
+    window: &gui::WindowOptions,
+) -> anyhow::Result<()> {
+    let fontconfig = Rc::new(FontConfiguration::new(Arc::clone(config)));
     let gui = gui::new()?;
-    let mux = Rc::new(mux::Mux::new(&config, PtySize::default())?);
+    let mux = Rc::new(mux);
     Mux::set_mux(&mux);
 
     mux.start()?;
 
-    gui.spawn_new_window(&fontconfig)?;
+    gui.spawn_new_window(&fontconfig, window)?;
 
     gui.run_forever()
 }
@@ -49,8 +179,204 @@ fn main() -> anyhow::Result<()> {
                 .hide_default_value(true)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("FILE")
+                .help("Record the session to FILE in the asciicast v2 format.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("record-input")
+                .long("record-input")
+                .help("Include what is typed, passwords too, in recordings."),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read the configuration from FILE, a JSON object of settings.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cwd")
+                .long("cwd")
+                .value_name("DIR")
+                .help("Start the program in DIR.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("env")
+                .long("env")
+                .value_name("KEY=VAL")
+                .help("Set an environment variable for the program. May be repeated.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("geometry")
+                .long("geometry")
+                .value_name("COLSxROWS")
+                .help("Size of the terminal in cells, 80x24 by default.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("title")
+                .long("title")
+                .help("Title of the window, until the program sets its own.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("class")
+                .long("class")
+                .help("Window class that window managers match rules against.")
+                .default_value("miro")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("hold")
+                .long("hold")
+                .help("Keep the window open after the program exits, to restart it with Enter."),
+        )
+        .arg(
+            // Launchers and file managers pass the program to terminals
+            // with -e; everything after it is the program's own.
+            Arg::with_name("exec")
+                .short("e")
+                .value_name("PROGRAM")
+                .help("The program to run instead of the shell, with its arguments.")
+                .takes_value(true)
+                .multiple(true)
+                .allow_hyphen_values(true)
+                .conflicts_with("command"),
+        )
+        .arg(
+            Arg::with_name("command")
+                .multiple(true)
+                .last(true)
+                .help("The program to run instead of the shell, after --."),
+        )
+        .subcommand(
+            SubCommand::with_name("play")
+                .about("Replay a session recorded in the asciicast v2 format.")
+                .arg(Arg::with_name("file").required(true).help("The recording to replay."))
+                .arg(
+                    Arg::with_name("speed")
+                        .short("s")
+                        .long("speed")
+                        .help("Playback speed multiplier.")
+                        .default_value("1")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about(
+                    "Save the scrollback and screen of the miro window this runs in, and print \
+                     where they went.",
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .help("How to save them (*text*, ansi, json, html).")
+                        .possible_values(&["text", "ansi", "json", "html"])
+                        .default_value("text")
+                        .hide_default_value(true)
+                        .takes_value(true),
+                )
+                .arg(Arg::with_name("selection").long("selection").help("Save only the selection."))
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write to FILE instead of a new file in the home directory.")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("headless")
+                .about(
+                    "Run a command on a pty without a window and print the final screen. \
+                     Exits with 0 if the command succeeded, 1 if it failed and 124 if it \
+                     timed out.",
+                )
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("rows")
+                        .long("rows")
+                        .help("Number of rows in the terminal.")
+                        .default_value("24")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cols")
+                        .long("cols")
+                        .help("Number of columns in the terminal.")
+                        .default_value("80")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .help("Kill the command if it is still running after this long.")
+                        .default_value("10")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .help("How to print the screen (*text*, ansi, json, html).")
+                        .possible_values(&["text", "ansi", "json", "html"])
+                        .default_value("text")
+                        .hide_default_value(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("scrollback")
+                        .long("scrollback")
+                        .help("Print the scrollback before the screen."),
+                )
+                .arg(
+                    Arg::with_name("export")
+                        .long("export")
+                        .conflicts_with("scrollback")
+                        .help("Print the scrollback and screen with wrapped lines joined."),
+                )
+                .arg(
+                    Arg::with_name("scrollback-lines")
+                        .long("scrollback-lines")
+                        .value_name("N")
+                        .help("Number of lines of scrollback to keep.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("stats")
+                        .long("stats")
+                        .help("Print how much memory the screen and scrollback take to stderr."),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the screen to FILE instead of stdout.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .multiple(true)
+                        .help("The command to run, defaulting to the shell."),
+                ),
+        )
         .get_matches();
 
+    if let Some(export_matches) = matches.subcommand_matches("export") {
+        return export(export_matches);
+    }
+
     let theme = match matches.value_of("theme") {
         Some("mario") => Theme {
             spritesheet_path: String::from(concat!(
@@ -76,5 +402,61 @@ fn main() -> anyhow::Result<()> {
         _ => unreachable!("not possible"),
     };
 
-    run(theme)
+    let config_path = match matches.value_of("config") {
+        Some(path) => Some(std::env::current_dir()?.join(path)),
+        None => None,
+    };
+    let mut config = match &config_path {
+        Some(path) => config::Config::load(path, theme)?,
+        None => config::Config::default_config(theme),
+    };
+    let window = gui::WindowOptions {
+        class: matches.value_of("class").unwrap().to_string(),
+        theme: matches.value_of("theme").unwrap().to_string(),
+        config: config_path,
+    };
+
+    if let Some(headless_matches) = matches.subcommand_matches("headless") {
+        return headless(config, headless_matches);
+    }
+
+    if let Some(play_matches) = matches.subcommand_matches("play") {
+        let path = Path::new(play_matches.value_of("file").unwrap());
+        let speed = play_matches.value_of("speed").unwrap().parse::<f64>()?;
+        return play(config, &window, path, speed);
+    }
+
+    if matches.is_present("hold") {
+        config.exit_behavior = config::ExitBehavior::Hold;
+    }
+    if matches.is_present("record-input") {
+        config.record_input = true;
+    }
+    let args = matches.values_of("exec").or_else(|| matches.values_of("command"));
+    let mut command = build_command(args)?;
+    if let Some(dir) = matches.value_of("cwd") {
+        command.current_dir(dir);
+    }
+    for var in matches.values_of("env").into_iter().flatten() {
+        let mut parts = var.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if !key.is_empty() => command.env(key, value),
+            _ => bail!("invalid environment variable {:?}, expected KEY=VAL", var),
+        };
+    }
+    let size = match matches.value_of("geometry") {
+        Some(geometry) => geometry.parse()?,
+        None => PtySize::default(),
+    };
+
+    run(
+        config,
+        Launch {
+            command,
+            size,
+            title: matches.value_of("title"),
+            window,
+            record: matches.value_of("record").map(Path::new),
+        },
+    )
 }
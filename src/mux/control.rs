@@ -0,0 +1,122 @@
+use crate::core::promise;
+use crate::mux::Mux;
+use crate::term::dump::DumpFormat;
+use anyhow::{bail, Context};
+use serde_derive::*;
+use std::fs::DirBuilder;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::thread;
+
+/// Set in the environment of the program running in the terminal, so that
+/// `miro export` can find the window it runs in.
+pub const SOCKET_ENV: &str = "MIRO_SOCKET";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportRequest {
+    pub format: DumpFormat,
+    pub selection: bool,
+    /// Where to write the export, or `None` for a new file in the home
+    /// directory.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Reply {
+    Exported(PathBuf),
+    Failed(String),
+}
+
+/// The directory of this process's socket, in the runtime directory.
+fn socket_dir() -> PathBuf {
+    let dir =
+        std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+    dir.join(format!("miro-{}", std::process::id()))
+}
+
+/// Answers export requests from a thread of its own, performing each of
+/// them on the gui thread, and returns the path of the socket.
+pub fn listen() -> anyhow::Result<PathBuf> {
+    // Whoever can connect can have files written as us, so the socket goes
+    // in a new directory that only we can enter.
+    let dir = socket_dir();
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("creating {}", dir.display()))?;
+    let path = dir.join("socket");
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            std::fs::remove_dir(&dir).ok();
+            return Err(err).with_context(|| format!("listening on {}", path.display()));
+        }
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            serve(stream).ok();
+        }
+    });
+    Ok(path)
+}
+
+/// Removes the socket made by `listen` along with its directory.
+pub fn remove(socket: &Path) {
+    std::fs::remove_file(socket).ok();
+    if let Some(dir) = socket.parent() {
+        std::fs::remove_dir(dir).ok();
+    }
+}
+
+fn serve(mut stream: UnixStream) -> anyhow::Result<()> {
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let request: ExportRequest = serde_json::from_str(&line)?;
+
+    let (tx, rx) = channel();
+    promise::spawn_into_main_thread(async move {
+        let mux = Mux::get().unwrap();
+        let result = mux.get_tab().export(request.format, request.selection, request.path);
+        tx.send(result.map_err(|err| err.to_string())).ok();
+    });
+    let reply = match rx.recv()? {
+        Ok(path) => Reply::Exported(path),
+        Err(err) => Reply::Failed(err),
+    };
+    writeln!(stream, "{}", serde_json::to_string(&reply)?)?;
+    Ok(())
+}
+
+/// Asks the window listening on `socket` to perform `request`, returning
+/// where the export was written.
+pub fn export(socket: &Path, request: &ExportRequest) -> anyhow::Result<PathBuf> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("connecting to {}", socket.display()))?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    match serde_json::from_str(&line)? {
+        Reply::Exported(path) => Ok(path),
+        Reply::Failed(err) => bail!("{}", err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn request_round_trip() {
+        let request = ExportRequest {
+            format: DumpFormat::Html,
+            selection: true,
+            path: Some("/tmp/x".into()),
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains("\"format\":\"html\""));
+        assert_eq!(serde_json::from_str::<ExportRequest>(&json).unwrap(), request);
+    }
+}
@@ -1,24 +1,33 @@
-use crate::config::Config;
+use crate::config::{Config, ExitBehavior, WindowOperation};
+use crate::core::escape::csi::Window;
 use crate::core::hyperlink::Hyperlink;
 use crate::core::promise;
 use crate::core::ratelim::RateLimiter;
-use crate::mux::tab::Tab;
-use crate::pty::{unix, PtySize, PtySystem};
-use crate::term::clipboard::Clipboard;
-use crate::term::TerminalHost;
-use anyhow::bail;
+use crate::mux::tab::{clone_command, Tab};
+use crate::pty::asciicast::Cast;
+use crate::pty::playback::spawn_playback;
+use crate::pty::{unix, Child, MasterPty, PtySize, PtySystem};
+use crate::term::clipboard::{Clipboard, ClipboardRequest, ClipboardSelection};
+use crate::term::{Alert, TerminalHost};
 use std::cell::{Ref, RefCell};
-use std::io::Read;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
 use std::process::Command;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::thread;
 
+pub mod control;
 pub mod tab;
 
 pub struct Mux {
     tab: RefCell<Tab>,
     config: Arc<Config>,
+    alerts: RefCell<Vec<Alert>>,
+    clipboard: Arc<dyn Clipboard>,
+    clipboard_requests: RefCell<VecDeque<ClipboardRequest>>,
+    window_requests: RefCell<Vec<Window>>,
+    pastes: RefCell<Vec<String>>,
 }
 
 fn read_from_tab_pty(config: Arc<Config>, mut reader: Box<dyn std::io::Read>) {
@@ -66,10 +75,34 @@ impl<'a> TerminalHost for Host<'a> {
     }
 
     fn get_clipboard(&mut self) -> anyhow::Result<Arc<dyn Clipboard>> {
-        bail!("peer requested clipboard; ignoring");
+        Ok(Arc::clone(Mux::get().unwrap().clipboard()))
     }
 
     fn set_title(&mut self, _title: &str) {}
+
+    fn alert(&mut self, alert: Alert) {
+        if let Some(mux) = Mux::get() {
+            mux.push_alert(alert);
+        }
+    }
+
+    fn clipboard_request(&mut self, request: ClipboardRequest) {
+        if let Some(mux) = Mux::get() {
+            mux.clipboard_request(request);
+        }
+    }
+
+    fn window_request(&mut self, request: Window) {
+        if let Some(mux) = Mux::get() {
+            mux.push_window_request(request);
+        }
+    }
+
+    fn paste_selection(&mut self, selection: ClipboardSelection) {
+        if let Some(mux) = Mux::get() {
+            mux.paste_selection(selection);
+        }
+    }
 }
 
 thread_local! {
@@ -77,12 +110,54 @@ thread_local! {
 }
 
 impl Mux {
-    pub fn new(config: &Arc<Config>, size: PtySize) -> anyhow::Result<Self> {
+    /// Runs `command` on a pty of the given size.  The terminal reaches
+    /// the system clipboard and selections through `clipboard`.
+    pub fn new(
+        config: &Arc<Config>,
+        size: PtySize,
+        command: Command,
+        clipboard: Arc<dyn Clipboard>,
+    ) -> anyhow::Result<Self> {
         let pty_system = Box::new(unix::UnixPtySystem);
         let pair = pty_system.openpty(size)?;
-        let child = pair.slave.spawn_command(Command::new(crate::pty::get_shell()?))?;
+        let child = pair.slave.spawn_command(clone_command(&command))?;
 
-        let terminal = crate::term::Terminal::new(
+        Ok(Self::with_pty(config, size, child, pair.master, Some(command), clipboard))
+    }
+
+    /// Creates a mux whose tab replays a recorded session instead of
+    /// running a shell.  The playback resizes the window through XTWINOPS
+    /// wherever the recording was resized, so that is always allowed.
+    pub fn new_playback(
+        config: &Arc<Config>,
+        cast: Cast,
+        speed: f64,
+        clipboard: Arc<dyn Clipboard>,
+    ) -> Self {
+        let mut config = Config::clone(config);
+        if !config.allow_window_ops.contains(&WindowOperation::Resize) {
+            config.allow_window_ops.push(WindowOperation::Resize);
+        }
+        let rewind = Box::new(|| {
+            promise::spawn_into_main_thread_with_low_priority(async move {
+                Mux::get().unwrap().get_tab().rewind();
+            });
+        });
+        let (pty, child) = spawn_playback(cast, speed, rewind);
+        let size = pty.get_size().unwrap_or_default();
+        let config = Arc::new(config);
+        Self::with_pty(&config, size, Box::new(child), Box::new(pty), None, clipboard)
+    }
+
+    fn with_pty(
+        config: &Arc<Config>,
+        size: PtySize,
+        child: Box<dyn Child>,
+        master: Box<dyn MasterPty>,
+        command: Option<Command>,
+        clipboard: Arc<dyn Clipboard>,
+    ) -> Self {
+        let mut terminal = crate::term::Terminal::new(
             size.rows as usize,
             size.cols as usize,
             size.pixel_width as usize,
@@ -90,10 +165,32 @@ impl Mux {
             config.scrollback_lines.unwrap_or(3500),
             config.hyperlink_rules.clone(),
         );
+        terminal.set_copy_on_select(config.copy_on_select);
+        terminal.set_middle_click_paste(config.middle_click_paste);
+        terminal.set_title_reporting(config.enable_title_reporting);
+        terminal.set_paste_filter(config.paste_filter);
 
-        let tab = Tab::new(terminal, child, pair.master);
+        // A playback ends when it is quit, so there is nothing to hold.
+        let exit_behavior =
+            if command.is_some() { config.exit_behavior } else { ExitBehavior::Close };
+        let tab = Tab::new(terminal, child, master, command, exit_behavior);
 
-        Ok(Self { tab: RefCell::new(tab), config: Arc::clone(config) })
+        Self {
+            tab: RefCell::new(tab),
+            config: Arc::clone(config),
+            alerts: RefCell::new(Vec::new()),
+            clipboard,
+            clipboard_requests: RefCell::new(VecDeque::new()),
+            window_requests: RefCell::new(Vec::new()),
+            pastes: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Spawns the tab's command again once it has exited, and starts
+    /// reading from its new pty.
+    pub fn respawn(&self) -> anyhow::Result<()> {
+        self.tab.borrow().respawn()?;
+        self.start()
     }
 
     pub fn start(&self) -> anyhow::Result<()> {
@@ -128,6 +225,87 @@ impl Mux {
         self.tab.borrow()
     }
 
+    pub fn push_alert(&self, alert: Alert) {
+        self.alerts.borrow_mut().push(alert);
+    }
+
+    pub fn take_alerts(&self) -> Vec<Alert> {
+        std::mem::take(&mut *self.alerts.borrow_mut())
+    }
+
+    pub fn push_window_request(&self, request: Window) {
+        self.window_requests.borrow_mut().push(request);
+    }
+
+    pub fn take_window_requests(&self) -> Vec<Window> {
+        std::mem::take(&mut *self.window_requests.borrow_mut())
+    }
+
+    pub fn push_paste(&self, text: String) {
+        self.pastes.borrow_mut().push(text);
+    }
+
+    pub fn take_pastes(&self) -> Vec<String> {
+        std::mem::take(&mut *self.pastes.borrow_mut())
+    }
+
+    pub fn clipboard(&self) -> &Arc<dyn Clipboard> {
+        &self.clipboard
+    }
+
+    /// Checks an OSC 52 request against the configured policy for each
+    /// selection it targets, performing it immediately when allowed or
+    /// queueing it for confirmation by the user.
+    pub fn clipboard_request(&self, request: ClipboardRequest) {
+        let decisions: Vec<_> = ClipboardSelection::targets(request.selection())
+            .into_iter()
+            .map(|target| match target {
+                ClipboardSelection::Clipboard => self.config.osc52_clipboard.permits(&request),
+                ClipboardSelection::Primary => self.config.osc52_primary.permits(&request),
+            })
+            .collect();
+
+        if decisions.is_empty() || decisions.contains(&Some(false)) {
+            return;
+        }
+        if decisions.contains(&None) {
+            self.clipboard_requests.borrow_mut().push_back(request);
+            return;
+        }
+        self.perform_clipboard_request(&request);
+    }
+
+    /// Applies an OSC 52 request that is allowed, answering queries on the
+    /// pty once the contents arrive.
+    pub fn perform_clipboard_request(&self, request: &ClipboardRequest) {
+        let reply = Box::new(|reply: String| {
+            // The terminal may still be parsing the output that asked, with
+            // the pty borrowed, so the answer is written from the event loop.
+            promise::spawn(async move {
+                if let Some(mux) = Mux::get() {
+                    mux.get_tab().writer().write_all(reply.as_bytes()).ok();
+                }
+            });
+        });
+        request.perform(&*self.clipboard, self.config.osc52_max_bytes, reply).ok();
+    }
+
+    /// Pastes the contents of `selection` once they have been fetched.
+    pub fn paste_selection(&self, selection: ClipboardSelection) {
+        self.clipboard.get_contents(
+            selection,
+            Box::new(|contents| {
+                if let (Ok(text), Some(mux)) = (contents, Mux::get()) {
+                    mux.push_paste(text);
+                }
+            }),
+        );
+    }
+
+    pub fn take_clipboard_request(&self) -> Option<ClipboardRequest> {
+        self.clipboard_requests.borrow_mut().pop_front()
+    }
+
     pub fn close(&self) {
         self.tab.borrow_mut().close()
     }
@@ -1,9 +1,19 @@
+use crate::config::ExitBehavior;
 use crate::core::promise;
 use crate::mux::Mux;
-use crate::pty::{Child, MasterPty, PtySize};
+use crate::pty::asciicast::{Recorder, RecordingPty, SharedRecorder};
+use crate::pty::{unix, Child, ExitStatus, MasterPty, PtySize, PtySystem};
 use crate::term::color::ColorPalette;
+use crate::term::dump::{self, DumpFormat};
+use crate::term::paste::normalize_newlines;
 use crate::term::{KeyCode, KeyModifiers, MouseEvent, Terminal, TerminalHost};
-use std::cell::{RefCell, RefMut};
+use anyhow::{bail, Context};
+use chrono::Local;
+use std::cell::{Cell, RefCell, RefMut};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::sync::{Arc, Mutex};
 
 const PASTE_CHUNK_SIZE: usize = 1024;
@@ -13,6 +23,16 @@ struct Paste {
     offset: usize,
 }
 
+/// The length of the next chunk of `text` to paste from `offset`, kept
+/// short of `PASTE_CHUNK_SIZE` so that it ends on a character boundary.
+fn chunk_len(text: &str, offset: usize) -> usize {
+    let mut end = (offset + PASTE_CHUNK_SIZE).min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    end - offset
+}
+
 fn schedule_next_paste(paste: &Arc<Mutex<Paste>>) {
     let paste = Arc::clone(paste);
     promise::spawn(async move {
@@ -21,7 +41,7 @@ fn schedule_next_paste(paste: &Arc<Mutex<Paste>>) {
         let tab = mux.get_tab();
 
         let remain = locked.text.len() - locked.offset;
-        let chunk = remain.min(PASTE_CHUNK_SIZE);
+        let chunk = chunk_len(&locked.text, locked.offset);
         let text_slice = &locked.text[locked.offset..locked.offset + chunk];
         tab.send_paste(text_slice).unwrap();
 
@@ -32,11 +52,54 @@ fn schedule_next_paste(paste: &Arc<Mutex<Paste>>) {
     });
 }
 
+/// Creates a file named after the current time in the home directory, for
+/// the recordings and exports that are not given a path, adding a counter
+/// to the name when it is already taken.
+pub fn home_file(extension: &str) -> anyhow::Result<(PathBuf, File)> {
+    let dir = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+    let stamp = Local::now().format("%Y%m%d-%H%M%S");
+    for n in 1.. {
+        let name = match n {
+            1 => format!("miro-{}.{}", stamp, extension),
+            n => format!("miro-{}-{}.{}", stamp, n, extension),
+        };
+        let path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err).with_context(|| format!("creating {}", path.display())),
+        }
+    }
+    unreachable!()
+}
+
 pub struct Tab {
     terminal: RefCell<Terminal>,
     process: RefCell<Box<dyn Child>>,
     pty: RefCell<Box<dyn MasterPty>>,
+    recorder: SharedRecorder,
     can_close: bool,
+    /// The command the process was spawned from, kept so that it can be
+    /// spawned again, or `None` for a playback.
+    command: Option<Command>,
+    exit_behavior: ExitBehavior,
+    exit_status: Cell<Option<ExitStatus>>,
+}
+
+/// Copies everything about `command` that spawning it depends on.
+pub(crate) fn clone_command(command: &Command) -> Command {
+    let mut clone = Command::new(command.get_program());
+    clone.args(command.get_args());
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => clone.env(key, value),
+            None => clone.env_remove(key),
+        };
+    }
+    if let Some(dir) = command.get_current_dir() {
+        clone.current_dir(dir);
+    }
+    clone
 }
 
 impl Tab {
@@ -45,12 +108,16 @@ impl Tab {
     }
 
     pub fn trickle_paste(&self, text: String) -> anyhow::Result<()> {
+        // Filter the whole text up front so that nothing the filter removes
+        // can be assembled across the boundary between two chunks.
+        let text = self.terminal.borrow().sanitize_paste(&normalize_newlines(&text));
         if text.len() <= PASTE_CHUNK_SIZE {
             self.send_paste(&text)?;
         } else {
-            self.send_paste(&text[0..PASTE_CHUNK_SIZE])?;
+            let chunk = chunk_len(&text, 0);
+            self.send_paste(&text[0..chunk])?;
 
-            let paste = Arc::new(Mutex::new(Paste { text, offset: PASTE_CHUNK_SIZE }));
+            let paste = Arc::new(Mutex::new(Paste { text, offset: chunk }));
             schedule_next_paste(&paste);
         }
         Ok(())
@@ -60,6 +127,11 @@ impl Tab {
         self.terminal.borrow_mut().advance_bytes(buf, host)
     }
 
+    /// Clears the terminal for a playback that is replaying from the start.
+    pub fn rewind(&self) {
+        self.terminal.borrow_mut().full_reset();
+    }
+
     pub fn mouse_event(
         &self,
         event: MouseEvent,
@@ -68,6 +140,10 @@ impl Tab {
         self.terminal.borrow_mut().mouse_event(event, host)
     }
 
+    pub fn focus_changed(&self, focused: bool) -> anyhow::Result<()> {
+        self.terminal.borrow_mut().focus_changed(focused, &mut *self.pty.borrow_mut())
+    }
+
     pub fn key_down(&self, key: KeyCode, mods: KeyModifiers) -> anyhow::Result<()> {
         self.terminal.borrow_mut().key_down(key, mods, &mut *self.pty.borrow_mut())
     }
@@ -99,6 +175,14 @@ impl Tab {
         self.terminal.borrow_mut().get_title().to_string()
     }
 
+    pub fn get_current_dir(&self) -> Option<PathBuf> {
+        if let Some(dir) = self.terminal.borrow().get_current_dir() {
+            return Some(dir.to_path_buf());
+        }
+        let pid = self.pty.borrow().process_group_leader()?;
+        std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+    }
+
     pub fn palette(&self) -> ColorPalette {
         self.terminal.borrow().palette().clone()
     }
@@ -108,24 +192,111 @@ impl Tab {
     }
 
     pub fn can_close(&self) -> bool {
-        self.can_close || self.is_dead()
+        self.can_close || self.exit_status().map_or(false, |s| self.exit_behavior.closes(s))
     }
 
     pub fn is_dead(&self) -> bool {
-        if let Ok(None) = self.process.borrow_mut().try_wait() {
-            false
-        } else {
-            true
+        self.exit_status().is_some()
+    }
+
+    /// Whether the process exited but the tab stays open because of the
+    /// exit behavior.
+    pub fn is_held(&self) -> bool {
+        self.is_dead() && !self.can_close()
+    }
+
+    /// How the process exited, or `None` while it is still running.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        if self.exit_status.get().is_none() {
+            let status = match self.process.borrow_mut().try_wait() {
+                Ok(status) => status,
+                Err(_) => Some(ExitStatus::default()),
+            };
+            self.exit_status.set(status);
         }
+        self.exit_status.get()
     }
 
-    pub fn new(terminal: Terminal, process: Box<dyn Child>, pty: Box<dyn MasterPty>) -> Self {
+    /// Spawns the command again on a fresh pty after the process exited,
+    /// keeping what is on the screen but none of the modes that the old
+    /// process left set.
+    pub fn respawn(&self) -> anyhow::Result<()> {
+        let command = match &self.command {
+            Some(command) => clone_command(command),
+            None => bail!("there is no command to respawn"),
+        };
+        let size = self.pty.borrow().get_size()?;
+        let pair = unix::UnixPtySystem.openpty(size)?;
+        *self.process.borrow_mut() = pair.slave.spawn_command(command)?;
+        *self.pty.borrow_mut() =
+            Box::new(RecordingPty::new(pair.master, Arc::clone(&self.recorder)));
+        self.terminal.borrow_mut().reset_modes();
+        self.exit_status.set(None);
+        Ok(())
+    }
+
+    pub fn new(
+        terminal: Terminal,
+        process: Box<dyn Child>,
+        pty: Box<dyn MasterPty>,
+        command: Option<Command>,
+        exit_behavior: ExitBehavior,
+    ) -> Self {
+        let recorder = SharedRecorder::default();
+        let pty = Box::new(RecordingPty::new(pty, Arc::clone(&recorder)));
         Self {
             terminal: RefCell::new(terminal),
             process: RefCell::new(process),
             pty: RefCell::new(pty),
+            recorder,
             can_close: false,
+            command,
+            exit_behavior,
+            exit_status: Cell::new(None),
+        }
+    }
+
+    /// Exports the scrollback and the screen, or only the selection, to
+    /// `path`, or to a new file in the home directory if there is none, and
+    /// returns where it went.
+    pub fn export(
+        &self,
+        format: DumpFormat,
+        selection: bool,
+        path: Option<PathBuf>,
+    ) -> anyhow::Result<PathBuf> {
+        let terminal = self.terminal.borrow();
+        if selection && terminal.selection().is_none() {
+            bail!("there is no selection to export");
         }
+        let (path, file) = match path {
+            Some(path) => {
+                let file =
+                    File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+                (path, file)
+            }
+            None => home_file(format.extension())?,
+        };
+        let mut out = BufWriter::new(file);
+        dump::export(&terminal, format, selection, &mut out)?;
+        out.flush()?;
+        Ok(path)
+    }
+
+    /// Starts recording the session to `path` in the asciicast v2 format,
+    /// including what is typed into it only if `record_input` is set.
+    pub fn start_recording(&self, path: &Path, record_input: bool) -> anyhow::Result<()> {
+        let size = self.pty.borrow().get_size()?;
+        *self.recorder.lock().unwrap() = Some(Recorder::create(path, size, record_input)?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&self) {
+        self.recorder.lock().unwrap().take();
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.lock().unwrap().is_some()
     }
 }
 
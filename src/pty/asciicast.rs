@@ -0,0 +1,289 @@
+use crate::pty::{MasterPty, PtySize};
+use anyhow::{anyhow, bail, Context};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Writes a session in the asciicast v2 format: a JSON header line
+/// followed by one `[time, code, data]` line per event.
+pub struct Recorder {
+    out: BufWriter<File>,
+    start: Instant,
+    pending_output: Vec<u8>,
+    pending_input: Vec<u8>,
+    /// Whether the keys typed into the terminal are recorded too.  Off by
+    /// default, as they include whatever is typed at password prompts.
+    record_input: bool,
+}
+
+/// Takes the complete UTF-8 prefix of `pending` plus `data`, leaving any
+/// trailing partial character in `pending` for the next call.
+fn take_utf8(pending: &mut Vec<u8>, data: &[u8]) -> String {
+    pending.extend_from_slice(data);
+    let valid = match std::str::from_utf8(pending) {
+        Ok(_) => pending.len(),
+        Err(err) if err.error_len().is_none() => err.valid_up_to(),
+        Err(_) => {
+            let text = String::from_utf8_lossy(pending).into_owned();
+            pending.clear();
+            return text;
+        }
+    };
+    let rest = pending.split_off(valid);
+    String::from_utf8(std::mem::replace(pending, rest)).unwrap()
+}
+
+impl Recorder {
+    pub fn create(path: &Path, size: PtySize, record_input: bool) -> anyhow::Result<Self> {
+        let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs())?;
+        let header = json!({
+            "version": 2,
+            "width": size.cols,
+            "height": size.rows,
+            "timestamp": timestamp,
+            "env": {
+                "SHELL": std::env::var("SHELL").unwrap_or_default(),
+                "TERM": "xterm-256color",
+            },
+        });
+        writeln!(out, "{}", header)?;
+        out.flush()?;
+        Ok(Self {
+            out,
+            start: Instant::now(),
+            pending_output: vec![],
+            pending_input: vec![],
+            record_input,
+        })
+    }
+
+    fn event(&mut self, code: &str, data: &str) -> anyhow::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let time = self.start.elapsed().as_secs_f64();
+        writeln!(self.out, "{}", json!([time, code, data]))?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    pub fn output(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let text = take_utf8(&mut self.pending_output, data);
+        self.event("o", &text)
+    }
+
+    pub fn input(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        if !self.record_input {
+            return Ok(());
+        }
+        let text = take_utf8(&mut self.pending_input, data);
+        self.event("i", &text)
+    }
+
+    pub fn resize(&mut self, size: PtySize) -> anyhow::Result<()> {
+        self.event("r", &format!("{}x{}", size.cols, size.rows))
+    }
+}
+
+pub type SharedRecorder = Arc<Mutex<Option<Recorder>>>;
+
+/// Applies `f` to the active recorder, if any, stopping the recording if
+/// it fails so that a full disk doesn't take the terminal down with it.
+fn record<F: FnOnce(&mut Recorder) -> anyhow::Result<()>>(recorder: &SharedRecorder, f: F) {
+    let mut recorder = recorder.lock().unwrap();
+    if let Some(active) = recorder.as_mut() {
+        if let Err(err) = f(active) {
+            eprintln!("stopping recording: {}", err);
+            recorder.take();
+        }
+    }
+}
+
+/// Wraps a pty so that its output, size changes and, if the recorder
+/// asks for it, input are written to the recorder while one is active.
+pub struct RecordingPty {
+    inner: Box<dyn MasterPty>,
+    recorder: SharedRecorder,
+}
+
+impl RecordingPty {
+    pub fn new(inner: Box<dyn MasterPty>, recorder: SharedRecorder) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+impl Write for RecordingPty {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        record(&self.recorder, |r| r.input(&buf[..len]));
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl MasterPty for RecordingPty {
+    fn resize(&self, size: PtySize) -> anyhow::Result<()> {
+        self.inner.resize(size)?;
+        record(&self.recorder, |r| r.resize(size));
+        Ok(())
+    }
+
+    fn get_size(&self) -> anyhow::Result<PtySize> {
+        self.inner.get_size()
+    }
+
+    fn try_clone_reader(&self) -> anyhow::Result<Box<dyn Read + Send>> {
+        let inner = self.inner.try_clone_reader()?;
+        Ok(Box::new(RecordingReader { inner, recorder: Arc::clone(&self.recorder) }))
+    }
+
+    fn process_group_leader(&self) -> Option<libc::pid_t> {
+        self.inner.process_group_leader()
+    }
+}
+
+struct RecordingReader {
+    inner: Box<dyn Read + Send>,
+    recorder: SharedRecorder,
+}
+
+impl Read for RecordingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        record(&self.recorder, |r| r.output(&buf[..len]));
+        Ok(len)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    Output(String),
+    Input(String),
+    Resize { cols: u16, rows: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub time: f64,
+    pub kind: EventKind,
+}
+
+/// A recorded session loaded from an asciicast v2 file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cast {
+    pub width: u16,
+    pub height: u16,
+    pub events: Vec<Event>,
+}
+
+impl Cast {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let header: Value =
+            serde_json::from_str(lines.next().ok_or_else(|| anyhow!("empty cast"))?)?;
+        if header["version"] != 2 {
+            bail!("unsupported asciicast version {}", header["version"]);
+        }
+        let dimension = |name: &str| {
+            header[name].as_u64().ok_or_else(|| anyhow!("header has no {}", name)).map(|v| v as u16)
+        };
+        let width = dimension("width")?;
+        let height = dimension("height")?;
+
+        let mut events = vec![];
+        for line in lines {
+            let (time, code, data): (f64, String, String) = serde_json::from_str(line)?;
+            let kind = match code.as_str() {
+                "o" => EventKind::Output(data),
+                "i" => EventKind::Input(data),
+                "r" => {
+                    let mut dims = data.splitn(2, 'x').map(str::parse::<u16>);
+                    match (dims.next(), dims.next()) {
+                        (Some(Ok(cols)), Some(Ok(rows))) => EventKind::Resize { cols, rows },
+                        _ => bail!("invalid resize event {:?}", data),
+                    }
+                }
+                _ => continue,
+            };
+            events.push(Event { time, kind });
+        }
+
+        Ok(Self { width, height, events })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_utf8() {
+        let mut pending = vec![];
+        let bytes = "é".as_bytes();
+        assert_eq!(take_utf8(&mut pending, &bytes[..1]), "");
+        assert_eq!(take_utf8(&mut pending, &bytes[1..]), "é");
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn parse() {
+        let text = concat!(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n",
+            "[0.5, \"o\", \"hello\\r\\n\"]\n",
+            "[1.0, \"i\", \"q\"]\n",
+            "[1.5, \"r\", \"100x30\"]\n",
+            "[2.0, \"m\", \"marker\"]\n",
+        );
+        let cast = Cast::parse(text).unwrap();
+        assert_eq!(cast.width, 80);
+        assert_eq!(cast.height, 24);
+        assert_eq!(
+            cast.events,
+            vec![
+                Event { time: 0.5, kind: EventKind::Output("hello\r\n".to_string()) },
+                Event { time: 1.0, kind: EventKind::Input("q".to_string()) },
+                Event { time: 1.5, kind: EventKind::Resize { cols: 100, rows: 30 } },
+            ]
+        );
+    }
+
+    #[test]
+    fn input_is_opt_in() {
+        let size = PtySize::default();
+        for &record_input in &[false, true] {
+            let path = std::env::temp_dir().join(format!(
+                "miro-input-{}-{}.cast",
+                std::process::id(),
+                record_input
+            ));
+            let mut recorder = Recorder::create(&path, size, record_input).unwrap();
+            recorder.output(b"Password: ").unwrap();
+            recorder.input(b"hunter2\r").unwrap();
+            drop(recorder);
+
+            let cast = Cast::load(&path).unwrap();
+            std::fs::remove_file(&path).ok();
+            let inputs = cast.events.iter().filter(|e| matches!(e.kind, EventKind::Input(_)));
+            assert_eq!(inputs.count(), record_input as usize);
+        }
+    }
+
+    #[test]
+    fn reject_version_one() {
+        assert!(Cast::parse("{\"version\": 1, \"width\": 80, \"height\": 24}").is_err());
+    }
+}
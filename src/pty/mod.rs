@@ -3,6 +3,8 @@ use serde_derive::*;
 use std::io::Result as IoResult;
 use std::process::Command;
 
+pub mod asciicast;
+pub mod playback;
 pub mod unix;
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -22,12 +24,33 @@ impl Default for PtySize {
     }
 }
 
+/// Parses a `COLSxROWS` geometry such as `120x40`.
+impl std::str::FromStr for PtySize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut dims = s.splitn(2, 'x').map(str::parse::<u16>);
+        match (dims.next(), dims.next()) {
+            (Some(Ok(cols)), Some(Ok(rows))) if cols > 0 && rows > 0 => {
+                Ok(PtySize { rows, cols, ..PtySize::default() })
+            }
+            _ => Err(anyhow!("invalid geometry {:?}, expected COLSxROWS", s)),
+        }
+    }
+}
+
 pub trait MasterPty: std::io::Write {
     fn resize(&self, size: PtySize) -> anyhow::Result<()>;
 
     fn get_size(&self) -> anyhow::Result<PtySize>;
 
     fn try_clone_reader(&self) -> anyhow::Result<Box<dyn std::io::Read + Send>>;
+
+    /// The foreground process group of the terminal, when the pty can
+    /// tell it.
+    fn process_group_leader(&self) -> Option<libc::pid_t> {
+        None
+    }
 }
 
 pub trait Child: std::fmt::Debug {
@@ -42,14 +65,49 @@ pub trait SlavePty {
     fn spawn_command(&self, cmd: Command) -> anyhow::Result<Box<dyn Child>>;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct ExitStatus {
-    successful: bool,
+    code: Option<i32>,
+    signal: Option<i32>,
+}
+
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    pub fn code(&self) -> Option<i32> {
+        self.code
+    }
+
+    /// The signal that killed the process, if it did not exit by itself.
+    pub fn signal(&self) -> Option<i32> {
+        self.signal
+    }
+}
+
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exited with code {}", code),
+            (None, Some(signal)) => {
+                let name = unsafe { libc::strsignal(signal) };
+                if name.is_null() {
+                    write!(f, "was killed by signal {}", signal)
+                } else {
+                    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+                    write!(f, "was killed by signal {} ({})", signal, name.to_string_lossy())
+                }
+            }
+            (None, None) => write!(f, "exited"),
+        }
+    }
 }
 
 impl From<std::process::ExitStatus> for ExitStatus {
     fn from(status: std::process::ExitStatus) -> ExitStatus {
-        ExitStatus { successful: status.success() }
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus { code: status.code(), signal: status.signal() }
     }
 }
 
@@ -96,3 +154,25 @@ pub fn get_shell() -> anyhow::Result<String> {
         }
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn geometry() {
+        let size: PtySize = "120x40".parse().unwrap();
+        assert_eq!(size, PtySize { rows: 40, cols: 120, ..PtySize::default() });
+        assert!("120".parse::<PtySize>().is_err());
+        assert!("0x40".parse::<PtySize>().is_err());
+        assert!("120x40x2".parse::<PtySize>().is_err());
+    }
+
+    #[test]
+    fn exit_status() {
+        assert_eq!(ExitStatus { code: Some(2), signal: None }.to_string(), "exited with code 2");
+        let killed = ExitStatus { code: None, signal: Some(libc::SIGKILL) };
+        assert!(!killed.success());
+        assert!(killed.to_string().starts_with("was killed by signal 9"));
+    }
+}
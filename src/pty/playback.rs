@@ -0,0 +1,257 @@
+use crate::pty::asciicast::{Cast, EventKind};
+use crate::pty::{Child, ExitStatus, MasterPty, PtySize};
+use anyhow::anyhow;
+use std::io::{Read, Result as IoResult, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const SEEK_SECONDS: f64 = 5.0;
+const MIN_SPEED: f64 = 0.125;
+const MAX_SPEED: f64 = 64.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Control {
+    TogglePause,
+    Seek(f64),
+    Speed(f64),
+    Quit,
+}
+
+/// What the playback thread hands to the reader: either recorded output
+/// or a request to reset the terminal before replaying from the start.
+enum Output {
+    Data(Vec<u8>),
+    Rewind,
+}
+
+/// Resets the terminal the playback is shown in.  The reset goes around
+/// the byte stream so that nothing in the recording can trigger it.
+pub type Rewind = Box<dyn FnMut() + Send>;
+
+impl Control {
+    /// Maps the bytes produced by a key press to a playback control.
+    fn from_input(input: &[u8]) -> Option<Control> {
+        match input {
+            b" " => Some(Control::TogglePause),
+            b"\x1b[C" | b"\x1bOC" => Some(Control::Seek(SEEK_SECONDS)),
+            b"\x1b[D" | b"\x1bOD" => Some(Control::Seek(-SEEK_SECONDS)),
+            b"+" | b"=" => Some(Control::Speed(2.0)),
+            b"-" => Some(Control::Speed(0.5)),
+            b"q" => Some(Control::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Stands in for a pty, feeding the output of a recorded session to the
+/// terminal on the original schedule. Keys typed into the terminal control
+/// the playback: space pauses, the arrow keys seek and `+`/`-` change the
+/// speed.
+pub struct PlaybackPty {
+    size: Mutex<PtySize>,
+    controls: Sender<Control>,
+    output: Mutex<Option<(Receiver<Output>, Rewind)>>,
+}
+
+/// The stand-in for the child process, which exits when the user quits
+/// the playback.
+#[derive(Debug)]
+pub struct PlaybackChild {
+    done: Arc<AtomicBool>,
+    controls: Sender<Control>,
+}
+
+pub fn spawn_playback(cast: Cast, speed: f64, rewind: Rewind) -> (PlaybackPty, PlaybackChild) {
+    let (controls, control_rx) = channel();
+    let (output_tx, output) = channel();
+    let done = Arc::new(AtomicBool::new(false));
+
+    let size = PtySize { rows: cast.height, cols: cast.width, ..PtySize::default() };
+    let thread_done = Arc::clone(&done);
+    std::thread::spawn(move || {
+        play(&cast, speed, &output_tx, &control_rx);
+        thread_done.store(true, Ordering::SeqCst);
+    });
+
+    (
+        PlaybackPty {
+            size: Mutex::new(size),
+            controls: controls.clone(),
+            output: Mutex::new(Some((output, rewind))),
+        },
+        PlaybackChild { done, controls },
+    )
+}
+
+/// The XTWINOPS sequence that asks the window to resize to the given
+/// number of cells, which is how a recorded resize reaches the window.
+fn resize_sequence(cols: u16, rows: u16) -> Vec<u8> {
+    format!("\x1b[8;{};{}t", rows, cols).into_bytes()
+}
+
+fn play(cast: &Cast, speed: f64, output: &Sender<Output>, controls: &Receiver<Control>) {
+    let mut speed = speed.max(MIN_SPEED).min(MAX_SPEED);
+    let mut paused = false;
+    let mut position = 0.0;
+    let mut next = 0;
+    let mut last_tick = Instant::now();
+
+    loop {
+        // Everything due by now is sent in one go, which also takes care
+        // of catching up after a seek.
+        let mut buf = vec![];
+        while next < cast.events.len() && cast.events[next].time <= position {
+            match &cast.events[next].kind {
+                EventKind::Output(data) => buf.extend_from_slice(data.as_bytes()),
+                EventKind::Resize { cols, rows } => buf.extend(resize_sequence(*cols, *rows)),
+                EventKind::Input(_) => {}
+            }
+            next += 1;
+        }
+        if !buf.is_empty() && output.send(Output::Data(buf)).is_err() {
+            return;
+        }
+
+        let control = match cast.events.get(next) {
+            Some(event) if !paused => {
+                let wait = Duration::from_secs_f64((event.time - position).max(0.0) / speed);
+                controls.recv_timeout(wait)
+            }
+            _ => controls.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        let now = Instant::now();
+        if !paused {
+            position += (now - last_tick).as_secs_f64() * speed;
+        }
+        last_tick = now;
+
+        match control {
+            Ok(Control::TogglePause) => paused = !paused,
+            Ok(Control::Seek(delta)) => {
+                let target = (position + delta).max(0.0);
+                if target < position {
+                    // Rewinding replays everything up to the target from
+                    // a freshly reset terminal at the original size.
+                    let resize = resize_sequence(cast.width, cast.height);
+                    if output.send(Output::Rewind).is_err()
+                        || output.send(Output::Data(resize)).is_err()
+                    {
+                        return;
+                    }
+                    next = 0;
+                }
+                position = target;
+            }
+            Ok(Control::Speed(factor)) => speed = (speed * factor).max(MIN_SPEED).min(MAX_SPEED),
+            Ok(Control::Quit) | Err(RecvTimeoutError::Disconnected) => return,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+    }
+}
+
+impl Write for PlaybackPty {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        if let Some(control) = Control::from_input(buf) {
+            self.controls.send(control).ok();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+impl MasterPty for PlaybackPty {
+    fn resize(&self, size: PtySize) -> anyhow::Result<()> {
+        *self.size.lock().unwrap() = size;
+        Ok(())
+    }
+
+    fn get_size(&self) -> anyhow::Result<PtySize> {
+        Ok(*self.size.lock().unwrap())
+    }
+
+    fn try_clone_reader(&self) -> anyhow::Result<Box<dyn Read + Send>> {
+        let (output, rewind) = self
+            .output
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| anyhow!("the playback output has already been taken"))?;
+        Ok(Box::new(PlaybackReader { output, rewind, buf: vec![], offset: 0 }))
+    }
+}
+
+struct PlaybackReader {
+    output: Receiver<Output>,
+    rewind: Rewind,
+    buf: Vec<u8>,
+    offset: usize,
+}
+
+impl Read for PlaybackReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        // Everything read so far has been handed to the terminal by the
+        // time the next read comes in, so a rewind lands between the
+        // output before and after it.
+        while self.offset >= self.buf.len() {
+            match self.output.recv() {
+                Ok(Output::Data(data)) => {
+                    self.buf = data;
+                    self.offset = 0;
+                }
+                Ok(Output::Rewind) => (self.rewind)(),
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.buf.len() - self.offset);
+        buf[..len].copy_from_slice(&self.buf[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+impl Child for PlaybackChild {
+    fn try_wait(&mut self) -> IoResult<Option<ExitStatus>> {
+        if self.done.load(Ordering::SeqCst) {
+            Ok(Some(ExitStatus { code: Some(0), signal: None }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn kill(&mut self) -> IoResult<()> {
+        self.controls.send(Control::Quit).ok();
+        Ok(())
+    }
+
+    fn wait(&mut self) -> IoResult<ExitStatus> {
+        while !self.done.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Ok(ExitStatus { code: Some(0), signal: None })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resize() {
+        let cast = Cast::parse(concat!(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n",
+            "[0.0, \"o\", \"a\"]\n",
+            "[0.0, \"r\", \"100x30\"]\n",
+        ))
+        .unwrap();
+        let (output, received) = channel();
+        let (_, controls) = channel();
+        play(&cast, 1.0, &output, &controls);
+        assert_eq!(received.try_recv().unwrap(), b"a\x1b[8;30;100t".to_vec());
+    }
+}
@@ -151,6 +151,13 @@ impl MasterPty for UnixMasterPty {
         };
         Ok(Box::new(fd))
     }
+
+    fn process_group_leader(&self) -> Option<libc::pid_t> {
+        match unsafe { libc::tcgetpgrp(self.fd.as_raw_fd()) } {
+            pid if pid > 0 => Some(pid),
+            _ => None,
+        }
+    }
 }
 
 impl io::Write for UnixMasterPty {
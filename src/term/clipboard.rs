@@ -1,50 +1,110 @@
-use anyhow::anyhow;
-use clipboard::{ClipboardContext, ClipboardProvider};
-use std::sync::Mutex;
+use crate::core::escape::osc::Selection;
+use crate::core::escape::OperatingSystemCommand;
+use anyhow::ensure;
+use serde_derive::*;
 
-pub trait Clipboard {
-    fn get_contents(&self) -> anyhow::Result<String>;
-    fn set_contents(&self, data: Option<String>) -> anyhow::Result<()>;
-}
-
-pub struct SystemClipboard {
-    inner: Mutex<Inner>,
+/// Which of the system buffers a clipboard operation targets.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardSelection {
+    Clipboard,
+    Primary,
 }
 
-struct Inner {
-    clipboard: Option<ClipboardContext>,
+impl ClipboardSelection {
+    /// Maps an OSC 52 selection to the buffers it refers to. Everything
+    /// other than the clipboard, including the cut buffers, is treated as
+    /// the primary selection.
+    pub fn targets(selection: Selection) -> Vec<ClipboardSelection> {
+        let mut targets = vec![];
+        if selection.contains(Selection::CLIPBOARD) {
+            targets.push(ClipboardSelection::Clipboard);
+        }
+        if !(selection - Selection::CLIPBOARD).is_empty() {
+            targets.push(ClipboardSelection::Primary);
+        }
+        targets
+    }
 }
 
-impl Inner {
-    fn new() -> Self {
-        Self { clipboard: None }
-    }
+/// Receives the contents of a selection once they are available.
+pub type ClipboardContents = Box<dyn FnOnce(anyhow::Result<String>)>;
 
-    fn clipboard(&mut self) -> anyhow::Result<&mut ClipboardContext> {
-        if self.clipboard.is_none() {
-            self.clipboard = Some(ClipboardContext::new().map_err(|e| anyhow!("{}", e))?);
-        }
-        Ok(self.clipboard.as_mut().unwrap())
-    }
+pub trait Clipboard {
+    /// Passes the contents of `selection` to `done`, which may run later
+    /// if the owner of the selection has to be asked for them.
+    fn get_contents(&self, selection: ClipboardSelection, done: ClipboardContents);
+    fn set_contents(
+        &self,
+        selection: ClipboardSelection,
+        data: Option<String>,
+    ) -> anyhow::Result<()>;
 }
 
-impl SystemClipboard {
-    pub fn new() -> Self {
-        Self { inner: Mutex::new(Inner::new()) }
-    }
+/// A clipboard operation requested by the program running in the
+/// terminal via OSC 52.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardRequest {
+    Set { selection: Selection, data: Option<String> },
+    Query { selection: Selection },
 }
 
-impl Clipboard for SystemClipboard {
-    fn get_contents(&self) -> anyhow::Result<String> {
-        let mut inner = self.inner.lock().unwrap();
-        inner.clipboard()?.get_contents().map_err(|e| anyhow!("{}", e))
+impl ClipboardRequest {
+    pub fn selection(&self) -> Selection {
+        match self {
+            ClipboardRequest::Set { selection, .. } | ClipboardRequest::Query { selection } => {
+                *selection
+            }
+        }
     }
 
-    fn set_contents(&self, data: Option<String>) -> anyhow::Result<()> {
-        let mut inner = self.inner.lock().unwrap();
-        let clip = inner.clipboard()?;
-        clip.set_contents(data.unwrap_or_else(|| "".into())).map_err(|e| anyhow!("{}", e))?;
+    pub fn is_query(&self) -> bool {
+        match self {
+            ClipboardRequest::Query { .. } => true,
+            ClipboardRequest::Set { .. } => false,
+        }
+    }
 
-        clip.get_contents().map(|_| ()).map_err(|e| anyhow!("{}", e))
+    /// Applies the request to `clipboard`. Queries are answered by passing
+    /// the sequence carrying the base64 encoded contents to `reply` once
+    /// they arrive; contents larger than `max_bytes` are neither stored nor
+    /// reported.
+    pub fn perform(
+        &self,
+        clipboard: &dyn Clipboard,
+        max_bytes: usize,
+        reply: Box<dyn FnOnce(String)>,
+    ) -> anyhow::Result<()> {
+        match self {
+            ClipboardRequest::Set { selection, data } => {
+                let len = data.as_ref().map(String::len).unwrap_or(0);
+                ensure!(len <= max_bytes, "OSC 52 data of {} bytes exceeds the limit", len);
+                for target in ClipboardSelection::targets(*selection) {
+                    clipboard.set_contents(target, data.clone())?;
+                }
+                Ok(())
+            }
+            ClipboardRequest::Query { selection } => {
+                let target = ClipboardSelection::targets(*selection)
+                    .into_iter()
+                    .next()
+                    .unwrap_or(ClipboardSelection::Clipboard);
+                let selection = *selection;
+                clipboard.get_contents(
+                    target,
+                    Box::new(move |contents| {
+                        if let Ok(mut contents) = contents {
+                            if contents.len() > max_bytes {
+                                contents.clear();
+                            }
+                            reply(
+                                OperatingSystemCommand::SetSelection(selection, contents)
+                                    .to_string(),
+                            );
+                        }
+                    }),
+                );
+                Ok(())
+            }
+        }
     }
 }
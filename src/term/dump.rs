@@ -0,0 +1,511 @@
+use super::*;
+use crate::core::cellcluster::CellCluster;
+use crate::core::color::{ColorAttribute, ColorSpec};
+use crate::core::escape::csi::{Sgr, CSI};
+use crate::core::escape::OperatingSystemCommand;
+use crate::term::color::ColorPalette;
+use anyhow::bail;
+use serde_derive::*;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::io::Write as IoWrite;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DumpFormat {
+    /// The text of each line, without attributes.
+    Text,
+    /// The text of each line with SGR sequences and hyperlinks, suitable
+    /// for `cat`ing into another terminal.
+    Ansi,
+    /// A JSON document describing the screen, the cursor and the runs of
+    /// cells sharing the same attributes on each line.
+    Json,
+    /// A self-contained HTML page, with the colors resolved through the
+    /// palette of the terminal.
+    Html,
+}
+
+impl std::str::FromStr for DumpFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "text" => Ok(DumpFormat::Text),
+            "ansi" => Ok(DumpFormat::Ansi),
+            "json" => Ok(DumpFormat::Json),
+            "html" => Ok(DumpFormat::Html),
+            _ => bail!("unknown dump format {:?}", s),
+        }
+    }
+}
+
+impl DumpFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            DumpFormat::Text => "txt",
+            DumpFormat::Ansi => "ans",
+            DumpFormat::Json => "json",
+            DumpFormat::Html => "html",
+        }
+    }
+}
+
+/// Renders the visible screen, preceded by the scrollback when
+/// `scrollback` is set, in the requested format.
+pub fn dump(state: &TerminalState, format: DumpFormat, scrollback: bool) -> String {
+    let screen = state.screen();
+    let end = screen.num_lines();
+    let first_visible = end - screen.physical_rows;
+    let start = if scrollback { 0 } else { first_visible };
+
+    match format {
+        DumpFormat::Json => {
+            let lines =
+                |range| screen.lines(range).map(|line| line_to_json(&line)).collect::<Vec<_>>();
+            let cursor = state.cursor_pos();
+            let doc = json!({
+                "rows": screen.physical_rows,
+                "cols": screen.physical_cols,
+                "title": state.get_title(),
+                "cursor": { "x": cursor.x, "y": cursor.y },
+                "scrollback": lines(start..first_visible),
+                "screen": lines(first_visible..end),
+            });
+            format!("{}\n", serde_json::to_string_pretty(&doc).unwrap())
+        }
+        _ => {
+            let mut out = vec![];
+            render(state, format, screen.lines(start..end), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        }
+    }
+}
+
+/// Writes the scrollback and the screen, or only the selection when
+/// `selection` is set, to `out` with the rows that were wrapped joined back
+/// into the lines they came from.  The lines are assembled and written one
+/// at a time so that a long scrollback is never held in memory twice.
+pub fn export(
+    state: &TerminalState,
+    format: DumpFormat,
+    selection: bool,
+    out: &mut dyn IoWrite,
+) -> anyhow::Result<()> {
+    let screen = state.screen();
+    let mut rows: Box<dyn Iterator<Item = (PhysRowIndex, Range<usize>)> + '_> = if selection {
+        let sel = match state.selection() {
+            Some(sel) => sel,
+            None => bail!("there is no selection to export"),
+        };
+        Box::new(
+            sel.rows().map(move |y| (screen.scrollback_or_visible_row(y), sel.cols_for_row(y))),
+        )
+    } else {
+        Box::new((0..screen.num_lines()).map(|idx| (idx, 0..usize::max_value())))
+    };
+
+    let mut cells = vec![];
+    let lines = std::iter::from_fn(move || {
+        for (idx, cols) in &mut rows {
+            let line = screen.line(idx);
+            let len = line.cells().len();
+            let row = &line.cells()[cols.start.min(len)..cols.end.min(len)];
+            cells.extend_from_slice(row);
+            match row.last() {
+                Some(last) if last.attrs().wrapped() => {
+                    let attrs = last.attrs().clone().set_wrapped(false).clone();
+                    let joined = Cell::new_grapheme(last.str(), attrs);
+                    *cells.last_mut().unwrap() = joined;
+                }
+                _ => return Some(Cow::Owned(Line::from_cells(std::mem::take(&mut cells)))),
+            }
+        }
+        if cells.is_empty() {
+            None
+        } else {
+            Some(Cow::Owned(Line::from_cells(std::mem::take(&mut cells))))
+        }
+    });
+
+    render(state, format, lines, out)?;
+    Ok(())
+}
+
+fn render<'a>(
+    state: &TerminalState,
+    format: DumpFormat,
+    lines: impl Iterator<Item = Cow<'a, Line>>,
+    out: &mut dyn IoWrite,
+) -> std::io::Result<()> {
+    match format {
+        DumpFormat::Text => {
+            for line in lines {
+                writeln!(out, "{}", line.as_str().trim_end())?;
+            }
+        }
+        DumpFormat::Ansi => {
+            for line in lines {
+                out.write_all(line_to_ansi(&line).as_bytes())?;
+            }
+        }
+        DumpFormat::Json => {
+            write!(out, "{{\n  \"lines\": [")?;
+            for (idx, line) in lines.enumerate() {
+                let separator = if idx == 0 { "" } else { "," };
+                write!(out, "{}\n    {}", separator, line_to_json(&line))?;
+            }
+            writeln!(out, "\n  ]\n}}")?;
+        }
+        DumpFormat::Html => html(state, lines, out)?,
+    }
+    Ok(())
+}
+
+/// Groups the cells of `line` into runs with the same attributes, leaving
+/// out the unstyled blanks at the end of the line.
+fn clusters(line: &Line) -> Vec<CellCluster> {
+    let mut clusters = line.cluster();
+    if let Some(last) = clusters.last_mut() {
+        if last.attrs == CellAttributes::default() {
+            let len = last.text.trim_end().len();
+            last.text.truncate(len);
+            last.byte_to_cell_idx.truncate(len);
+            if len == 0 {
+                clusters.pop();
+            }
+        }
+    }
+    clusters
+}
+
+fn color_spec(color: ColorAttribute) -> ColorSpec {
+    match color {
+        ColorAttribute::TrueColorWithPaletteFallback(rgb, _)
+        | ColorAttribute::TrueColorWithDefaultFallback(rgb) => ColorSpec::TrueColor(rgb),
+        ColorAttribute::PaletteIndex(idx) => ColorSpec::PaletteIndex(idx),
+        ColorAttribute::Default => ColorSpec::Default,
+    }
+}
+
+/// The SGR sequences that reset the pen and then select `attrs`.
+fn sgr(attrs: &CellAttributes) -> String {
+    let mut sgrs = vec![Sgr::Reset];
+    if attrs.intensity() != Intensity::Normal {
+        sgrs.push(Sgr::Intensity(attrs.intensity()));
+    }
+    if attrs.underline() != Underline::None {
+        sgrs.push(Sgr::Underline(attrs.underline()));
+    }
+    if attrs.blink() != Blink::None {
+        sgrs.push(Sgr::Blink(attrs.blink()));
+    }
+    if attrs.italic() {
+        sgrs.push(Sgr::Italic(true));
+    }
+    if attrs.reverse() {
+        sgrs.push(Sgr::Inverse(true));
+    }
+    if attrs.strikethrough() {
+        sgrs.push(Sgr::StrikeThrough(true));
+    }
+    if attrs.invisible() {
+        sgrs.push(Sgr::Invisible(true));
+    }
+    if attrs.overline() {
+        sgrs.push(Sgr::Overline(true));
+    }
+    if attrs.foreground != ColorAttribute::Default {
+        sgrs.push(Sgr::Foreground(color_spec(attrs.foreground)));
+    }
+    if attrs.background != ColorAttribute::Default {
+        sgrs.push(Sgr::Background(color_spec(attrs.background)));
+    }
+    if attrs.underline_color != ColorAttribute::Default {
+        sgrs.push(Sgr::UnderlineColor(color_spec(attrs.underline_color)));
+    }
+    sgrs.into_iter().map(|sgr| CSI::Sgr(sgr).to_string()).collect()
+}
+
+fn line_to_ansi(line: &Line) -> String {
+    let mut out = String::new();
+    let mut styled = false;
+    let mut link = None;
+
+    for cluster in clusters(line) {
+        if cluster.attrs.hyperlink != link {
+            link = cluster.attrs.hyperlink.clone();
+            let osc = OperatingSystemCommand::SetHyperlink(link.as_ref().map(|l| (**l).clone()));
+            out.push_str(&osc.to_string());
+        }
+        if cluster.attrs.clone_sgr_only() != CellAttributes::default() {
+            out.push_str(&sgr(&cluster.attrs));
+            styled = true;
+        } else if styled {
+            out.push_str(&CSI::Sgr(Sgr::Reset).to_string());
+            styled = false;
+        }
+        out.push_str(&cluster.text);
+    }
+
+    if link.is_some() {
+        out.push_str(&OperatingSystemCommand::SetHyperlink(None).to_string());
+    }
+    if styled {
+        out.push_str(&CSI::Sgr(Sgr::Reset).to_string());
+    }
+    out.push('\n');
+    out
+}
+
+fn color_to_json(color: ColorAttribute) -> Value {
+    match color_spec(color) {
+        ColorSpec::Default => json!("default"),
+        ColorSpec::PaletteIndex(idx) => json!(idx),
+        ColorSpec::TrueColor(rgb) => json!(rgb.to_rgb_string()),
+    }
+}
+
+fn line_to_json(line: &Line) -> Value {
+    let runs: Vec<Value> = clusters(line)
+        .into_iter()
+        .map(|cluster| {
+            let attrs = &cluster.attrs;
+            json!({
+                "col": cluster.byte_to_cell_idx[0],
+                "text": cluster.text,
+                "foreground": color_to_json(attrs.foreground),
+                "background": color_to_json(attrs.background),
+                "intensity": attrs.intensity(),
+                "underline": attrs.underline(),
+                "blink": attrs.blink(),
+                "italic": attrs.italic(),
+                "reverse": attrs.reverse(),
+                "strikethrough": attrs.strikethrough(),
+                "invisible": attrs.invisible(),
+                "hyperlink": attrs.hyperlink.as_ref().map(|link| link.uri()),
+            })
+        })
+        .collect();
+    json!({ "text": line.as_str().trim_end(), "runs": runs })
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether `uri` is safe to link to from an exported page.  The links come
+/// from the programs running in the terminal, so anything that could run
+/// script in the browser, such as `javascript:`, is written as plain text.
+fn is_safe_link(uri: &str) -> bool {
+    match uri.find(':') {
+        Some(end) => {
+            let scheme = uri[..end].to_ascii_lowercase();
+            matches!(scheme.as_str(), "http" | "https" | "mailto" | "file")
+        }
+        None => false,
+    }
+}
+
+/// The CSS for the cells with `attrs`, leaving out whatever matches the
+/// defaults of the page.
+fn css(palette: &ColorPalette, attrs: &CellAttributes) -> String {
+    let mut fg = palette.resolve_fg(attrs.foreground);
+    let mut bg = palette.resolve_bg(attrs.background);
+    if attrs.reverse() {
+        std::mem::swap(&mut fg, &mut bg);
+    }
+    if attrs.invisible() {
+        fg = bg;
+    }
+
+    let mut style = String::new();
+    if fg != palette.foreground {
+        write!(style, "color: {}; ", fg.to_rgb_string()).unwrap();
+    }
+    if bg != palette.background {
+        write!(style, "background: {}; ", bg.to_rgb_string()).unwrap();
+    }
+    match attrs.intensity() {
+        Intensity::Normal => {}
+        Intensity::Bold => style.push_str("font-weight: bold; "),
+        Intensity::Half => style.push_str("opacity: 0.5; "),
+    }
+    if attrs.italic() {
+        style.push_str("font-style: italic; ");
+    }
+
+    let mut lines = vec![];
+    if attrs.underline() != Underline::None {
+        lines.push("underline");
+    }
+    if attrs.strikethrough() {
+        lines.push("line-through");
+    }
+    if attrs.overline() {
+        lines.push("overline");
+    }
+    if !lines.is_empty() {
+        write!(style, "text-decoration: {}; ", lines.join(" ")).unwrap();
+        let decoration_style = match attrs.underline() {
+            Underline::Double => Some("double"),
+            Underline::Curly => Some("wavy"),
+            Underline::Dotted => Some("dotted"),
+            Underline::Dashed => Some("dashed"),
+            Underline::None | Underline::Single => None,
+        };
+        if let Some(decoration_style) = decoration_style {
+            write!(style, "text-decoration-style: {}; ", decoration_style).unwrap();
+        }
+        if attrs.underline_color != ColorAttribute::Default {
+            let color = palette.resolve_fg(attrs.underline_color);
+            write!(style, "text-decoration-color: {}; ", color.to_rgb_string()).unwrap();
+        }
+    }
+    style.truncate(style.trim_end().len());
+    style
+}
+
+fn html<'a>(
+    state: &TerminalState,
+    lines: impl Iterator<Item = Cow<'a, Line>>,
+    out: &mut dyn IoWrite,
+) -> std::io::Result<()> {
+    let palette = state.palette();
+    writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>\n</head>", escape_html(state.get_title()))?;
+    writeln!(
+        out,
+        "<body style=\"margin: 0; color: {}; background: {};\">",
+        palette.foreground.to_rgb_string(),
+        palette.background.to_rgb_string()
+    )?;
+    write!(out, "<pre style=\"margin: 0; padding: 1em; font-family: monospace;\">")?;
+
+    for line in lines {
+        let mut row = String::new();
+        for cluster in clusters(&line) {
+            let link = cluster.attrs.hyperlink.as_ref().filter(|link| is_safe_link(link.uri()));
+            if let Some(link) = link {
+                write!(row, "<a href=\"{}\" style=\"color: inherit;\">", escape_html(link.uri()))
+                    .unwrap();
+            }
+            let style = css(palette, &cluster.attrs);
+            if style.is_empty() {
+                row.push_str(&escape_html(&cluster.text));
+            } else {
+                write!(row, "<span style=\"{}\">{}</span>", style, escape_html(&cluster.text))
+                    .unwrap();
+            }
+            if link.is_some() {
+                row.push_str("</a>");
+            }
+        }
+        writeln!(out, "{}", row)?;
+    }
+
+    writeln!(out, "</pre>\n</body>\n</html>")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn state() -> TerminalState {
+        let mut state = TerminalState::new(2, 10, 0, 0, 0, vec![]);
+        let mut bold = CellAttributes::default();
+        bold.set_intensity(Intensity::Bold).set_foreground(ColorAttribute::PaletteIndex(1));
+        let line = state.screen_mut().line_mut(0);
+        line.set_cell(0, Cell::new('a', CellAttributes::default()));
+        line.set_cell(1, Cell::new('b', bold.clone()));
+        line.set_cell(2, Cell::new(' ', bold));
+        state
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(dump(&state(), DumpFormat::Text, false), "ab\n\n");
+    }
+
+    #[test]
+    fn ansi() {
+        assert_eq!(dump(&state(), DumpFormat::Ansi, false), "a\x1b[0m\x1b[1m\x1b[31mb \x1b[0m\n\n");
+    }
+
+    #[test]
+    fn json() {
+        let doc: Value = serde_json::from_str(&dump(&state(), DumpFormat::Json, false)).unwrap();
+        let runs = &doc["screen"][0]["runs"];
+        assert_eq!(runs[1]["col"], 1);
+        assert_eq!(runs[1]["text"], "b ");
+        assert_eq!(runs[1]["intensity"], "Bold");
+        assert_eq!(runs[1]["foreground"], 1);
+        assert_eq!(doc["screen"][1]["runs"], json!([]));
+    }
+
+    #[test]
+    fn html() {
+        let html = dump(&state(), DumpFormat::Html, false);
+        assert!(html.contains("<pre style=\"margin: 0; padding: 1em; font-family: monospace;\">"));
+        assert!(html.contains("a<span style=\"color: #cc5555; font-weight: bold;\">b </span>\n\n"));
+        assert_eq!(escape_html("<a href=\"x\">&"), "&lt;a href=&quot;x&quot;&gt;&amp;");
+    }
+
+    #[test]
+    fn html_links() {
+        let mut state = TerminalState::new(1, 10, 0, 0, 0, vec![]);
+        let line = state.screen_mut().line_mut(0);
+        for (idx, uri) in
+            ["https://example.com", "javascript:alert(1)", "JavaScript:x"].iter().enumerate()
+        {
+            let mut attrs = CellAttributes::default();
+            attrs.set_hyperlink(Some(Arc::new(Hyperlink::new_with_params(
+                uri.to_string(),
+                HashMap::new(),
+            ))));
+            line.set_cell(idx, Cell::new('x', attrs));
+        }
+
+        let html = dump(&state, DumpFormat::Html, false);
+        assert!(html.contains("<a href=\"https://example.com\" style=\"color: inherit;\">x</a>"));
+        assert!(!html.to_lowercase().contains("javascript"));
+        assert!(html.contains("</a>xx\n"));
+    }
+
+    #[test]
+    fn export_joins_wrapped_lines() {
+        let mut state = TerminalState::new(2, 3, 0, 0, 0, vec![]);
+        let mut wrapped = CellAttributes::default();
+        wrapped.set_wrapped(true);
+        let screen = state.screen_mut();
+        screen.line_mut(0).set_cell(0, Cell::new('a', CellAttributes::default()));
+        screen.line_mut(0).set_cell(1, Cell::new('b', CellAttributes::default()));
+        screen.line_mut(0).set_cell(2, Cell::new('c', wrapped));
+        screen.line_mut(1).set_cell(0, Cell::new('d', CellAttributes::default()));
+
+        let exported = |format, selection| -> anyhow::Result<String> {
+            let mut out = vec![];
+            export(&state, format, selection, &mut out)?;
+            Ok(String::from_utf8(out)?)
+        };
+        assert_eq!(dump(&state, DumpFormat::Text, false), "abc\nd\n");
+        assert_eq!(exported(DumpFormat::Text, false).unwrap(), "abcd\n");
+        assert_eq!(exported(DumpFormat::Ansi, false).unwrap(), "abcd\n");
+        let doc: Value = serde_json::from_str(&exported(DumpFormat::Json, false).unwrap()).unwrap();
+        assert_eq!(doc["lines"][0]["text"], "abcd");
+        assert!(exported(DumpFormat::Text, true).is_err());
+    }
+}
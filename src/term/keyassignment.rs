@@ -1,3 +1,4 @@
+use crate::term::dump::DumpFormat;
 use crate::term::{KeyCode, KeyModifiers};
 use std::collections::HashMap;
 
@@ -10,6 +11,15 @@ pub enum KeyAssignment {
     DecreaseFontSize,
     ResetFontSize,
     Hide,
+    SpawnWindow,
+    ScrollToPreviousPrompt,
+    ScrollToNextPrompt,
+    CopyLastCommandOutput,
+    ToggleRecording,
+    /// Saves the scrollback and the screen to a file in the home directory.
+    ExportScrollback(DumpFormat),
+    /// Saves the selection to a file in the home directory.
+    ExportSelection(DumpFormat),
 }
 
 pub struct KeyMap(HashMap<(KeyCode, KeyModifiers), KeyAssignment>);
@@ -41,6 +51,16 @@ impl KeyMap {
             [KeyModifiers::ALT, KeyCode::Enter, ToggleFullScreen],
             [KeyModifiers::SUPER, KeyCode::Char('m'), Hide],
             [ctrl_shift, KeyCode::Char('m'), Hide],
+            [KeyModifiers::SUPER, KeyCode::Char('n'), SpawnWindow],
+            [ctrl_shift, KeyCode::Char('n'), SpawnWindow],
+            [ctrl_shift, KeyCode::UpArrow, ScrollToPreviousPrompt],
+            [ctrl_shift, KeyCode::DownArrow, ScrollToNextPrompt],
+            [ctrl_shift, KeyCode::Char('o'), CopyLastCommandOutput],
+            [ctrl_shift, KeyCode::Char('r'), ToggleRecording],
+            [ctrl_shift, KeyCode::Char('s'), ExportScrollback(DumpFormat::Text)],
+            [ctrl_shift, KeyCode::Char('a'), ExportScrollback(DumpFormat::Ansi)],
+            [ctrl_shift, KeyCode::Char('h'), ExportScrollback(DumpFormat::Html)],
+            [ctrl_shift, KeyCode::Char('e'), ExportSelection(DumpFormat::Html)],
             [KeyModifiers::CTRL, KeyCode::Char('-'), DecreaseFontSize],
             [KeyModifiers::CTRL, KeyCode::Char('0'), ResetFontSize],
             [KeyModifiers::CTRL, KeyCode::Char('='), IncreaseFontSize],
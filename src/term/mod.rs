@@ -7,7 +7,9 @@ pub mod input;
 pub use input::*;
 
 pub mod clipboard;
+pub mod dump;
 pub mod keyassignment;
+pub mod paste;
 
 pub use crate::core::cell::{self, *};
 
@@ -16,6 +18,8 @@ pub use crate::core::surface::line::*;
 pub mod screen;
 pub use screen::*;
 
+pub mod scrollback;
+
 pub mod selection;
 use selection::{SelectionCoordinate, SelectionRange};
 
@@ -0,0 +1,75 @@
+use serde_derive::*;
+
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+/// Controls what is removed from text before it is pasted into the
+/// terminal.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PasteFilter {
+    /// Paste the text exactly as it is.
+    None,
+    /// Remove the bracketed paste markers so that the text cannot end
+    /// the paste early.
+    StripTerminators,
+    /// Remove all control characters other than tab and newlines.
+    StripControl,
+}
+
+impl Default for PasteFilter {
+    fn default() -> Self {
+        PasteFilter::StripControl
+    }
+}
+
+impl PasteFilter {
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            PasteFilter::None => text.to_string(),
+            PasteFilter::StripTerminators => {
+                // Removing one marker can join the text around it into
+                // another, so repeat until none are left.
+                let mut text = text.to_string();
+                while text.contains(PASTE_START) || text.contains(PASTE_END) {
+                    text = text.replace(PASTE_START, "").replace(PASTE_END, "");
+                }
+                text
+            }
+            PasteFilter::StripControl => text
+                .chars()
+                .filter(|&c| !c.is_control() || c == '\t' || c == '\n' || c == '\r')
+                .collect(),
+        }
+    }
+}
+
+/// Converts CRLF line endings to the CR that the Enter key sends.
+pub fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\r")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strip_terminators() {
+        let text = "echo hi\x1b[201~rm -rf ~\n";
+        assert_eq!(PasteFilter::StripTerminators.apply(text), "echo hirm -rf ~\n");
+
+        let nested = "a\x1b[20\x1b[201~1~b";
+        assert_eq!(PasteFilter::StripTerminators.apply(nested), "ab");
+    }
+
+    #[test]
+    fn strip_control() {
+        let text = "ls\t-l\x1b[201~\x07\u{9b}\r\n";
+        assert_eq!(PasteFilter::StripControl.apply(text), "ls\t-l[201~\r\n");
+        assert_eq!(PasteFilter::None.apply(text), text);
+    }
+
+    #[test]
+    fn newlines() {
+        assert_eq!(normalize_newlines("a\r\nb\nc\r"), "a\rb\nc\r");
+    }
+}
@@ -1,9 +1,20 @@
+use super::scrollback::{Scrollback, ScrollbackStats};
 use super::*;
+use std::borrow::Cow;
 use std::collections::VecDeque;
 
+/// How many lines of scrollback are kept expanded, where they are cheap to
+/// read and change, before they are moved to the compressed scrollback.
+const EXPANDED_SCROLLBACK: usize = 1000;
+
+/// The lines of a screen are indexed from the oldest line of the
+/// scrollback.  The visible lines and the most recent part of the
+/// scrollback are kept expanded in `lines`, and the older ones are packed
+/// and compressed in `scrollback`.
 #[derive(Debug, Clone)]
 pub struct Screen {
-    pub lines: VecDeque<Line>,
+    lines: VecDeque<Line>,
+    scrollback: Scrollback,
     pub scrollback_size: usize,
     pub physical_rows: usize,
     pub physical_cols: usize,
@@ -14,19 +25,26 @@ impl Screen {
         let physical_rows = physical_rows.max(1);
         let physical_cols = physical_cols.max(1);
 
-        let mut lines = VecDeque::with_capacity(physical_rows + scrollback_size);
+        let capacity = physical_rows + scrollback_size.min(EXPANDED_SCROLLBACK);
+        let mut lines = VecDeque::with_capacity(capacity);
         for _ in 0..physical_rows {
             lines.push_back(Line::with_width(physical_cols));
         }
 
-        Screen { lines, scrollback_size, physical_rows, physical_cols }
+        Screen {
+            lines,
+            scrollback: Scrollback::default(),
+            scrollback_size,
+            physical_rows,
+            physical_cols,
+        }
     }
 
     pub fn resize(&mut self, physical_rows: usize, physical_cols: usize) {
         let physical_rows = physical_rows.max(1);
         let physical_cols = physical_cols.max(1);
 
-        let capacity = physical_rows + self.scrollback_size;
+        let capacity = physical_rows + self.scrollback_size.min(EXPANDED_SCROLLBACK);
         let current_capacity = self.lines.capacity();
         if capacity > current_capacity {
             self.lines.reserve(capacity - current_capacity);
@@ -39,18 +57,100 @@ impl Screen {
         }
         self.physical_rows = physical_rows;
         self.physical_cols = physical_cols;
+        self.compress_scrollback();
+    }
+
+    /// The number of lines, including the whole scrollback.
+    #[inline]
+    pub fn num_lines(&self) -> usize {
+        self.scrollback.len() + self.lines.len()
+    }
+
+    /// Whether the line at `idx` is kept expanded, which is always the
+    /// case for the visible lines.
+    #[inline]
+    pub fn is_expanded(&self, idx: PhysRowIndex) -> bool {
+        idx >= self.scrollback.len() && idx < self.num_lines()
+    }
+
+    /// Returns the line at `idx`, decompressing it if it is part of the
+    /// older scrollback.
+    pub fn line(&self, idx: PhysRowIndex) -> Cow<Line> {
+        match idx.checked_sub(self.scrollback.len()) {
+            Some(idx) => Cow::Borrowed(&self.lines[idx]),
+            None => Cow::Owned(self.scrollback.get(idx)),
+        }
+    }
+
+    pub fn lines(&self, range: Range<PhysRowIndex>) -> impl Iterator<Item = Cow<Line>> {
+        range.map(move |idx| self.line(idx))
     }
 
+    /// Only expanded lines can be changed in place, so `idx` has to be
+    /// one of them.
     #[inline]
     pub fn line_mut(&mut self, idx: PhysRowIndex) -> &mut Line {
+        let idx = idx - self.scrollback.len();
         &mut self.lines[idx]
     }
 
+    /// The expanded lines, oldest first.
+    pub fn lines_mut(&mut self) -> impl Iterator<Item = &mut Line> {
+        self.lines.iter_mut()
+    }
+
+    /// Marks the line at `idx` for repainting.  Compressed lines don't
+    /// keep a dirty flag, and are repainted whenever they are in view.
+    pub fn set_dirty(&mut self, idx: PhysRowIndex) {
+        if self.is_expanded(idx) {
+            self.line_mut(idx).set_dirty();
+        }
+    }
+
     #[inline]
     pub fn dirty_line(&mut self, idx: VisibleRowIndex) {
         let line_idx = self.phys_row(idx);
-        if line_idx < self.lines.len() {
-            self.lines[line_idx].set_dirty();
+        self.set_dirty(line_idx);
+    }
+
+    pub fn set_exit_code(&mut self, idx: PhysRowIndex, exit_code: Option<i32>) {
+        match idx.checked_sub(self.scrollback.len()) {
+            Some(idx) => self.lines[idx].set_exit_code(exit_code),
+            None => self.scrollback.set_exit_code(idx, exit_code),
+        }
+    }
+
+    /// The indices of the lines that start a prompt, in order.
+    pub fn prompt_rows(&self) -> Vec<PhysRowIndex> {
+        let offset = self.scrollback.len();
+        let mut rows = self.scrollback.prompt_rows();
+        rows.extend(
+            self.lines
+                .iter()
+                .enumerate()
+                .filter(|(_, line)| line.semantic_type() == SemanticType::Prompt)
+                .map(|(idx, _)| offset + idx),
+        );
+        rows
+    }
+
+    pub fn stats(&self) -> ScrollbackStats {
+        let line_size = |line: &Line| {
+            std::mem::size_of::<Line>() + line.cells().len() * std::mem::size_of::<Cell>()
+        };
+        ScrollbackStats {
+            expanded_lines: self.lines.len(),
+            expanded_bytes: self.lines.iter().map(line_size).sum(),
+            ..self.scrollback.stats()
+        }
+    }
+
+    /// Moves the expanded lines that are too far into the scrollback to
+    /// the compressed scrollback.
+    fn compress_scrollback(&mut self) {
+        while self.lines.len() > self.physical_rows + EXPANDED_SCROLLBACK {
+            let line = self.lines.pop_front().unwrap();
+            self.scrollback.push(&line);
         }
     }
 
@@ -94,12 +194,12 @@ impl Screen {
     #[inline]
     pub fn phys_row(&self, row: VisibleRowIndex) -> PhysRowIndex {
         assert!(row >= 0, "phys_row called with negative row {}", row);
-        (self.lines.len() - self.physical_rows) + row as usize
+        (self.num_lines() - self.physical_rows) + row as usize
     }
 
     #[inline]
     pub fn scrollback_or_visible_row(&self, row: ScrollbackOrVisibleRowIndex) -> PhysRowIndex {
-        ((self.lines.len() - self.physical_rows) as ScrollbackOrVisibleRowIndex + row).max(0)
+        ((self.num_lines() - self.physical_rows) as ScrollbackOrVisibleRowIndex + row).max(0)
             as usize
     }
 
@@ -116,25 +216,37 @@ impl Screen {
         self.phys_row(range.start)..self.phys_row(range.end)
     }
 
+    /// Like `phys_range`, but indexing the expanded lines.
+    fn expanded_range(&self, range: &Range<VisibleRowIndex>) -> Range<usize> {
+        let first_visible = self.lines.len() - self.physical_rows;
+        first_visible + range.start as usize..first_visible + range.end as usize
+    }
+
     pub fn scroll_up(&mut self, scroll_region: &Range<VisibleRowIndex>, num_rows: usize) {
-        let phys_scroll = self.phys_range(scroll_region);
+        let phys_scroll = self.expanded_range(scroll_region);
         let num_rows = num_rows.min(phys_scroll.end - phys_scroll.start);
 
         for y in phys_scroll.clone() {
-            self.line_mut(y).set_dirty();
+            self.lines[y].set_dirty();
         }
 
-        let lines_removed = if scroll_region.start > 0 {
+        let mut lines_removed = if scroll_region.start > 0 {
             num_rows
         } else {
             let max_allowed = self.physical_rows + self.scrollback_size;
-            if self.lines.len() + num_rows >= max_allowed {
-                (self.lines.len() + num_rows) - max_allowed
+            if self.num_lines() + num_rows >= max_allowed {
+                (self.num_lines() + num_rows) - max_allowed
             } else {
                 0
             }
         };
 
+        if scroll_region.start == 0 {
+            let from_scrollback = lines_removed.min(self.scrollback.len());
+            self.scrollback.remove_oldest(from_scrollback);
+            lines_removed -= from_scrollback;
+        }
+
         let remove_idx = if scroll_region.start == 0 { 0 } else { phys_scroll.start };
 
         let to_move = lines_removed.min(num_rows);
@@ -166,16 +278,18 @@ impl Screen {
                 self.lines.insert(phys_scroll.end, Line::with_width(self.physical_cols));
             }
         }
+
+        self.compress_scrollback();
     }
 
     pub fn scroll_down(&mut self, scroll_region: &Range<VisibleRowIndex>, num_rows: usize) {
-        let phys_scroll = self.phys_range(scroll_region);
+        let phys_scroll = self.expanded_range(scroll_region);
         let num_rows = num_rows.min(phys_scroll.end - phys_scroll.start);
 
         let middle = phys_scroll.end - num_rows;
 
         for y in phys_scroll.start..middle {
-            self.line_mut(y).set_dirty();
+            self.lines[y].set_dirty();
         }
 
         for _ in 0..num_rows {
@@ -187,3 +301,33 @@ impl Screen {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compressed_scrollback() {
+        let mut screen = Screen::new(2, 8, 3000);
+        for n in 0..4000 {
+            let line = screen.line_mut(screen.phys_row(1));
+            *line = Line::from(n.to_string().as_str());
+            if n % 1000 == 0 {
+                line.set_semantic_type(SemanticType::Prompt);
+            }
+            screen.scroll_up(&(0..2), 1);
+        }
+
+        assert_eq!(screen.num_lines(), 3002);
+        assert!(!screen.is_expanded(0));
+        assert!(screen.is_expanded(screen.phys_row(0)));
+        assert_eq!(screen.line(0).as_str(), "999");
+        assert_eq!(screen.line(1500).as_str(), "2499");
+        assert_eq!(screen.line(screen.phys_row(0)).as_str(), "3999");
+        assert_eq!(screen.prompt_rows(), vec![1, 1001, 2001]);
+
+        screen.set_exit_code(1, Some(3));
+        assert_eq!(screen.line(1).exit_code(), Some(3));
+        assert!(screen.stats().compressed_lines > 0);
+    }
+}
@@ -0,0 +1,454 @@
+//! Storage for the part of the scrollback that is far enough from the
+//! bottom of the screen that it is unlikely to change again.
+//!
+//! Lines are first packed into their text and the runs of cells sharing
+//! the same attributes.  Once enough of them have accumulated, the oldest
+//! are encoded into a block and compressed with zstd.  Blocks are only
+//! decompressed when their lines are needed again, and the most recently
+//! used ones are kept around so that scrolling through them stays cheap.
+use super::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+/// How many lines go into each compressed block.
+const BLOCK_LINES: usize = 512;
+/// How many lines are kept packed before the oldest are compressed.
+const PACKED_LINES: usize = 1024;
+/// How many decompressed blocks are kept around.
+const THAWED_BLOCKS: usize = 4;
+/// How many of the most recently added attributes are searched for a
+/// match before adding another one to the table of a block.
+const ATTRIBUTE_SEARCH: usize = 16;
+const ZSTD_LEVEL: i32 = 3;
+
+/// A line reduced to its text and the runs of cells that share the same
+/// attributes.
+#[derive(Debug, Clone, PartialEq)]
+struct PackedLine {
+    /// The text of every cell up to the last one that isn't blank.
+    text: String,
+    /// The cells whose text isn't a single char, as (cell, byte length).
+    graphemes: Vec<(u32, u32)>,
+    /// The number of cells sharing each successive set of attributes.
+    runs: Vec<(u32, CellAttributes)>,
+    width: u32,
+    semantic_type: SemanticType,
+    exit_code: Option<i32>,
+}
+
+impl PackedLine {
+    fn pack(line: &Line) -> Self {
+        let cells = line.cells();
+        let blank = Cell::default();
+        let len = cells.iter().rposition(|cell| *cell != blank).map_or(0, |idx| idx + 1);
+
+        let mut packed = PackedLine {
+            text: String::new(),
+            graphemes: vec![],
+            runs: vec![],
+            width: cells.len() as u32,
+            semantic_type: line.semantic_type(),
+            exit_code: line.exit_code(),
+        };
+        for (idx, cell) in cells[..len].iter().enumerate() {
+            let text = cell.str();
+            if text.chars().count() != 1 {
+                packed.graphemes.push((idx as u32, text.len() as u32));
+            }
+            packed.text.push_str(text);
+
+            // Implicit hyperlinks are found again when the line is scanned
+            let mut attrs = cell.attrs().clone();
+            if attrs.hyperlink.as_ref().map_or(false, |link| link.is_implicit()) {
+                attrs.hyperlink = None;
+            }
+            match packed.runs.last_mut() {
+                Some((count, last)) if *last == attrs => *count += 1,
+                _ => packed.runs.push((1, attrs)),
+            }
+        }
+        packed
+    }
+
+    fn unpack(&self) -> Line {
+        let mut cells = Vec::with_capacity(self.width as usize);
+        let mut text = self.text.as_str();
+        let mut next_grapheme = 0;
+        for (count, attrs) in &self.runs {
+            for _ in 0..*count {
+                let len = match self.graphemes.get(next_grapheme) {
+                    Some(&(idx, len)) if idx as usize == cells.len() => {
+                        next_grapheme += 1;
+                        len as usize
+                    }
+                    _ => text.chars().next().map_or(0, char::len_utf8),
+                };
+                let (grapheme, rest) = text.split_at(len);
+                cells.push(Cell::new_grapheme(grapheme, attrs.clone()));
+                text = rest;
+            }
+        }
+        cells.resize(self.width as usize, Cell::default());
+
+        let mut line = Line::from_cells(cells);
+        line.set_semantic_type(self.semantic_type);
+        line.set_exit_code(self.exit_code);
+        line
+    }
+
+    fn heap_size(&self) -> usize {
+        size_of::<Self>()
+            + self.text.capacity()
+            + self.graphemes.capacity() * size_of::<(u32, u32)>()
+            + self.runs.capacity() * size_of::<(u32, CellAttributes)>()
+    }
+}
+
+fn put(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn get(data: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = data[0];
+        *data = &data[1..];
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    value
+}
+
+/// `BLOCK_LINES` packed lines, compressed.
+#[derive(Debug, Clone)]
+struct Block {
+    id: u64,
+    /// The encoded lines, which refer to their attributes by their index
+    /// in `attrs`.
+    data: Vec<u8>,
+    attrs: Vec<CellAttributes>,
+    /// The offsets of the lines that start a prompt, which are kept
+    /// outside of the compressed data so that finding them is cheap.
+    prompts: Vec<usize>,
+}
+
+impl Block {
+    fn compress(id: u64, lines: &[PackedLine]) -> Self {
+        let mut buf = vec![];
+        let mut attrs: Vec<CellAttributes> = vec![];
+        for line in lines {
+            put(&mut buf, u64::from(line.width));
+            put(&mut buf, line.text.len() as u64);
+            buf.extend_from_slice(line.text.as_bytes());
+            put(&mut buf, line.graphemes.len() as u64);
+            for (idx, len) in &line.graphemes {
+                put(&mut buf, u64::from(*idx));
+                put(&mut buf, u64::from(*len));
+            }
+            put(&mut buf, line.runs.len() as u64);
+            for (count, run_attrs) in &line.runs {
+                let mut recent = attrs.iter().rev().take(ATTRIBUTE_SEARCH);
+                let idx = match recent.position(|known| known == run_attrs) {
+                    Some(pos) => attrs.len() - 1 - pos,
+                    None => {
+                        attrs.push(run_attrs.clone());
+                        attrs.len() - 1
+                    }
+                };
+                put(&mut buf, u64::from(*count));
+                put(&mut buf, idx as u64);
+            }
+            put(
+                &mut buf,
+                match line.semantic_type {
+                    SemanticType::Output => 0,
+                    SemanticType::Prompt => 1,
+                    SemanticType::Input => 2,
+                },
+            );
+            put(&mut buf, line.exit_code.map_or(0, |code| u64::from(code as u32) + 1));
+        }
+
+        let prompts = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.semantic_type == SemanticType::Prompt)
+            .map(|(idx, _)| idx)
+            .collect();
+        let data = zstd::encode_all(&buf[..], ZSTD_LEVEL).expect("compressing into memory failed");
+        Block { id, data, attrs, prompts }
+    }
+
+    fn decompress(&self) -> Vec<PackedLine> {
+        let buf = zstd::decode_all(&self.data[..]).expect("scrollback block is corrupt");
+        let mut data = &buf[..];
+        (0..BLOCK_LINES)
+            .map(|_| {
+                let width = get(&mut data) as u32;
+                let len = get(&mut data) as usize;
+                let text = String::from_utf8(data[..len].to_vec()).expect("invalid utf8 in block");
+                data = &data[len..];
+                let graphemes = (0..get(&mut data))
+                    .map(|_| (get(&mut data) as u32, get(&mut data) as u32))
+                    .collect();
+                let runs = (0..get(&mut data))
+                    .map(|_| (get(&mut data) as u32, self.attrs[get(&mut data) as usize].clone()))
+                    .collect();
+                let semantic_type = match get(&mut data) {
+                    1 => SemanticType::Prompt,
+                    2 => SemanticType::Input,
+                    _ => SemanticType::Output,
+                };
+                let exit_code = match get(&mut data) {
+                    0 => None,
+                    code => Some((code - 1) as u32 as i32),
+                };
+                PackedLine { text, graphemes, runs, width, semantic_type, exit_code }
+            })
+            .collect()
+    }
+
+    fn heap_size(&self) -> usize {
+        size_of::<Self>()
+            + self.data.capacity()
+            + self.attrs.capacity() * size_of::<CellAttributes>()
+            + self.prompts.capacity() * size_of::<usize>()
+    }
+}
+
+/// How much memory the lines of a screen take up, for debugging.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScrollbackStats {
+    pub expanded_lines: usize,
+    pub expanded_bytes: usize,
+    pub packed_lines: usize,
+    pub packed_bytes: usize,
+    pub compressed_lines: usize,
+    pub compressed_bytes: usize,
+    /// The decompressed blocks that are kept around.
+    pub thawed_bytes: usize,
+}
+
+impl std::fmt::Display for ScrollbackStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let rows = [
+            ("expanded", self.expanded_lines, self.expanded_bytes),
+            ("packed", self.packed_lines, self.packed_bytes),
+            ("compressed", self.compressed_lines, self.compressed_bytes),
+        ];
+        for (name, lines, bytes) in &rows {
+            writeln!(f, "{:<10} {:>9} lines {:>12} bytes", name, lines, bytes)?;
+        }
+        write!(f, "{:<10} {:>28} bytes", "thawed", self.thawed_bytes)
+    }
+}
+
+/// The oldest lines of a screen, indexed from the oldest one.
+#[derive(Debug, Clone, Default)]
+pub struct Scrollback {
+    blocks: VecDeque<Block>,
+    /// How many lines of the first block have already been discarded.
+    skip: usize,
+    packed: VecDeque<PackedLine>,
+    next_block_id: u64,
+    /// The most recently used decompressed blocks, the latest last.
+    thawed: RefCell<VecDeque<(u64, Vec<PackedLine>)>>,
+}
+
+impl Scrollback {
+    pub fn len(&self) -> usize {
+        self.blocks.len() * BLOCK_LINES - self.skip + self.packed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, line: &Line) {
+        self.packed.push_back(PackedLine::pack(line));
+        if self.packed.len() >= PACKED_LINES + BLOCK_LINES {
+            let lines: Vec<PackedLine> = self.packed.drain(..BLOCK_LINES).collect();
+            self.blocks.push_back(Block::compress(self.next_block_id, &lines));
+            self.next_block_id += 1;
+        }
+    }
+
+    /// Discards the `count` oldest lines.
+    pub fn remove_oldest(&mut self, count: usize) {
+        for _ in 0..count {
+            if self.blocks.is_empty() {
+                self.packed.pop_front();
+                continue;
+            }
+            self.skip += 1;
+            if self.skip == BLOCK_LINES {
+                let block = self.blocks.pop_front().unwrap();
+                self.thawed.get_mut().retain(|(id, _)| *id != block.id);
+                self.skip = 0;
+            }
+        }
+    }
+
+    /// Calls `f` with the lines of the `n`th block, decompressing it if it
+    /// isn't one of the recently used ones.
+    fn with_block<T>(&self, n: usize, f: impl FnOnce(&[PackedLine]) -> T) -> T {
+        let block = &self.blocks[n];
+        let mut thawed = self.thawed.borrow_mut();
+        match thawed.iter().position(|(id, _)| *id == block.id) {
+            Some(pos) => {
+                let entry = thawed.remove(pos).unwrap();
+                thawed.push_back(entry);
+            }
+            None => {
+                if thawed.len() == THAWED_BLOCKS {
+                    thawed.pop_front();
+                }
+                thawed.push_back((block.id, block.decompress()));
+            }
+        }
+        f(&thawed.back().unwrap().1)
+    }
+
+    pub fn get(&self, idx: usize) -> Line {
+        let idx = idx + self.skip;
+        let compressed = self.blocks.len() * BLOCK_LINES;
+        if idx < compressed {
+            self.with_block(idx / BLOCK_LINES, |lines| lines[idx % BLOCK_LINES].unpack())
+        } else {
+            self.packed[idx - compressed].unpack()
+        }
+    }
+
+    pub fn set_exit_code(&mut self, idx: usize, exit_code: Option<i32>) {
+        let idx = idx + self.skip;
+        let compressed = self.blocks.len() * BLOCK_LINES;
+        if idx < compressed {
+            let n = idx / BLOCK_LINES;
+            let mut lines = self.with_block(n, |lines| lines.to_vec());
+            lines[idx % BLOCK_LINES].exit_code = exit_code;
+            let id = self.blocks[n].id;
+            self.blocks[n] = Block::compress(id, &lines);
+            self.thawed.get_mut().retain(|(thawed_id, _)| *thawed_id != id);
+        } else {
+            self.packed[idx - compressed].exit_code = exit_code;
+        }
+    }
+
+    /// The indices of the lines that start a prompt, in order.
+    pub fn prompt_rows(&self) -> Vec<usize> {
+        let compressed = self.blocks.len() * BLOCK_LINES;
+        let blocks = self.blocks.iter().enumerate().flat_map(|(n, block)| {
+            block.prompts.iter().map(move |offset| n * BLOCK_LINES + offset)
+        });
+        let packed = self
+            .packed
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.semantic_type == SemanticType::Prompt)
+            .map(|(idx, _)| compressed + idx);
+        blocks.chain(packed).filter(|&idx| idx >= self.skip).map(|idx| idx - self.skip).collect()
+    }
+
+    /// Fills in everything but the expanded lines, which belong to the
+    /// screen.
+    pub fn stats(&self) -> ScrollbackStats {
+        let thawed = self.thawed.borrow();
+        ScrollbackStats {
+            packed_lines: self.packed.len(),
+            packed_bytes: self.packed.iter().map(PackedLine::heap_size).sum(),
+            compressed_lines: self.blocks.len() * BLOCK_LINES - self.skip,
+            compressed_bytes: self.blocks.iter().map(Block::heap_size).sum(),
+            thawed_bytes: thawed
+                .iter()
+                .flat_map(|(_, lines)| lines.iter())
+                .map(PackedLine::heap_size)
+                .sum(),
+            ..ScrollbackStats::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::color::ColorAttribute;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn line(n: usize) -> Line {
+        let mut line = Line::with_width(10);
+        let mut red = CellAttributes::default();
+        red.set_foreground(ColorAttribute::PaletteIndex(1));
+        line.set_cell(0, Cell::new_grapheme("e\u{301}", red.clone()));
+        for (idx, c) in n.to_string().chars().enumerate() {
+            line.set_cell(idx + 1, Cell::new(c, red.clone()));
+        }
+        line.set_cell(7, Cell::new('\u{4e2d}', CellAttributes::default()));
+        if n % 100 == 0 {
+            line.set_semantic_type(SemanticType::Prompt);
+            line.set_exit_code(Some(-1));
+        }
+        line
+    }
+
+    fn scrollback(count: usize) -> Scrollback {
+        let mut scrollback = Scrollback::default();
+        for n in 0..count {
+            scrollback.push(&line(n));
+        }
+        scrollback
+    }
+
+    #[test]
+    fn pack() {
+        let mut link = CellAttributes::default();
+        let uri = "https://example.com";
+        link.set_hyperlink(Some(Arc::new(Hyperlink::new_with_params(uri, HashMap::new()))));
+        let mut original = line(1234);
+        original.set_cell(4, Cell::new('x', link));
+        original.set_cell(9, Cell::new(' ', CellAttributes::default().set_wrapped(true).clone()));
+
+        let packed = PackedLine::pack(&original);
+        assert_eq!(packed.text, "e\u{301}123x  \u{4e2d}  ");
+        assert_eq!(packed.unpack().cells(), original.cells());
+    }
+
+    #[test]
+    fn blocks() {
+        let count = PACKED_LINES + 3 * BLOCK_LINES + 10;
+        let mut scrollback = scrollback(count);
+        assert_eq!(scrollback.blocks.len(), 3);
+        assert_eq!(scrollback.len(), count);
+        for n in &[0, 1, BLOCK_LINES, 2 * BLOCK_LINES + 1, count - 1] {
+            assert_eq!(scrollback.get(*n), line(*n));
+        }
+
+        scrollback.remove_oldest(BLOCK_LINES + 5);
+        assert_eq!(scrollback.blocks.len(), 2);
+        assert_eq!(scrollback.len(), count - BLOCK_LINES - 5);
+        assert_eq!(scrollback.get(0), line(BLOCK_LINES + 5));
+        assert_eq!(scrollback.get(scrollback.len() - 1), line(count - 1));
+    }
+
+    #[test]
+    fn prompts() {
+        let mut scrollback = scrollback(PACKED_LINES + 2 * BLOCK_LINES);
+        scrollback.remove_oldest(150);
+        let rows = scrollback.prompt_rows();
+        assert_eq!(rows[..2], [50, 150]);
+        assert_eq!(rows.len(), (PACKED_LINES + 2 * BLOCK_LINES) / 100 - 1);
+
+        scrollback.set_exit_code(50, Some(0));
+        scrollback.set_exit_code(scrollback.len() - 48, Some(1));
+        assert_eq!(scrollback.get(50).exit_code(), Some(0));
+        assert_eq!(scrollback.get(scrollback.len() - 48).exit_code(), Some(1));
+        assert_eq!(scrollback.get(150).exit_code(), Some(-1));
+    }
+}
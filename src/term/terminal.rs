@@ -1,14 +1,29 @@
 use super::*;
+use crate::core::escape::csi::Window;
 use crate::core::escape::parser::Parser;
 use crate::core::hyperlink::Rule as HyperlinkRule;
-use crate::term::clipboard::Clipboard;
+use crate::term::clipboard::{Clipboard, ClipboardRequest, ClipboardSelection};
 use std::sync::Arc;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Alert {
+    Bell,
+    ToastNotification { title: Option<String>, body: String },
+}
+
 pub trait TerminalHost {
     fn writer(&mut self) -> &mut dyn std::io::Write;
     fn get_clipboard(&mut self) -> anyhow::Result<Arc<dyn Clipboard>>;
     fn set_title(&mut self, title: &str);
     fn click_link(&mut self, link: &Arc<Hyperlink>);
+    fn alert(&mut self, alert: Alert);
+    fn clipboard_request(&mut self, request: ClipboardRequest);
+    /// Asks the gui to report on or manipulate the window in response to
+    /// an XTWINOPS sequence that the terminal cannot answer by itself.
+    fn window_request(&mut self, request: Window);
+    /// Pastes the contents of `selection` on behalf of the terminal, such
+    /// as for a middle click.
+    fn paste_selection(&mut self, selection: ClipboardSelection);
 }
 
 pub struct Terminal {
@@ -43,8 +58,8 @@ impl Terminal {
             state: TerminalState::new(
                 physical_rows,
                 physical_cols,
-                pixel_height,
                 pixel_width,
+                pixel_height,
                 scrollback_size,
                 hyperlink_rules,
             ),
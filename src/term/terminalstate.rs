@@ -3,14 +3,18 @@ use crate::core::escape::csi::{
     Cursor, DecPrivateMode, DecPrivateModeCode, Device, Edit, EraseInDisplay, EraseInLine, Mode,
     Sgr, TerminalMode, TerminalModeCode, Window,
 };
-use crate::core::escape::osc::{ChangeColorPair, ColorOrQuery};
+use crate::core::escape::osc::{ChangeColorPair, ColorOrQuery, FinalTermSemanticPrompt};
 use crate::core::escape::{
     Action, ControlCode, Esc, EscCode, OneBased, OperatingSystemCommand, CSI,
 };
 use crate::core::hyperlink::Rule as HyperlinkRule;
+use crate::term::clipboard::{ClipboardRequest, ClipboardSelection};
 use crate::term::color::ColorPalette;
+use crate::term::paste::PasteFilter;
 use anyhow::bail;
+use std::borrow::Cow;
 use std::fmt::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 struct TabStop {
@@ -140,6 +144,8 @@ pub struct TerminalState {
     application_cursor_keys: bool,
     application_keypad: bool,
     bracketed_paste: bool,
+    paste_filter: PasteFilter,
+    focus_tracking: bool,
     sgr_mouse: bool,
     button_event_mouse: bool,
     current_mouse_button: MouseButton,
@@ -154,11 +160,82 @@ pub struct TerminalState {
     tabs: TabStop,
     hyperlink_rules: Vec<HyperlinkRule>,
     title: String,
+    icon_name: String,
+    title_stack: Vec<TitleStackEntry>,
+    title_reporting: bool,
+    current_dir: Option<PathBuf>,
+    semantic_type: SemanticType,
+    copy_on_select: bool,
+    middle_click_paste: ClipboardSelection,
     palette: ColorPalette,
     pixel_width: usize,
     pixel_height: usize,
 }
 
+/// The deepest the stack of saved titles may grow; pushing beyond this
+/// discards the oldest entry.
+const TITLE_STACK_DEPTH: usize = 10;
+
+#[derive(Debug, Clone, Default)]
+struct TitleStackEntry {
+    title: Option<String>,
+    icon_name: Option<String>,
+}
+
+/// The path of a `file://` URI, if it names this machine; after `ssh` the
+/// remote shell reports directories that don't exist here.
+fn parse_file_uri(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let (host, path) = rest.split_at(rest.find('/')?);
+    if !is_local_host(host) {
+        return None;
+    }
+
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    Some(path_from_bytes(decoded))
+}
+
+fn is_local_host(host: &str) -> bool {
+    host.is_empty()
+        || host.eq_ignore_ascii_case("localhost")
+        || local_hostname().map_or(false, |name| name.eq_ignore_ascii_case(host))
+}
+
+fn local_hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8(buf[..len].to_vec()).ok()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
 fn is_double_click_word(s: &str) -> bool {
     if s.len() > 1 {
         true
@@ -193,6 +270,8 @@ impl TerminalState {
             application_cursor_keys: false,
             application_keypad: false,
             bracketed_paste: false,
+            paste_filter: PasteFilter::default(),
+            focus_tracking: false,
             sgr_mouse: false,
             button_event_mouse: false,
             cursor_visible: true,
@@ -207,6 +286,13 @@ impl TerminalState {
             tabs: TabStop::new(physical_cols, 8),
             hyperlink_rules,
             title: "miro".to_string(),
+            icon_name: "miro".to_string(),
+            title_stack: vec![],
+            title_reporting: false,
+            current_dir: None,
+            semantic_type: SemanticType::Output,
+            copy_on_select: true,
+            middle_click_paste: ClipboardSelection::Primary,
             palette: ColorPalette::default(),
             pixel_height,
             pixel_width,
@@ -217,6 +303,45 @@ impl TerminalState {
         &self.title
     }
 
+    /// Replaces the title until the program sets one of its own.
+    pub fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+    }
+
+    pub fn get_current_dir(&self) -> Option<&Path> {
+        self.current_dir.as_deref()
+    }
+
+    /// Whether selecting text also places it on the clipboard, in
+    /// addition to the primary selection.
+    pub fn set_copy_on_select(&mut self, copy_on_select: bool) {
+        self.copy_on_select = copy_on_select;
+    }
+
+    /// Whether the title and icon name may be reported back to the
+    /// program running in the terminal.
+    pub fn set_title_reporting(&mut self, enable: bool) {
+        self.title_reporting = enable;
+    }
+
+    pub fn set_paste_filter(&mut self, filter: PasteFilter) {
+        self.paste_filter = filter;
+    }
+
+    /// Removes whatever the paste filter does not allow from `text`.
+    pub fn sanitize_paste(&self, text: &str) -> String {
+        self.paste_filter.apply(text)
+    }
+
+    pub fn bracketed_paste_enabled(&self) -> bool {
+        self.bracketed_paste
+    }
+
+    /// Which buffer is pasted by a middle click.
+    pub fn set_middle_click_paste(&mut self, selection: ClipboardSelection) {
+        self.middle_click_paste = selection;
+    }
+
     pub fn palette(&self) -> &ColorPalette {
         &self.palette
     }
@@ -229,6 +354,11 @@ impl TerminalState {
         &mut self.screen
     }
 
+    /// The selected range, starting with its topmost row.
+    pub fn selection(&self) -> Option<SelectionRange> {
+        self.selection_range.map(|r| r.normalize())
+    }
+
     pub fn get_selection_text(&self) -> String {
         let mut s = String::new();
 
@@ -236,15 +366,15 @@ impl TerminalState {
             let screen = self.screen();
             let mut last_was_wrapped = false;
             for y in sel.rows() {
-                let idx = screen.scrollback_or_visible_row(y);
+                let line = screen.line(screen.scrollback_or_visible_row(y));
                 let cols = sel.cols_for_row(y);
-                let last_col_idx = cols.end.min(screen.lines[idx].cells().len()) - 1;
+                let last_col_idx = cols.end.min(line.cells().len()) - 1;
                 if !s.is_empty() && !last_was_wrapped {
                     s.push('\n');
                 }
-                s.push_str(screen.lines[idx].columns_as_str(cols).trim_end());
+                s.push_str(line.columns_as_str(cols).trim_end());
 
-                let last_cell = &screen.lines[idx].cells()[last_col_idx];
+                let last_cell = &line.cells()[last_col_idx];
 
                 last_was_wrapped = last_cell.attrs().wrapped() && last_cell.str() != " ";
             }
@@ -257,7 +387,7 @@ impl TerminalState {
         if let Some(sel) = self.selection_range.as_ref().map(|r| r.normalize()) {
             let screen = self.screen_mut();
             for y in screen.scrollback_or_visible_range(&sel.rows()) {
-                screen.line_mut(y).set_dirty();
+                screen.set_dirty(y);
             }
         }
     }
@@ -316,23 +446,29 @@ impl TerminalState {
         y: ScrollbackOrVisibleRowIndex,
     ) -> Option<Arc<Hyperlink>> {
         let rules = &self.hyperlink_rules;
+        let screen = &mut *self.screen;
+
+        let idx = screen.scrollback_or_visible_row(y);
+        let mut decompressed;
+        let line = if screen.is_expanded(idx) {
+            screen.line_mut(idx)
+        } else if idx < screen.num_lines() {
+            decompressed = screen.line(idx).into_owned();
+            &mut decompressed
+        } else {
+            return None;
+        };
 
-        let idx = self.screen.scrollback_or_visible_row(y);
-        match self.screen.lines.get_mut(idx) {
-            Some(ref mut line) => {
-                line.scan_and_create_hyperlinks(rules);
-                match line.cells().get(x) {
-                    Some(cell) => cell.attrs().hyperlink.as_ref().cloned(),
-                    None => None,
-                }
-            }
+        line.scan_and_create_hyperlinks(rules);
+        match line.cells().get(x) {
+            Some(cell) => cell.attrs().hyperlink.as_ref().cloned(),
             None => None,
         }
     }
 
     fn invalidate_hyperlinks(&mut self) {
         let screen = self.screen_mut();
-        for line in &mut screen.lines {
+        for line in screen.lines_mut() {
             if line.has_hyperlink() {
                 line.set_dirty();
             }
@@ -347,6 +483,21 @@ impl TerminalState {
         self.invalidate_hyperlinks();
     }
 
+    fn set_selection_contents(
+        &self,
+        text: Option<String>,
+        host: &mut dyn TerminalHost,
+    ) -> anyhow::Result<()> {
+        let clipboard = host.get_clipboard()?;
+        if text.is_some() {
+            clipboard.set_contents(ClipboardSelection::Primary, text.clone())?;
+        }
+        if self.copy_on_select {
+            clipboard.set_contents(ClipboardSelection::Clipboard, text)?;
+        }
+        Ok(())
+    }
+
     fn mouse_single_click_left(
         &mut self,
         event: MouseEvent,
@@ -358,7 +509,7 @@ impl TerminalState {
             y: event.y as ScrollbackOrVisibleRowIndex
                 - self.viewport_offset as ScrollbackOrVisibleRowIndex,
         });
-        host.get_clipboard()?.set_contents(None)
+        self.set_selection_contents(None, host)
     }
 
     fn mouse_double_click_left(
@@ -370,9 +521,9 @@ impl TerminalState {
             - self.viewport_offset as ScrollbackOrVisibleRowIndex;
 
         let idx = self.screen().scrollback_or_visible_row(y);
-        let selection_range = match self.screen().lines[idx]
-            .compute_double_click_range(event.x, is_double_click_word)
-        {
+        let click_range =
+            self.screen().line(idx).compute_double_click_range(event.x, is_double_click_word);
+        let selection_range = match click_range {
             DoubleClickRange::Range(click_range) => SelectionRange {
                 start: SelectionCoordinate { x: click_range.start, y },
                 end: SelectionCoordinate { x: click_range.end - 1, y },
@@ -382,8 +533,10 @@ impl TerminalState {
 
                 let mut end_coord = SelectionCoordinate { x: range_start.end - 1, y };
 
-                for y_cont in idx + 1..self.screen().lines.len() {
-                    match self.screen().lines[y_cont]
+                for y_cont in idx + 1..self.screen().num_lines() {
+                    match self
+                        .screen()
+                        .line(y_cont)
                         .compute_double_click_range(0, is_double_click_word)
                     {
                         DoubleClickRange::Range(range_end) => {
@@ -413,7 +566,7 @@ impl TerminalState {
 
         self.dirty_selection_lines();
         let text = self.get_selection_text();
-        host.get_clipboard()?.set_contents(Some(text))
+        self.set_selection_contents(Some(text), host)
     }
 
     fn mouse_triple_click_left(
@@ -430,7 +583,7 @@ impl TerminalState {
         });
         self.dirty_selection_lines();
         let text = self.get_selection_text();
-        host.get_clipboard()?.set_contents(Some(text))
+        self.set_selection_contents(Some(text), host)
     }
 
     fn mouse_press_left(
@@ -454,7 +607,7 @@ impl TerminalState {
             _ => {
                 self.selection_range = None;
                 self.selection_start = None;
-                host.get_clipboard()?.set_contents(None)?;
+                self.set_selection_contents(None, host)?;
             }
         }
 
@@ -470,7 +623,7 @@ impl TerminalState {
         if let Some(&LastMouseClick { streak: 1, .. }) = self.last_mouse_click.as_ref() {
             let text = self.get_selection_text();
             if !text.is_empty() {
-                host.get_clipboard()?.set_contents(Some(text))?;
+                self.set_selection_contents(Some(text), host)?;
             } else if let Some(link) = self.current_highlight() {
                 host.click_link(&link);
             }
@@ -537,8 +690,7 @@ impl TerminalState {
                     format!("\x1b[<{};{};{}M", button, event.x + 1, event.y + 1).as_bytes(),
                 )?;
             } else if event.button == MouseButton::Middle {
-                let clip = host.get_clipboard()?.get_contents()?;
-                self.send_paste(&clip, host.writer())?
+                host.paste_selection(self.middle_click_paste);
             }
         }
 
@@ -639,6 +791,7 @@ impl TerminalState {
         text: &str,
         writer: &mut dyn std::io::Write,
     ) -> anyhow::Result<()> {
+        let text = self.sanitize_paste(text);
         if self.bracketed_paste {
             let buf = format!("\x1b[200~{}\x1b[201~", text);
             writer.write_all(buf.as_bytes())?;
@@ -648,6 +801,19 @@ impl TerminalState {
         Ok(())
     }
 
+    /// Reports a change of focus to the program when it has enabled focus
+    /// tracking with DEC mode 1004.
+    pub fn focus_changed(
+        &mut self,
+        focused: bool,
+        writer: &mut dyn std::io::Write,
+    ) -> anyhow::Result<()> {
+        if self.focus_tracking {
+            writer.write_all(if focused { b"\x1b[I" } else { b"\x1b[O" })?;
+        }
+        Ok(())
+    }
+
     pub fn key_down(
         &mut self,
         key: KeyCode,
@@ -865,19 +1031,17 @@ impl TerminalState {
         self.set_cursor_pos(&Position::Relative(0), &Position::Relative(0));
     }
 
-    pub fn get_dirty_lines(&self) -> Vec<(usize, &Line, Range<usize>)> {
+    pub fn get_dirty_lines(&self) -> Vec<(usize, Cow<Line>, Range<usize>)> {
         let mut res = Vec::new();
 
         let screen = self.screen();
         let height = screen.physical_rows;
-        let len = screen.lines.len() - self.viewport_offset as usize;
+        let len = screen.num_lines() - self.viewport_offset as usize;
 
         let selection = self.selection_range.map(|r| r.normalize());
 
-        for (i, line) in screen.lines.iter().skip(len - height).enumerate() {
-            if i >= height {
-                break;
-            }
+        // Lines decompressed from the scrollback are always dirty
+        for (i, line) in screen.lines(len - height..len).enumerate() {
             if line.is_dirty() {
                 let selrange = match selection {
                     None => 0..0,
@@ -887,7 +1051,7 @@ impl TerminalState {
                         sel.cols_for_row(row)
                     }
                 };
-                res.push((i, &*line, selrange));
+                res.push((i, line, selrange));
             }
         }
 
@@ -896,14 +1060,14 @@ impl TerminalState {
 
     pub fn clean_dirty_lines(&mut self) {
         let screen = self.screen_mut();
-        for line in &mut screen.lines {
+        for line in screen.lines_mut() {
             line.clear_dirty();
         }
     }
 
     pub fn make_all_lines_dirty(&mut self) {
         let screen = self.screen_mut();
-        for line in &mut screen.lines {
+        for line in screen.lines_mut() {
             line.set_dirty();
         }
     }
@@ -950,16 +1114,16 @@ impl TerminalState {
         let position = position.max(0);
 
         let rows = self.screen().physical_rows;
-        let avail_scrollback = self.screen().lines.len() - rows;
+        let avail_scrollback = self.screen().num_lines() - rows;
 
         let position = position.min(avail_scrollback as i64);
 
         self.viewport_offset = position;
-        let top = self.screen().lines.len() - (rows + position as usize);
+        let top = self.screen().num_lines() - (rows + position as usize);
         {
             let screen = self.screen_mut();
             for y in top..top + rows {
-                screen.line_mut(y).set_dirty();
+                screen.set_dirty(y);
             }
         }
         self.recompute_highlight();
@@ -992,6 +1156,96 @@ impl TerminalState {
             y + 1
         };
         self.set_cursor_pos(&Position::Absolute(x as i64), &Position::Absolute(y as i64));
+        let semantic_type = self.semantic_type;
+        self.cursor_line_mut().set_semantic_type(semantic_type);
+    }
+
+    fn cursor_line_mut(&mut self) -> &mut Line {
+        let y = self.cursor.y;
+        let screen = self.screen_mut();
+        let idx = screen.phys_row(y);
+        screen.line_mut(idx)
+    }
+
+    fn perform_semantic_prompt(&mut self, mark: FinalTermSemanticPrompt) {
+        match mark {
+            FinalTermSemanticPrompt::PromptStart => {
+                if self.cursor.x != 0 {
+                    self.new_line(true);
+                }
+                self.semantic_type = SemanticType::Prompt;
+                self.cursor_line_mut().set_semantic_type(SemanticType::Prompt);
+            }
+            FinalTermSemanticPrompt::CommandStart => {
+                self.semantic_type = SemanticType::Input;
+            }
+            FinalTermSemanticPrompt::OutputStart => {
+                self.semantic_type = SemanticType::Output;
+                let line = self.cursor_line_mut();
+                if line.semantic_type() != SemanticType::Prompt {
+                    line.set_semantic_type(SemanticType::Output);
+                }
+            }
+            FinalTermSemanticPrompt::CommandFinished(status) => {
+                self.semantic_type = SemanticType::Output;
+                let y = self.cursor.y;
+                let screen = self.screen_mut();
+                let end = screen.phys_row(y) + 1;
+                if let Some(idx) = screen.prompt_rows().into_iter().rev().find(|&idx| idx < end) {
+                    screen.set_exit_code(idx, status);
+                }
+            }
+        }
+    }
+
+    /// Scrolls the viewport so that the previous (negative `delta`) or
+    /// next (positive `delta`) prompt is at the top of the screen.
+    pub fn scroll_to_prompt(&mut self, delta: i64) {
+        if delta == 0 {
+            return;
+        }
+        let rows = self.screen().physical_rows;
+        let len = self.screen().num_lines();
+        let top = len - rows - self.viewport_offset as usize;
+        let prompts = self.screen().prompt_rows();
+
+        let target = if delta < 0 {
+            let before: Vec<_> = prompts.iter().filter(|&&idx| idx < top).collect();
+            before.len().checked_sub(delta.abs() as usize).map(|i| *before[i])
+        } else {
+            prompts.iter().filter(|&&idx| idx > top).nth(delta as usize - 1).cloned()
+        };
+
+        match target {
+            Some(idx) => self.set_scroll_viewport((len - rows) as i64 - idx as i64),
+            None if delta > 0 => self.set_scroll_viewport(0),
+            None => {}
+        }
+    }
+
+    /// Returns the output of the most recently completed command, as
+    /// delimited by the shell's OSC 133 marks.
+    pub fn last_command_output(&self) -> Option<String> {
+        let prompts = self.screen().prompt_rows();
+        let (start, end) = match prompts.as_slice() {
+            [.., start, end] => (*start, *end),
+            _ => return None,
+        };
+
+        let mut s = String::new();
+        let mut last_was_wrapped = false;
+        for line in self.screen().lines(start + 1..end) {
+            if line.semantic_type() != SemanticType::Output {
+                continue;
+            }
+            if !s.is_empty() && !last_was_wrapped {
+                s.push('\n');
+            }
+            s.push_str(line.as_str().trim_end());
+            last_was_wrapped = line.cells().last().map(|c| c.attrs().wrapped()).unwrap_or(false);
+        }
+
+        Some(s.trim_end().to_string())
     }
 
     fn c1_index(&mut self) {
@@ -1139,6 +1393,13 @@ impl TerminalState {
             | Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::AnyEventMouse)) => {
             }
 
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::FocusTracking)) => {
+                self.focus_tracking = true;
+            }
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::FocusTracking)) => {
+                self.focus_tracking = false;
+            }
+
             Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::SGRMouse)) => {
                 self.sgr_mouse = true;
             }
@@ -1183,7 +1444,8 @@ impl TerminalState {
 
     fn checksum_rectangle(&mut self, left: u32, top: u32, right: u32, bottom: u32) -> u16 {
         let screen = self.screen_mut();
-        let mut checksum = 0;
+        let bottom = bottom.min(screen.physical_rows as u32 - 1);
+        let mut checksum: u16 = 0;
         for y in top..=bottom {
             let line_idx = screen.phys_row(VisibleRowIndex::from(y));
             let line = screen.line_mut(line_idx);
@@ -1192,8 +1454,8 @@ impl TerminalState {
                     break;
                 }
 
-                let ch = cell.str().chars().nth(0).unwrap() as u32;
-                checksum += u16::from(ch as u8);
+                let ch = cell.str().chars().next().map_or(0, |c| c as u32);
+                checksum = checksum.wrapping_add(u16::from(ch as u8));
             }
         }
         checksum
@@ -1209,6 +1471,19 @@ impl TerminalState {
                 let response = Window::ResizeWindowCells { width, height };
                 write!(host.writer(), "{}", CSI::Window(response)).ok();
             }
+            Window::ReportTextAreaSizePixels => {
+                let response = Window::ResizeWindowPixels {
+                    width: Some(self.pixel_width as i64),
+                    height: Some(self.pixel_height as i64),
+                };
+                write!(host.writer(), "{}", CSI::Window(response)).ok();
+            }
+            Window::ReportCellSizePixels => {
+                let screen = self.screen();
+                let height = self.pixel_height / screen.physical_rows.max(1);
+                let width = self.pixel_width / screen.physical_cols.max(1);
+                write!(host.writer(), "\x1b[6;{};{}t", height, width).ok();
+            }
             Window::ChecksumRectangularArea { request_id, top, left, bottom, right, .. } => {
                 let checksum = self.checksum_rectangle(
                     left.as_zero_based(),
@@ -1218,14 +1493,98 @@ impl TerminalState {
                 );
                 write!(host.writer(), "\x1bP{}!~{:04x}\x1b\\", request_id, checksum).ok();
             }
-            Window::Iconify | Window::DeIconify => {}
-            Window::PopIconAndWindowTitle
-            | Window::PopWindowTitle
-            | Window::PopIconTitle
-            | Window::PushIconAndWindowTitle
-            | Window::PushIconTitle
-            | Window::PushWindowTitle => {}
-            _ => {}
+            Window::ReportWindowTitle => {
+                if self.title_reporting {
+                    write!(host.writer(), "\x1b]l{}\x1b\\", self.title).ok();
+                }
+            }
+            Window::ReportIconLabel => {
+                if self.title_reporting {
+                    write!(host.writer(), "\x1b]L{}\x1b\\", self.icon_name).ok();
+                }
+            }
+            Window::PushIconAndWindowTitle => self.push_title(true, true),
+            Window::PushWindowTitle => self.push_title(true, false),
+            Window::PushIconTitle => self.push_title(false, true),
+            Window::PopIconAndWindowTitle => self.pop_title(true, true, host),
+            Window::PopWindowTitle => self.pop_title(true, false, host),
+            Window::PopIconTitle => self.pop_title(false, true, host),
+            Window::RefreshWindow => self.make_all_lines_dirty(),
+            _ => host.window_request(window),
+        }
+    }
+
+    /// Returns the terminal to its initial state, keeping its size and the
+    /// settings that come from the configuration.  Used to rewind a
+    /// playback; RIS from the program is not honoured.
+    pub fn full_reset(&mut self) {
+        let screen = &self.screen.screen;
+        let (rows, cols) = (screen.physical_rows, screen.physical_cols);
+        self.screen = ScreenOrAlt::new(rows, cols, screen.scrollback_size);
+        self.cursor = CursorPosition::default();
+        self.viewport_offset = 0;
+        self.selection_range = None;
+        self.selection_start = None;
+        self.reset_modes();
+    }
+
+    /// Returns the modes, the pen and the palette to their initial values
+    /// and leaves the alternate screen, keeping the contents of the primary
+    /// screen.  Used when the program that set them is replaced by another.
+    pub fn reset_modes(&mut self) {
+        if self.screen.is_alt_screen_active() {
+            self.screen.activate_primary_screen();
+            if let Some(saved) = self.screen.saved_cursor().take() {
+                self.cursor = saved.position;
+            }
+            self.set_scroll_viewport(0);
+        }
+        let screen = self.screen();
+        let (rows, cols) = (screen.physical_rows, screen.physical_cols);
+        self.pen = CellAttributes::default();
+        self.scroll_region = 0..rows as VisibleRowIndex;
+        self.wrap_next = false;
+        self.insert = false;
+        self.application_cursor_keys = false;
+        self.application_keypad = false;
+        self.bracketed_paste = false;
+        self.focus_tracking = false;
+        self.sgr_mouse = false;
+        self.button_event_mouse = false;
+        self.current_mouse_button = MouseButton::None;
+        self.cursor_visible = true;
+        self.dec_line_drawing_mode = false;
+        self.tabs = TabStop::new(cols, 8);
+        self.semantic_type = SemanticType::Output;
+        self.palette = ColorPalette::default();
+        self.title_stack.clear();
+    }
+
+    fn push_title(&mut self, title: bool, icon_name: bool) {
+        if self.title_stack.len() >= TITLE_STACK_DEPTH {
+            self.title_stack.remove(0);
+        }
+        self.title_stack.push(TitleStackEntry {
+            title: if title { Some(self.title.clone()) } else { None },
+            icon_name: if icon_name { Some(self.icon_name.clone()) } else { None },
+        });
+    }
+
+    fn pop_title(&mut self, title: bool, icon_name: bool, host: &mut dyn TerminalHost) {
+        let entry = match self.title_stack.pop() {
+            Some(entry) => entry,
+            None => return,
+        };
+        if icon_name {
+            if let Some(icon_name) = entry.icon_name {
+                self.icon_name = icon_name;
+            }
+        }
+        if title {
+            if let Some(title) = entry.title {
+                host.set_title(&title);
+                self.title = title;
+            }
         }
     }
 
@@ -1351,7 +1710,7 @@ impl TerminalState {
                 let line_idx = screen.phys_row(y);
                 let line = screen.line_mut(line_idx);
                 if let Some(cell) = line.cells().get(to_copy).cloned() {
-                    line.fill_range(x..=x + n as usize, &cell);
+                    line.fill_range(x..x + n as usize, &cell);
                     self.set_cursor_pos(&Position::Relative(i64::from(n)), &Position::Relative(0))
                 }
             }
@@ -1370,7 +1729,7 @@ impl TerminalState {
                 self.scroll_region = top..bottom + 1;
             }
             Cursor::ForwardTabulation(n) => {
-                for _ in 0..n {
+                for _ in 0..n.min(self.screen().physical_cols as u32) {
                     self.c0_horizontal_tab();
                 }
             }
@@ -1418,9 +1777,7 @@ impl TerminalState {
                 self.set_cursor_pos(&Position::Relative(0), &Position::Relative(i64::from(line)))
             }
             Cursor::NextLine(n) => {
-                for _ in 0..n {
-                    self.new_line(true);
-                }
+                self.set_cursor_pos(&Position::Absolute(0), &Position::Relative(i64::from(n)))
             }
             Cursor::PrecedingLine(n) => {
                 self.set_cursor_pos(&Position::Absolute(0), &Position::Relative(-(i64::from(n))))
@@ -1484,12 +1841,18 @@ impl TerminalState {
             Sgr::StrikeThrough(strike) => {
                 self.pen.set_strikethrough(strike);
             }
+            Sgr::Overline(overline) => {
+                self.pen.set_overline(overline);
+            }
             Sgr::Foreground(col) => {
                 self.pen.set_foreground(col);
             }
             Sgr::Background(col) => {
                 self.pen.set_background(col);
             }
+            Sgr::UnderlineColor(col) => {
+                self.pen.set_underline_color(col);
+            }
             Sgr::Font(_) => {}
         }
     }
@@ -1532,8 +1895,6 @@ impl<'a> Performer<'a> {
             None => return,
         };
 
-        let mut x_offset = 0;
-
         for g in unicode_segmentation::UnicodeSegmentation::graphemes(p.as_str(), true) {
             let g = if self.dec_line_drawing_mode {
                 match g {
@@ -1554,7 +1915,7 @@ impl<'a> Performer<'a> {
                 g
             };
 
-            if !self.insert && self.wrap_next {
+            if self.wrap_next {
                 self.new_line(true);
             }
 
@@ -1566,29 +1927,29 @@ impl<'a> Performer<'a> {
 
             let print_width = unicode_column_width(g).max(1);
 
-            if !self.insert && x + print_width >= width {
+            if x + print_width >= width {
                 pen.set_wrapped(true);
             }
 
             let cell = Cell::new_grapheme(g, pen);
 
             if self.insert {
+                // Make room at the cursor, losing whatever is pushed past
+                // the right margin
                 let screen = self.screen_mut();
-                for _ in x..x + print_width as usize {
-                    screen.insert_cell(x + x_offset, y);
+                for _ in 0..print_width {
+                    screen.insert_cell(x, y);
                 }
             }
 
-            self.screen_mut().set_cell(x + x_offset, y, &cell);
+            self.screen_mut().set_cell(x, y, &cell);
 
             self.clear_selection_if_intersects(
                 x..x + print_width,
                 y as ScrollbackOrVisibleRowIndex,
             );
 
-            if self.insert {
-                x_offset += print_width;
-            } else if x + print_width < width {
+            if x + print_width < width {
                 self.cursor.x += print_width;
                 self.wrap_next = false;
             } else {
@@ -1625,7 +1986,7 @@ impl<'a> Performer<'a> {
                 self.set_cursor_pos(&Position::Relative(-1), &Position::Relative(0));
             }
             ControlCode::HorizontalTab => self.c0_horizontal_tab(),
-            ControlCode::Bell => {}
+            ControlCode::Bell => self.host.alert(Alert::Bell),
             _ => {}
         }
     }
@@ -1673,12 +2034,18 @@ impl<'a> Performer<'a> {
     fn osc_dispatch(&mut self, osc: OperatingSystemCommand) {
         self.flush_print();
         match osc {
-            OperatingSystemCommand::SetIconNameAndWindowTitle(title)
-            | OperatingSystemCommand::SetWindowTitle(title) => {
+            OperatingSystemCommand::SetIconNameAndWindowTitle(title) => {
+                self.icon_name = title.clone();
                 self.title = title.clone();
                 self.host.set_title(&title);
             }
-            OperatingSystemCommand::SetIconName(_) => {}
+            OperatingSystemCommand::SetWindowTitle(title) => {
+                self.title = title.clone();
+                self.host.set_title(&title);
+            }
+            OperatingSystemCommand::SetIconName(icon_name) => {
+                self.icon_name = icon_name;
+            }
             OperatingSystemCommand::SetHyperlink(link) => {
                 self.set_hyperlink(link);
             }
@@ -1690,21 +2057,33 @@ impl<'a> Performer<'a> {
                 }
             }
 
-            OperatingSystemCommand::ClearSelection(_) => {
-                if let Ok(clip) = self.host.get_clipboard() {
-                    clip.set_contents(None).ok();
-                }
+            OperatingSystemCommand::ClearSelection(selection) => {
+                self.host.clipboard_request(ClipboardRequest::Set { selection, data: None });
             }
-            OperatingSystemCommand::QuerySelection(_) => {}
-            OperatingSystemCommand::SetSelection(_, selection_data) => {
-                if let Ok(clip) = self.host.get_clipboard() {
-                    match clip.set_contents(Some(selection_data)) {
-                        Ok(_) => (),
-                        Err(_) => {}
-                    }
+            OperatingSystemCommand::QuerySelection(selection) => {
+                self.host.clipboard_request(ClipboardRequest::Query { selection });
+            }
+            OperatingSystemCommand::SetSelection(selection, data) => {
+                self.host.clipboard_request(ClipboardRequest::Set { selection, data: Some(data) });
+            }
+            OperatingSystemCommand::FinalTermSemanticPrompt(mark) => {
+                self.perform_semantic_prompt(mark);
+            }
+            OperatingSystemCommand::SystemNotification(body) => {
+                self.host.alert(Alert::ToastNotification { title: None, body });
+            }
+            OperatingSystemCommand::RxvtExtension(params) => {
+                if params.len() >= 2 && params[0] == "notify" {
+                    let title = Some(params[1].clone());
+                    let body = params[2..].join(";");
+                    self.host.alert(Alert::ToastNotification { title, body });
                 }
             }
-            OperatingSystemCommand::SystemNotification(_) => {}
+            OperatingSystemCommand::CurrentWorkingDirectory(uri) => {
+                // A directory on another host is forgotten, so that the
+                // one of the local process is used instead
+                self.current_dir = parse_file_uri(&uri);
+            }
             OperatingSystemCommand::ChangeColorNumber(specs) => {
                 for pair in specs {
                     match pair.color {
@@ -1762,10 +2141,44 @@ impl<'a> Performer<'a> {
                             | DynamicColorNumber::TektronixCursorColor => {}
                         }
                     }
-                    idx += 1;
+                    idx = idx.saturating_add(1);
                 }
                 self.make_all_lines_dirty();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod conformance;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_uri() {
+        let home = Some(PathBuf::from("/home/me"));
+        assert_eq!(parse_file_uri("file:///home/me"), home);
+        assert_eq!(parse_file_uri("file://localhost/home/me"), home);
+        assert_eq!(parse_file_uri("file://LocalHost/home/me"), home);
+        let local = format!("file://{}/home/me", local_hostname().unwrap());
+        assert_eq!(parse_file_uri(&local), home);
+        assert_eq!(parse_file_uri("file://elsewhere.invalid/home/me"), None);
+        assert_eq!(parse_file_uri("file://localhost"), None);
+        assert_eq!(parse_file_uri("/home/me"), None);
+        assert_eq!(parse_file_uri("http://host/home/me"), None);
+    }
+
+    #[test]
+    fn file_uri_percent_decoding() {
+        assert_eq!(
+            parse_file_uri("file:///my%20files/%C3%A9t%c3%a9"),
+            Some(PathBuf::from("/my files/\u{e9}t\u{e9}"))
+        );
+        // Escapes that are cut short or not hex are kept as they are
+        assert_eq!(parse_file_uri("file:///100%"), Some(PathBuf::from("/100%")));
+        assert_eq!(parse_file_uri("file:///a%2"), Some(PathBuf::from("/a%2")));
+        assert_eq!(parse_file_uri("file:///a%zz"), Some(PathBuf::from("/a%zz")));
+    }
+}
@@ -0,0 +1,362 @@
+//! Golden snapshot tests for the escape sequence handling.
+//!
+//! Each case feeds its input to a small terminal and compares a textual
+//! rendering of the resulting state, and of everything the terminal asked
+//! of its host, against `snapshots/<case>.snap`.  Run the tests with
+//! `MIRO_UPDATE_SNAPSHOTS=1` to rewrite the snapshots after an intentional
+//! change, and review the diff before committing it.
+use super::*;
+use crate::core::color::ColorAttribute;
+use crate::core::hyperlink::Hyperlink;
+use crate::term::clipboard::{Clipboard, ClipboardRequest, ClipboardSelection};
+use crate::term::color::ColorPalette;
+use crate::term::Terminal;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const ROWS: usize = 4;
+const COLS: usize = 10;
+const SCROLLBACK: usize = 2;
+
+/// Records the replies written back to the pty and every request made of
+/// the host, in order.
+#[derive(Default)]
+struct MockHost {
+    replies: Vec<u8>,
+    events: Vec<String>,
+}
+
+impl TerminalHost for MockHost {
+    fn writer(&mut self) -> &mut dyn std::io::Write {
+        &mut self.replies
+    }
+
+    fn get_clipboard(&mut self) -> anyhow::Result<Arc<dyn Clipboard>> {
+        anyhow::bail!("there is no clipboard in the conformance tests")
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.events.push(format!("set_title {:?}", title));
+    }
+
+    fn click_link(&mut self, link: &Arc<Hyperlink>) {
+        self.events.push(format!("click_link {}", link.uri()));
+    }
+
+    fn alert(&mut self, alert: Alert) {
+        self.events.push(format!("alert {:?}", alert));
+    }
+
+    fn clipboard_request(&mut self, request: ClipboardRequest) {
+        let targets = ClipboardSelection::targets(request.selection());
+        self.events.push(match request {
+            ClipboardRequest::Set { data, .. } => format!("clipboard set {:?} {:?}", targets, data),
+            ClipboardRequest::Query { .. } => format!("clipboard query {:?}", targets),
+        });
+    }
+
+    fn window_request(&mut self, request: Window) {
+        self.events.push(format!("window {:?}", request));
+    }
+
+    fn paste_selection(&mut self, selection: ClipboardSelection) {
+        self.events.push(format!("paste {:?}", selection));
+    }
+}
+
+fn color(color: ColorAttribute) -> String {
+    match color {
+        ColorAttribute::TrueColorWithPaletteFallback(rgb, _)
+        | ColorAttribute::TrueColorWithDefaultFallback(rgb) => rgb.to_rgb_string(),
+        ColorAttribute::PaletteIndex(idx) => idx.to_string(),
+        ColorAttribute::Default => "default".to_string(),
+    }
+}
+
+fn describe(attrs: &CellAttributes) -> String {
+    let mut parts = vec![];
+    match attrs.intensity() {
+        Intensity::Normal => {}
+        Intensity::Bold => parts.push("bold".to_string()),
+        Intensity::Half => parts.push("half".to_string()),
+    }
+    if attrs.underline() != Underline::None {
+        parts.push(format!("underline={:?}", attrs.underline()));
+    }
+    if attrs.blink() != Blink::None {
+        parts.push(format!("blink={:?}", attrs.blink()));
+    }
+    let flags = [
+        (attrs.italic(), "italic"),
+        (attrs.reverse(), "reverse"),
+        (attrs.strikethrough(), "strikethrough"),
+        (attrs.invisible(), "invisible"),
+        (attrs.overline(), "overline"),
+        (attrs.wrapped(), "wrapped"),
+    ];
+    parts.extend(flags.iter().filter(|(set, _)| *set).map(|(_, name)| name.to_string()));
+    let colors =
+        [("fg", attrs.foreground), ("bg", attrs.background), ("ul", attrs.underline_color)];
+    parts.extend(
+        colors
+            .iter()
+            .filter(|(_, c)| *c != ColorAttribute::Default)
+            .map(|(name, c)| format!("{}={}", name, color(*c))),
+    );
+    if let Some(link) = &attrs.hyperlink {
+        parts.push(format!("link={}", link.uri()));
+    }
+    parts.join(" ")
+}
+
+fn modes(state: &TerminalState) -> Vec<&'static str> {
+    let modes = [
+        (state.insert, "insert"),
+        (state.wrap_next, "wrap_next"),
+        (state.application_cursor_keys, "application_cursor_keys"),
+        (state.application_keypad, "application_keypad"),
+        (state.bracketed_paste, "bracketed_paste"),
+        (state.focus_tracking, "focus_tracking"),
+        (state.sgr_mouse, "sgr_mouse"),
+        (state.button_event_mouse, "button_event_mouse"),
+        (!state.cursor_visible, "hidden_cursor"),
+        (state.dec_line_drawing_mode, "dec_line_drawing"),
+        (state.screen.is_alt_screen_active(), "alt_screen"),
+    ];
+    modes.iter().filter(|(set, _)| *set).map(|(_, name)| *name).collect()
+}
+
+fn palette_changes(palette: &ColorPalette) -> Vec<String> {
+    let default = ColorPalette::default();
+    let mut changes: Vec<String> = (0..256)
+        .filter(|&idx| palette.colors.0[idx] != default.colors.0[idx])
+        .map(|idx| format!("color {} {}", idx, palette.colors.0[idx].to_rgb_string()))
+        .collect();
+    let named = [
+        ("foreground", palette.foreground, default.foreground),
+        ("background", palette.background, default.background),
+        ("cursor_bg", palette.cursor_bg, default.cursor_bg),
+        ("selection_fg", palette.selection_fg, default.selection_fg),
+        ("selection_bg", palette.selection_bg, default.selection_bg),
+    ];
+    changes.extend(
+        named
+            .iter()
+            .filter(|(_, color, default)| color != default)
+            .map(|(name, color, _)| format!("{} {}", name, color.to_rgb_string())),
+    );
+    changes
+}
+
+fn section(out: &mut String, name: &str, entries: &[String]) {
+    if !entries.is_empty() {
+        writeln!(out, "{}:", name).unwrap();
+        for entry in entries {
+            writeln!(out, "  {}", entry).unwrap();
+        }
+    }
+}
+
+/// Renders everything observable about `state` and `host`.  Sections
+/// that still hold their initial values are left out to keep the
+/// snapshots focused on what each case exercises.
+fn render(input: &[u8], state: &TerminalState, host: &MockHost) -> String {
+    let mut out = String::new();
+    let screen = state.screen();
+    let first_visible = (screen.num_lines() - screen.physical_rows) as i64;
+    let row = |idx: usize| idx as i64 - first_visible;
+
+    writeln!(out, "input: {:?}", String::from_utf8_lossy(input)).unwrap();
+    writeln!(out, "screen:").unwrap();
+    for (idx, line) in screen.lines(0..screen.num_lines()).enumerate() {
+        writeln!(out, "{:>2}|{}", row(idx), line.as_str().trim_end()).unwrap();
+    }
+    writeln!(out, "cursor: {},{}", state.cursor.x, state.cursor.y).unwrap();
+
+    let modes = modes(state);
+    if !modes.is_empty() {
+        writeln!(out, "modes: {}", modes.join(" ")).unwrap();
+    }
+    if state.scroll_region != (0..screen.physical_rows as VisibleRowIndex) {
+        writeln!(out, "scroll region: {:?}", state.scroll_region).unwrap();
+    }
+    if state.title != "miro" {
+        writeln!(out, "title: {:?}", state.title).unwrap();
+    }
+    if state.icon_name != "miro" {
+        writeln!(out, "icon name: {:?}", state.icon_name).unwrap();
+    }
+    if !state.title_stack.is_empty() {
+        writeln!(out, "title stack: {}", state.title_stack.len()).unwrap();
+    }
+    if let Some(dir) = &state.current_dir {
+        writeln!(out, "cwd: {}", dir.display()).unwrap();
+    }
+
+    let mut attributes = vec![];
+    let mut semantic = vec![];
+    for (idx, line) in screen.lines(0..screen.num_lines()).enumerate() {
+        for cluster in line.cluster() {
+            if cluster.attrs != CellAttributes::default() {
+                attributes.push(format!(
+                    "{}:{} {:?} {}",
+                    row(idx),
+                    cluster.byte_to_cell_idx[0],
+                    cluster.text,
+                    describe(&cluster.attrs)
+                ));
+            }
+        }
+        if line.semantic_type() != SemanticType::Output || line.exit_code().is_some() {
+            let mut entry = format!("{}: {:?}", row(idx), line.semantic_type());
+            if let Some(code) = line.exit_code() {
+                write!(entry, " exit {}", code).unwrap();
+            }
+            semantic.push(entry);
+        }
+    }
+    section(&mut out, "attributes", &attributes);
+    section(&mut out, "semantic", &semantic);
+    section(&mut out, "palette", &palette_changes(state.palette()));
+
+    if !host.replies.is_empty() {
+        writeln!(out, "replies: {:?}", String::from_utf8_lossy(&host.replies)).unwrap();
+    }
+    section(&mut out, "events", &host.events);
+    out
+}
+
+fn snapshot_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src/term/terminalstate/snapshots")
+        .join(format!("{}.snap", name))
+}
+
+fn check(name: &str, input: &[u8]) {
+    let mut terminal = Terminal::new(ROWS, COLS, 80, 64, SCROLLBACK, vec![]);
+    terminal.set_title_reporting(true);
+    let mut host = MockHost::default();
+    terminal.advance_bytes(input, &mut host);
+    let actual = render(input, &terminal, &host);
+
+    let path = snapshot_path(name);
+    if std::env::var_os("MIRO_UPDATE_SNAPSHOTS").is_some() {
+        std::fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!("reading {}: {}; run with MIRO_UPDATE_SNAPSHOTS=1 to create it", path.display(), err)
+    });
+    if actual != expected {
+        panic!(
+            "{} does not match {}\n--- expected\n{}--- actual\n{}",
+            name,
+            path.display(),
+            expected,
+            actual
+        );
+    }
+}
+
+macro_rules! snapshots {
+    ($($name:ident: $input:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                check(stringify!($name), $input);
+            }
+        )*
+    };
+}
+
+snapshots! {
+    // Printing and C0 controls
+    print: b"hello",
+    wrap: b"0123456789ab",
+    newline_scroll: b"a\r\nb\r\nc\r\nd\r\ne",
+    scrollback_limit: b"1\r\n2\r\n3\r\n4\r\n5\r\n6\r\n7",
+    controls: b"ab\x08c\tX\x07",
+    wide_chars: "\u{4e2d}a".as_bytes(),
+
+    // CSI cursor movement
+    cursor_position: b"\x1b[2;3HA\x1b[HB\x1b[4;10fC",
+    cursor_relative: b"\x1b[3;5H\x1b[2AU\x1b[BD\x1b[3DL\x1b[4CR",
+    cursor_clamp: b"\x1b[99;99H\x1b[99A\x1b[99Dx",
+    cursor_absolute: b"\x1b[5Ga\x1b[3db\x1b[2`c\x1b[2ad\x1b[2je\x1b[ef\x1b[kg",
+    next_preceding_line: b"ab\x1b[2Ec\x1b[Fd",
+    save_restore_cursor: b"\x1b[2;2H\x1b[s\x1b[4;4Hx\x1b[uy\x1b7\x1b[Hz\x1b8w",
+    tabs: b"ab\x1bH\r\tX\x1b[2IY",
+
+    // CSI editing
+    erase_in_line: b"0123456789\x1b[1;5H\x1b[K\x1b[2;1Habcdef\x1b[2;3H\x1b[1K\x1b[3;1Hxyz\x1b[2K",
+    erase_below: b"aaaa\r\nbbbb\r\ncccc\r\ndddd\x1b[2;3H\x1b[J",
+    erase_above: b"aaaa\r\nbbbb\r\ncccc\x1b[2;3H\x1b[1J",
+    erase_display: b"aaaa\r\nbbbb\x1b[2J\x1b[3J",
+    erase_with_background: b"ab\x1b[44m\x1b[K",
+    insert_delete_characters: b"abcdef\x1b[1;2H\x1b[2@\x1b[2;1Habcdef\x1b[2;2H\x1b[2P\x1b[3;1Habcdef\x1b[3;2H\x1b[2X",
+    insert_line: b"1\r\n2\r\n3\r\n4\x1b[2;1H\x1b[L",
+    delete_line: b"1\r\n2\r\n3\r\n4\x1b[2;1H\x1b[2M",
+    scroll_up: b"1\r\n2\r\n3\r\n4\x1b[S",
+    scroll_down: b"1\r\n2\r\n3\r\n4\x1b[2T",
+    scroll_region: b"\x1b[2;3r1\r\n2\r\n3\r\n4",
+    repeat: b"ab\x1b[3b",
+
+    // CSI modes and device reports
+    insert_mode: b"abc\r\x1b[4hXY\x1b[4lZ",
+    dec_modes: b"\x1b[?1h\x1b[?25l\x1b[?1000h\x1b[?1002h\x1b[?1004h\x1b[?1006h\x1b[?2004h\x1b[?12h\x1b=",
+    dec_modes_reset: b"\x1b[?1h\x1b[?2004h\x1b=\x1b[?1l\x1b[?2004l\x1b>\x1b[?9999h\x1b[?2004s\x1b[?2004r",
+    alt_screen_1049: b"main\x1b[?1049halt\x1b[?1049l",
+    alt_screen_47: b"main\x1b[?47halt",
+    device_reports: b"\x1b[?62;22c\x1b[c\x1b[>c\x1b[5n\x1b[3;5H\x1b[6n",
+    soft_reset: b"\x1b[1;31m\x1b[!pa",
+
+    // SGR
+    sgr_attributes: b"\x1b[1ma\x1b[2mb\x1b[3;4mc\x1b[0;5;7md\x1b[0;8;9me\x1b[0;53mf\x1b[0;4:3mg\x1b[0;21mh\x1b[mi",
+    sgr_colors: b"\x1b[31ma\x1b[42mb\x1b[0;91mc\x1b[38;5;200md\x1b[0;38;2;1;2;3me\x1b[0;48;2;255;0;16mf\x1b[0;58;5;9mg\x1b[0mh",
+
+    // XTWINOPS and DECRQCRA
+    window_reports: b"\x1b[18t\x1b[14t\x1b[16t\x1b[21t\x1b[20t",
+    window_requests: b"\x1b[2t\x1b[1t\x1b[3;10;20t\x1b[8;24;80t\x1b[4;100;200t\x1b[5t\x1b[6t\x1b[7t\x1b[9;1t\x1b[10;2t\x1b[11t\x1b[13t\x1b[19t",
+    title_stack: b"\x1b]2;one\x07\x1b[22t\x1b]0;two\x07\x1b[22;2t\x1b]2;three\x07\x1b[23;2t\x1b[23t",
+    checksum: b"AB\x1b[1;0;1;1;1;2*y",
+
+    // Sequences that are parsed but have no effect
+    ignored_sequences: b"\x1b[<0;3;4M\x1b[99z\x1b[1 q\x1b[2Z\x1bN\x1b#8\x1b\\\x1bP1$qm\x1b\\\x1b]999;x\x07\x1b]3;a=b\x07ok",
+
+    // Esc
+    dec_line_drawing: b"\x1b(0lqk\x1b(Bx",
+    esc_index: b"a\x1bDb\x1bEc\x1bMd",
+    reverse_index_scroll: b"1\r\n2\x1b[H\x1bMx",
+
+    // OSC
+    osc_titles: b"\x1b]0;both\x07\x1b]1;icon\x07\x1b]2;win\x07",
+    osc_hyperlink: b"\x1b]8;;http://a.b\x07link\x1b]8;;\x07 x",
+    osc_clipboard: b"\x1b]52;c;aGVsbG8=\x07\x1b]52;p;?\x07\x1b]52;c\x07",
+    osc_notifications: b"\x1b]9;hi there\x07\x1b]777;notify;Title;a;b\x07",
+    osc_cwd: b"\x1b]7;file://localhost/tmp/a%20b\x07",
+    osc_cwd_remote: b"\x1b]7;file:///tmp/a\x07\x1b]7;file://elsewhere.invalid/tmp/b\x07",
+    osc_semantic_prompt: b"\x1b]133;A\x07$ \x1b]133;B\x07ls\r\n\x1b]133;C\x07out\r\n\x1b]133;D;1\x07\x1b]133;A\x07$ ",
+    osc_colors: b"\x1b]4;1;#102030\x07\x1b]4;1;?\x07\x1b]10;#aabbcc\x07\x1b]11;?\x07\x1b]17;red\x07",
+
+    // Inputs that used to crash or hang the terminal
+    empty_mode_params: b"\x1b[?h\x1b[h\x1b[?lok",
+    huge_counts: b"\x1b[4294967295I\x1b[4294967295Ex\x1b[4294967295b",
+    insert_mode_overflow: "\x1b[4h0123456789abc\u{4e2d}".as_bytes(),
+    checksum_out_of_range: b"AB\x1b[1;0;1;1;99;99*y",
+    osc_invalid_colors: "\x1b]10;\x07\x1b]4;1;\x07\x1b]11;#\u{e9}\u{e9}\u{e9}\x07".as_bytes(),
+}
+
+#[test]
+fn reset_modes() {
+    let mut terminal = Terminal::new(ROWS, COLS, 80, 64, SCROLLBACK, vec![]);
+    let mut host = MockHost::default();
+    let input =
+        b"$ vim\x1b[?1049h\x1b[?2004h\x1b[?1h\x1b=\x1b[?1002h\x1b[?25l\x1b[2;3r\x1b[1m\x1b[22t~";
+    terminal.advance_bytes(input, &mut host);
+    terminal.reset_modes();
+    terminal.advance_bytes(b"x", &mut host);
+
+    let expected = "input: \"\"\nscreen:\n 0|$ vimx\n 1|\n 2|\n 3|\ncursor: 6,0\n";
+    assert_eq!(render(b"", &terminal, &host), expected);
+}
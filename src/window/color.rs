@@ -1,5 +1,5 @@
 use crate::window::Operator;
-use palette::{Blend, LinSrgb, LinSrgba, Srgb, Srgba};
+use palette::{Blend, Lab, Limited, LinSrgb, LinSrgba, Srgb, Srgba};
 
 lazy_static::lazy_static! {
     static ref SRGB_TO_F32_TABLE: [f32;256] = generate_srgb8_to_linear_f32_table();
@@ -164,6 +164,61 @@ impl Color {
         c.into_format().into_components()
     }
 
+    pub fn relative_luminance(self) -> f32 {
+        let c: LinSrgb = self.into();
+        0.2126 * c.red + 0.7152 * c.green + 0.0722 * c.blue
+    }
+
+    pub fn contrast_ratio(self, other: Color) -> f32 {
+        let a = self.relative_luminance();
+        let b = other.relative_luminance();
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// Adjusts the lightness of this color in Lab space, keeping its hue
+    /// and chroma, until it reaches `min_ratio` contrast against `bg`.
+    pub fn ensure_contrast(self, bg: Color, min_ratio: f32) -> Color {
+        if self.contrast_ratio(bg) >= min_ratio {
+            return self;
+        }
+
+        let lab = Lab::from(LinSrgb::from(self));
+        let with_lightness = |l: f32| -> Color {
+            let mut lab = lab;
+            lab.l = l;
+            Srgb::from_linear(LinSrgb::from(lab).clamp()).into()
+        };
+        let search = |target: f32| -> Option<Color> {
+            if with_lightness(target).contrast_ratio(bg) < min_ratio {
+                return None;
+            }
+            let (mut lo, mut hi) = (lab.l, target);
+            for _ in 0..16 {
+                let mid = (lo + hi) / 2.0;
+                if with_lightness(mid).contrast_ratio(bg) >= min_ratio {
+                    hi = mid;
+                } else {
+                    lo = mid;
+                }
+            }
+            Some(with_lightness(hi))
+        };
+
+        let (first, second) = if self.relative_luminance() >= bg.relative_luminance() {
+            (100.0, 0.0)
+        } else {
+            (0.0, 100.0)
+        };
+        search(first).or_else(|| search(second)).unwrap_or_else(|| {
+            let (a, b) = (with_lightness(first), with_lightness(second));
+            if a.contrast_ratio(bg) >= b.contrast_ratio(bg) {
+                a
+            } else {
+                b
+            }
+        })
+    }
+
     #[inline]
     pub fn composite(self, dest: Color, operator: Operator) -> Color {
         match operator {
@@ -192,3 +247,36 @@ impl Color {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn contrast_ratio() {
+        let black = Color::rgb(0, 0, 0);
+        let white = Color::rgb(0xff, 0xff, 0xff);
+        assert!((black.contrast_ratio(white) - 21.0).abs() < 0.01);
+        assert!((white.contrast_ratio(black) - 21.0).abs() < 0.01);
+        assert!((white.contrast_ratio(white) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn ensure_contrast() {
+        let black = Color::rgb(0, 0, 0);
+
+        let white = Color::rgb(0xff, 0xff, 0xff);
+        assert_eq!(white.ensure_contrast(black, 4.5), white);
+
+        let blue = Color::rgb(0, 0, 0x60);
+        let adjusted = blue.ensure_contrast(black, 4.5);
+        assert!(adjusted.contrast_ratio(black) >= 4.5);
+        let (red, green, blue, _) = adjusted.as_rgba();
+        assert!(blue > red && blue > green);
+
+        // Nothing reaches 21:1 against gray, so settle for the best there is
+        let gray = Color::rgb(0x80, 0x80, 0x80);
+        let adjusted = Color::rgb(0x70, 0x70, 0x70).ensure_contrast(gray, 21.0);
+        assert!(adjusted.contrast_ratio(gray) > 5.0);
+    }
+}
@@ -5,6 +5,9 @@ use std::rc::Rc;
 
 pub const FPS: u32 = 60;
 
+/// Receives the contents of a selection once they have been fetched.
+pub type SelectionCallback = Box<dyn FnOnce(anyhow::Result<String>)>;
+
 thread_local! {
     static CONN: RefCell<Option<Rc<Connection>>> = RefCell::new(None);
 }
@@ -30,4 +33,12 @@ pub trait ConnectionOps {
     fn terminate_message_loop(&self);
     fn run_message_loop(&self) -> anyhow::Result<()>;
     fn schedule_timer<F: FnMut() + 'static>(&self, interval: std::time::Duration, callback: F);
+
+    /// Fetches the primary selection and passes it to `done`, which runs
+    /// from the event loop if another client has to be asked for it.
+    fn get_primary_selection(&self, done: SelectionCallback) {
+        done(Err(anyhow::anyhow!("the primary selection is not supported on this system")))
+    }
+
+    fn set_primary_selection(&self, _text: Option<String>) {}
 }
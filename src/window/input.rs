@@ -69,6 +69,27 @@ bitflags! {
         const X2 = 1<<4;
     }
 }
+bitflags! {
+    /// How the input method asks for a character of the preedit text to
+    /// be drawn.
+    #[derive(Default)]
+    pub struct PreeditFeedback: u8 {
+        const NONE = 0;
+        #[allow(clippy::identity_op)]
+        const REVERSE = 1<<0;
+        const UNDERLINE = 1<<1;
+        const HIGHLIGHT = 1<<2;
+    }
+}
+
+/// The text that the input method is composing, which is shown at the
+/// cursor until it is committed.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Preedit {
+    pub text: Vec<(char, PreeditFeedback)>,
+    /// The index of the character that the caret is in front of.
+    pub caret: usize,
+}
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq, Eq)]
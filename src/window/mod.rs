@@ -43,12 +43,28 @@ pub enum MouseCursor {
     Text,
 }
 
+/// A change to the state of a window as it is managed by the window
+/// manager, rather than its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowStateChange {
+    Iconify,
+    DeIconify,
+    Raise,
+    Lower,
+    Maximize { horizontal: bool, vertical: bool },
+    RestoreMaximized,
+    FullScreen,
+    UndoFullScreen,
+    ToggleFullScreen,
+}
+
 #[allow(unused_variables)]
 pub trait WindowCallbacks: Any {
     fn can_close(&self) -> bool {
         true
     }
     fn focus_change(&mut self, focused: bool);
+    fn ime_preedit(&mut self, preedit: Option<Preedit>) {}
     fn destroy(&mut self) {}
     fn resize(&mut self, dimensions: Dimensions) {}
     fn paint(&mut self, frame: &mut glium::Frame) {}
@@ -76,6 +92,20 @@ pub trait WindowOps {
     fn set_title(&self, title: &str);
     fn set_inner_size(&self, width: usize, height: usize);
     fn set_text_cursor_position(&self, _cursor: Rect) {}
+    fn set_urgent(&self, _urgent: bool) {}
+    fn set_window_position(&self, _x: isize, _y: isize) {}
+    fn change_state(&self, _change: WindowStateChange) {}
+    /// The position of the window's client area on the screen.
+    fn get_window_position(&self) -> Option<Point> {
+        None
+    }
+    /// The size of the screen that holds the window, in pixels.
+    fn get_screen_size(&self) -> Option<Size> {
+        None
+    }
+    fn is_iconified(&self) -> bool {
+        false
+    }
     fn apply<F: Send + 'static + Fn(&mut dyn Any, &dyn WindowOps)>(&self, func: F)
     where
         Self: Sized;
@@ -89,4 +119,7 @@ pub trait WindowOpsMut {
     fn set_title(&mut self, title: &str);
     fn set_inner_size(&self, width: usize, height: usize);
     fn set_text_cursor_position(&mut self, _cursor: Rect) {}
+    fn set_urgent(&mut self, _urgent: bool) {}
+    fn set_window_position(&mut self, _x: isize, _y: isize) {}
+    fn change_state(&mut self, _change: WindowStateChange) {}
 }
@@ -1,12 +1,13 @@
 use super::keyboard::Keyboard;
+use super::xim::InputMethod;
 use crate::core::promise;
-use crate::window::connection::{ConnectionOps, FPS};
+use crate::window::connection::{ConnectionOps, SelectionCallback, FPS};
 use crate::window::os::x11::WindowInner;
 use crate::window::spawn::SPAWN_QUEUE;
 use anyhow::{anyhow, bail};
 use mio::unix::EventedFd;
 use mio::{Evented, Events, Poll, PollOpt, Ready, Token};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
 use std::collections::{HashMap, VecDeque};
 use std::os::unix::io::AsRawFd;
@@ -78,6 +79,16 @@ impl TimerList {
     }
 }
 
+/// How long to wait for the owner of the primary selection to answer.
+const PRIMARY_SELECTION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Callbacks waiting for the owner of the primary selection to convert it
+/// for us, answered when the SelectionNotify event arrives.
+struct PrimaryRequests {
+    deadline: Instant,
+    callbacks: Vec<SelectionCallback>,
+}
+
 pub struct Connection {
     pub display: *mut x11::xlib::Display,
     conn: xcb::Connection,
@@ -91,11 +102,18 @@ pub struct Connection {
     pub atom_xsel_data: xcb::Atom,
     pub atom_targets: xcb::Atom,
     pub atom_clipboard: xcb::Atom,
+    selection_window: xcb::xproto::Window,
+    primary_text: RefCell<Option<String>>,
+    primary_requests: RefCell<Option<PrimaryRequests>>,
+    /// The time of the last key or button event, which is when the
+    /// selection made by that event is taken to have been made.
+    last_event_time: Cell<xcb::Timestamp>,
     keysyms: *mut xcb_key_symbols_t,
     pub(crate) windows: RefCell<HashMap<xcb::xproto::Window, Arc<Mutex<WindowInner>>>>,
     should_terminate: RefCell<bool>,
     timers: RefCell<TimerList>,
     pub(crate) visual: xcb::xproto::Visualtype,
+    pub(crate) xim: Option<InputMethod>,
 }
 
 impl std::ops::Deref for Connection {
@@ -176,6 +194,24 @@ fn window_id_from_event(event: &xcb::GenericEvent) -> Option<xcb::xproto::Window
     }
 }
 
+fn time_from_event(event: &xcb::GenericEvent) -> Option<xcb::Timestamp> {
+    match event.response_type() & 0x7f {
+        xcb::KEY_PRESS | xcb::KEY_RELEASE => {
+            let key_press: &xcb::KeyPressEvent = unsafe { xcb::cast_event(event) };
+            Some(key_press.time())
+        }
+        xcb::MOTION_NOTIFY => {
+            let motion: &xcb::MotionNotifyEvent = unsafe { xcb::cast_event(event) };
+            Some(motion.time())
+        }
+        xcb::BUTTON_PRESS | xcb::BUTTON_RELEASE => {
+            let button_press: &xcb::ButtonPressEvent = unsafe { xcb::cast_event(event) };
+            Some(button_press.time())
+        }
+        _ => None,
+    }
+}
+
 impl ConnectionOps for Connection {
     fn terminate_message_loop(&self) {
         *self.should_terminate.borrow_mut() = true;
@@ -198,6 +234,7 @@ impl ConnectionOps for Connection {
 
         while !*self.should_terminate.borrow() {
             self.timers.borrow_mut().run_ready();
+            self.expire_primary_requests();
 
             let now = Instant::now();
             let diff = now - last_interval;
@@ -240,6 +277,61 @@ impl ConnectionOps for Connection {
             interval,
         });
     }
+
+    fn get_primary_selection(&self, done: SelectionCallback) {
+        let owned = self.primary_text.borrow().clone();
+        if let Some(text) = owned {
+            return done(Ok(text));
+        }
+
+        let mut requests = self.primary_requests.borrow_mut();
+        if let Some(requests) = requests.as_mut() {
+            requests.callbacks.push(done);
+            return;
+        }
+        *requests = Some(PrimaryRequests {
+            deadline: Instant::now() + PRIMARY_SELECTION_TIMEOUT,
+            callbacks: vec![done],
+        });
+
+        xcb::convert_selection(
+            &self.conn,
+            self.selection_window,
+            xcb::ATOM_PRIMARY,
+            self.atom_utf8_string,
+            self.atom_xsel_data,
+            xcb::CURRENT_TIME,
+        );
+        self.conn.flush();
+    }
+
+    fn set_primary_selection(&self, text: Option<String>) {
+        let time = self.last_event_time.get();
+        match text {
+            Some(text) => {
+                xcb::set_selection_owner(
+                    &self.conn,
+                    self.selection_window,
+                    xcb::ATOM_PRIMARY,
+                    time,
+                );
+                // The server ignores the request if another client took the
+                // selection after the event that made ours.
+                let owned =
+                    match xcb::get_selection_owner(&self.conn, xcb::ATOM_PRIMARY).get_reply() {
+                        Ok(reply) => reply.owner() == self.selection_window,
+                        Err(_) => false,
+                    };
+                *self.primary_text.borrow_mut() = if owned { Some(text) } else { None };
+            }
+            None => {
+                if self.primary_text.borrow_mut().take().is_some() {
+                    xcb::set_selection_owner(&self.conn, xcb::NONE, xcb::ATOM_PRIMARY, time);
+                }
+            }
+        }
+        self.conn.flush();
+    }
 }
 
 impl Connection {
@@ -269,14 +361,149 @@ impl Connection {
     }
 
     fn process_xcb_event(&self, event: &xcb::GenericEvent) -> anyhow::Result<()> {
-        if let Some(window_id) = window_id_from_event(event) {
+        if let Some(time) = time_from_event(event) {
+            self.last_event_time.set(time);
+        }
+
+        if let Some(xim) = self.xim.as_ref() {
+            let filtered = xim.filter_event(event);
+            self.process_ime_events(xim)?;
+            if filtered {
+                return Ok(());
+            }
+        }
+
+        let r = event.response_type() & 0x7f;
+        if r == xcb::SELECTION_REQUEST {
+            let request: &xcb::SelectionRequestEvent = unsafe { xcb::cast_event(event) };
+            self.process_selection_request(request);
+        } else if r == xcb::SELECTION_NOTIFY {
+            let notify: &xcb::SelectionNotifyEvent = unsafe { xcb::cast_event(event) };
+            if notify.requestor() == self.selection_window
+                && notify.selection() == xcb::ATOM_PRIMARY
+            {
+                self.process_selection_notify(notify);
+            }
+        } else if r == xcb::SELECTION_CLEAR {
+            let clear: &xcb::SelectionClearEvent = unsafe { xcb::cast_event(event) };
+            if clear.selection() == xcb::ATOM_PRIMARY {
+                self.primary_text.borrow_mut().take();
+            }
+        } else if let Some(window_id) = window_id_from_event(event) {
             self.process_window_event(window_id, event)?;
-        } else {
-            let r = event.response_type() & 0x7f;
-            if r == self.kbd_ev {
-                self.keyboard.process_xkb_event(&self.conn, event)?;
+        } else if r == self.kbd_ev {
+            self.keyboard.process_xkb_event(&self.conn, event)?;
+        }
+        Ok(())
+    }
+
+    /// Answers another client asking for the contents of the primary
+    /// selection that we currently own.
+    fn process_selection_request(&self, request: &xcb::SelectionRequestEvent) {
+        // Obsolete clients leave the property unset and expect the target
+        // to be used in its place.
+        let property =
+            if request.property() == xcb::NONE { request.target() } else { request.property() };
+
+        let primary_text = self.primary_text.borrow();
+        let reply_property = match primary_text.as_ref() {
+            Some(text) if request.selection() == xcb::ATOM_PRIMARY => {
+                if request.target() == self.atom_targets {
+                    xcb::change_property(
+                        &self.conn,
+                        xcb::PROP_MODE_REPLACE as u8,
+                        request.requestor(),
+                        property,
+                        xcb::ATOM_ATOM,
+                        32,
+                        &[self.atom_targets, self.atom_utf8_string, xcb::ATOM_STRING],
+                    );
+                    property
+                } else if request.target() == self.atom_utf8_string
+                    || request.target() == xcb::ATOM_STRING
+                {
+                    xcb::change_property(
+                        &self.conn,
+                        xcb::PROP_MODE_REPLACE as u8,
+                        request.requestor(),
+                        property,
+                        request.target(),
+                        8,
+                        text.as_bytes(),
+                    );
+                    property
+                } else {
+                    xcb::NONE
+                }
+            }
+            _ => xcb::NONE,
+        };
+
+        let notify = xcb::SelectionNotifyEvent::new(
+            request.time(),
+            request.requestor(),
+            request.selection(),
+            request.target(),
+            reply_property,
+        );
+        xcb::send_event(&self.conn, false, request.requestor(), 0, &notify);
+        self.conn.flush();
+    }
+
+    /// Hands the converted primary selection to everyone waiting for it.
+    fn process_selection_notify(&self, notify: &xcb::SelectionNotifyEvent) {
+        let requests = self.primary_requests.borrow_mut().take();
+        let result = self.read_selection_property(notify.property());
+        for done in requests.map(|r| r.callbacks).unwrap_or_default() {
+            done(match &result {
+                Ok(text) => Ok(text.clone()),
+                Err(err) => Err(anyhow!("reading the primary selection: {}", err)),
+            });
+        }
+    }
+
+    /// Gives up on a primary selection whose owner has not answered.
+    fn expire_primary_requests(&self) {
+        let expired = match self.primary_requests.borrow().as_ref() {
+            Some(requests) => Instant::now() >= requests.deadline,
+            None => false,
+        };
+        if expired {
+            let requests = self.primary_requests.borrow_mut().take().unwrap();
+            for done in requests.callbacks {
+                done(Err(anyhow!("timed out waiting for the primary selection")));
+            }
+        }
+    }
+
+    fn read_selection_property(&self, property: xcb::Atom) -> anyhow::Result<String> {
+        if property == xcb::NONE {
+            return Ok(String::new());
+        }
+        let reply = xcb::get_property(
+            &self.conn,
+            true,
+            self.selection_window,
+            property,
+            xcb::ATOM_ANY,
+            0,
+            u32::max_value() / 4,
+        )
+        .get_reply()?;
+        Ok(String::from_utf8_lossy(reply.value::<u8>()).into_owned())
+    }
+
+    fn process_ime_events(&self, xim: &InputMethod) -> anyhow::Result<()> {
+        for mut key_event in xim.take_key_events() {
+            if let Some(window) = self.window_by_id(key_event.window as xcb::xproto::Window) {
+                window.lock().unwrap().dispatch_ime_key_event(&mut key_event)?;
             }
         }
+
+        let windows: Vec<_> = self.windows.borrow().values().map(Arc::clone).collect();
+        for window in windows {
+            window.lock().unwrap().dispatch_ime_preedit();
+        }
         Ok(())
     }
 
@@ -333,12 +560,36 @@ impl Connection {
             })
             .nth(0)
             .ok_or_else(|| anyhow!("did not find 24-bit visual"))?;
+
+        let selection_window = conn.generate_id();
+        xcb::create_window(
+            &conn,
+            xcb::COPY_FROM_PARENT as u8,
+            selection_window,
+            screen.root(),
+            0,
+            0,
+            1,
+            1,
+            0,
+            xcb::WINDOW_CLASS_INPUT_ONLY as u16,
+            xcb::COPY_FROM_PARENT,
+            &[],
+        );
         eprintln!(
             "picked visual {:x}, screen root visual is {:x}",
             visual.visual_id(),
             screen.root_visual()
         );
 
+        let xim = match InputMethod::open(display) {
+            Ok(xim) => Some(xim),
+            Err(err) => {
+                eprintln!("input method is not available: {}", err);
+                None
+            }
+        };
+
         let (keyboard, kbd_ev) = Keyboard::new(&conn)?;
 
         let cursor_font_id = conn.generate_id();
@@ -359,10 +610,15 @@ impl Connection {
             atom_utf8_string,
             atom_xsel_data,
             atom_targets,
+            selection_window,
+            primary_text: RefCell::new(None),
+            primary_requests: RefCell::new(None),
+            last_event_time: Cell::new(xcb::CURRENT_TIME),
             windows: RefCell::new(HashMap::new()),
             should_terminate: RefCell::new(false),
             timers: RefCell::new(TimerList::new()),
             visual,
+            xim,
         };
 
         Ok(conn)
@@ -402,6 +658,7 @@ impl Connection {
 
 impl Drop for Connection {
     fn drop(&mut self) {
+        xcb::destroy_window(&self.conn, self.selection_window);
         unsafe {
             xcb_key_symbols_free(self.keysyms);
         }
@@ -1,6 +1,7 @@
 pub mod connection;
 pub mod keyboard;
 pub mod window;
+pub mod xim;
 pub mod xkeysyms;
 
 pub use self::window::*;
@@ -1,8 +1,9 @@
+use super::xim::InputContext;
 use super::*;
 use crate::window::connection::ConnectionOps;
 use crate::window::{
-    Dimensions, KeyEvent, MouseButtons, MouseCursor, MouseEvent, MouseEventKind, MousePress,
-    WindowCallbacks, WindowOps, WindowOpsMut,
+    Dimensions, KeyEvent, MouseButtons, MouseCursor, MouseEvent, MouseEventKind, MousePress, Point,
+    Rect, Size, WindowCallbacks, WindowOps, WindowOpsMut, WindowStateChange,
 };
 use anyhow::anyhow;
 use std::any::Any;
@@ -18,10 +19,12 @@ pub(crate) struct WindowInner {
     height: u16,
     cursor: Option<MouseCursor>,
     gl_state: Option<Rc<glium::backend::Context>>,
+    ime: Option<InputContext>,
 }
 
 impl Drop for WindowInner {
     fn drop(&mut self) {
+        self.ime.take();
         xcb::destroy_window(self.conn.conn(), self.window_id);
     }
 }
@@ -108,6 +111,30 @@ impl WindowInner {
         Ok(())
     }
 
+    pub fn dispatch_ime_key_event(
+        &mut self,
+        event: &mut x11::xlib::XKeyEvent,
+    ) -> anyhow::Result<()> {
+        let key = match self.ime.as_ref().and_then(|ime| ime.lookup_key(event)) {
+            Some((code, mods)) => KeyEvent {
+                key: code,
+                raw_key: None,
+                modifiers: mods,
+                repeat_count: 1,
+                key_is_down: true,
+            },
+            None => return Ok(()),
+        };
+        self.callbacks.key_event(&key, &Window::from_id(self.window_id));
+        Ok(())
+    }
+
+    pub fn dispatch_ime_preedit(&mut self) {
+        if let Some(preedit) = self.ime.as_ref().and_then(|ime| ime.take_preedit()) {
+            self.callbacks.ime_preedit(preedit);
+        }
+    }
+
     pub fn dispatch_event(&mut self, event: &xcb::GenericEvent) -> anyhow::Result<()> {
         let r = event.response_type() & 0x7f;
         match r {
@@ -195,9 +222,15 @@ impl WindowInner {
                 self.conn.windows.borrow_mut().remove(&self.window_id);
             }
             xcb::FOCUS_IN => {
+                if let Some(ime) = self.ime.as_ref() {
+                    ime.focus(true);
+                }
                 self.callbacks.focus_change(true);
             }
             xcb::FOCUS_OUT => {
+                if let Some(ime) = self.ime.as_ref() {
+                    ime.focus(false);
+                }
                 self.callbacks.focus_change(false);
             }
             _ => {
@@ -218,7 +251,7 @@ impl Window {
     }
 
     pub fn new_window(
-        _class_name: &str,
+        class_name: &str,
         name: &str,
         width: usize,
         height: usize,
@@ -276,6 +309,7 @@ impl Window {
                 height: height.try_into()?,
                 cursor: None,
                 gl_state: None,
+                ime: conn.xim.as_ref().and_then(|xim| xim.create_context(window_id)),
             }))
         };
 
@@ -289,6 +323,19 @@ impl Window {
             &[conn.atom_delete],
         );
 
+        // WM_CLASS holds the instance name and then the class, each
+        // terminated by a NUL.
+        let class = format!("{0}\0{0}\0", class_name);
+        xcb::change_property(
+            &*conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            window_id,
+            xcb::ATOM_WM_CLASS,
+            xcb::ATOM_STRING,
+            8,
+            class.as_bytes(),
+        );
+
         let window_handle = Window::from_id(window_id);
         window.lock().unwrap().enable_opengl()?;
         conn.windows.borrow_mut().insert(window_id, window.clone());
@@ -298,6 +345,47 @@ impl Window {
     }
 }
 
+impl WindowInner {
+    fn root(&self) -> Option<xcb::xproto::Window> {
+        self.conn.conn().get_setup().roots().nth(self.conn.screen_num() as usize).map(|s| s.root())
+    }
+
+    fn intern_atom(&self, name: &str) -> xcb::Atom {
+        xcb::intern_atom(self.conn.conn(), false, name)
+            .get_reply()
+            .map(|reply| reply.atom())
+            .unwrap_or(xcb::NONE)
+    }
+
+    fn send_to_root(&self, message_type: &str, data: [u32; 5]) {
+        let root = match self.root() {
+            Some(root) => root,
+            None => return,
+        };
+        let event = xcb::ClientMessageEvent::new(
+            32,
+            self.window_id,
+            self.intern_atom(message_type),
+            xcb::ClientMessageData::from_data32(data),
+        );
+        xcb::send_event(
+            self.conn.conn(),
+            false,
+            root,
+            xcb::EVENT_MASK_SUBSTRUCTURE_REDIRECT | xcb::EVENT_MASK_SUBSTRUCTURE_NOTIFY,
+            &event,
+        );
+    }
+
+    /// Asks the window manager to add (1), remove (0) or toggle (2) up
+    /// to two `_NET_WM_STATE` properties.
+    fn change_net_wm_state(&self, action: u32, first: &str, second: Option<&str>) {
+        let first = self.intern_atom(first);
+        let second = second.map(|name| self.intern_atom(name)).unwrap_or(xcb::NONE);
+        self.send_to_root("_NET_WM_STATE", [action, first, second, 1, 0]);
+    }
+}
+
 impl WindowOpsMut for WindowInner {
     fn close(&mut self) {
         xcb::destroy_window(self.conn.conn(), self.window_id);
@@ -323,6 +411,95 @@ impl WindowOpsMut for WindowInner {
     fn set_title(&mut self, title: &str) {
         xcb_util::icccm::set_wm_name(self.conn.conn(), self.window_id, title);
     }
+
+    fn set_text_cursor_position(&mut self, cursor: Rect) {
+        if let Some(ime) = self.ime.as_mut() {
+            ime.set_spot_location(cursor.min_x() as i16, cursor.max_y() as i16);
+        }
+    }
+
+    fn set_urgent(&mut self, urgent: bool) {
+        const URGENCY_HINT: u32 = 1 << 8;
+        const NUM_HINTS: usize = 9;
+        // Keep whatever else has been hinted and only flip the urgency bit.
+        let mut hints = xcb::get_property(
+            self.conn.conn(),
+            false,
+            self.window_id,
+            xcb::ATOM_WM_HINTS,
+            xcb::ATOM_WM_HINTS,
+            0,
+            NUM_HINTS as u32,
+        )
+        .get_reply()
+        .map(|reply| reply.value::<u32>().to_vec())
+        .unwrap_or_default();
+        hints.resize(NUM_HINTS, 0);
+        if urgent {
+            hints[0] |= URGENCY_HINT;
+        } else {
+            hints[0] &= !URGENCY_HINT;
+        }
+        xcb::change_property(
+            self.conn.conn(),
+            xcb::PROP_MODE_REPLACE as u8,
+            self.window_id,
+            xcb::ATOM_WM_HINTS,
+            xcb::ATOM_WM_HINTS,
+            32,
+            &hints,
+        );
+    }
+
+    fn set_window_position(&mut self, x: isize, y: isize) {
+        xcb::configure_window(
+            self.conn.conn(),
+            self.window_id,
+            &[
+                (xcb::CONFIG_WINDOW_X as u16, x as i32 as u32),
+                (xcb::CONFIG_WINDOW_Y as u16, y as i32 as u32),
+            ],
+        );
+    }
+
+    fn change_state(&mut self, change: WindowStateChange) {
+        const ICONIC_STATE: u32 = 3;
+        const MAX_HORZ: &str = "_NET_WM_STATE_MAXIMIZED_HORZ";
+        const MAX_VERT: &str = "_NET_WM_STATE_MAXIMIZED_VERT";
+        const FULLSCREEN: &str = "_NET_WM_STATE_FULLSCREEN";
+        match change {
+            WindowStateChange::Iconify => {
+                self.send_to_root("WM_CHANGE_STATE", [ICONIC_STATE, 0, 0, 0, 0])
+            }
+            WindowStateChange::DeIconify => {
+                xcb::map_window(self.conn.conn(), self.window_id);
+            }
+            WindowStateChange::Raise | WindowStateChange::Lower => {
+                let mode = if change == WindowStateChange::Raise {
+                    xcb::STACK_MODE_ABOVE
+                } else {
+                    xcb::STACK_MODE_BELOW
+                };
+                xcb::configure_window(
+                    self.conn.conn(),
+                    self.window_id,
+                    &[(xcb::CONFIG_WINDOW_STACK_MODE as u16, mode)],
+                );
+            }
+            WindowStateChange::Maximize { horizontal, vertical } => match (horizontal, vertical) {
+                (true, true) => self.change_net_wm_state(1, MAX_HORZ, Some(MAX_VERT)),
+                (true, false) => self.change_net_wm_state(1, MAX_HORZ, None),
+                (false, true) => self.change_net_wm_state(1, MAX_VERT, None),
+                (false, false) => {}
+            },
+            WindowStateChange::RestoreMaximized => {
+                self.change_net_wm_state(0, MAX_HORZ, Some(MAX_VERT))
+            }
+            WindowStateChange::FullScreen => self.change_net_wm_state(1, FULLSCREEN, None),
+            WindowStateChange::UndoFullScreen => self.change_net_wm_state(0, FULLSCREEN, None),
+            WindowStateChange::ToggleFullScreen => self.change_net_wm_state(2, FULLSCREEN, None),
+        }
+    }
 }
 
 impl WindowOps for Window {
@@ -349,6 +526,42 @@ impl WindowOps for Window {
         Connection::with_window_inner(self.0, move |inner| inner.set_inner_size(width, height));
     }
 
+    fn set_text_cursor_position(&self, cursor: Rect) {
+        Connection::with_window_inner(self.0, move |inner| inner.set_text_cursor_position(cursor));
+    }
+
+    fn set_urgent(&self, urgent: bool) {
+        Connection::with_window_inner(self.0, move |inner| inner.set_urgent(urgent));
+    }
+
+    fn set_window_position(&self, x: isize, y: isize) {
+        Connection::with_window_inner(self.0, move |inner| inner.set_window_position(x, y));
+    }
+
+    fn change_state(&self, change: WindowStateChange) {
+        Connection::with_window_inner(self.0, move |inner| inner.change_state(change));
+    }
+
+    fn get_window_position(&self) -> Option<Point> {
+        let conn = Connection::get()?;
+        let root = conn.conn().get_setup().roots().nth(conn.screen_num() as usize)?.root();
+        let reply = xcb::translate_coordinates(conn.conn(), self.0, root, 0, 0).get_reply().ok()?;
+        Some(Point::new(reply.dst_x() as isize, reply.dst_y() as isize))
+    }
+
+    fn get_screen_size(&self) -> Option<Size> {
+        let conn = Connection::get()?;
+        let screen = conn.conn().get_setup().roots().nth(conn.screen_num() as usize)?;
+        Some(Size::new(screen.width_in_pixels() as isize, screen.height_in_pixels() as isize))
+    }
+
+    fn is_iconified(&self) -> bool {
+        Connection::get()
+            .and_then(|conn| xcb::get_window_attributes(conn.conn(), self.0).get_reply().ok())
+            .map(|attr| attr.map_state() == xcb::MAP_STATE_UNMAPPED as u8)
+            .unwrap_or(false)
+    }
+
     fn apply<F: Send + 'static + Fn(&mut dyn Any, &dyn WindowOps)>(&self, func: F)
     where
         Self: Sized,
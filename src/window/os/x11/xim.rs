@@ -0,0 +1,473 @@
+use super::xkeysyms::{keysym_to_keycode, modifiers_from_state};
+use crate::window::{KeyCode, Modifiers, Preedit, PreeditFeedback};
+use anyhow::ensure;
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_short, c_ulong, c_ushort, c_void};
+use x11::xlib::{Display, XEvent, XKeyEvent};
+
+type XIM = *mut c_void;
+type XIC = *mut c_void;
+type XPointer = *mut c_char;
+type WireToEventProc =
+    Option<unsafe extern "C" fn(*mut Display, *mut XEvent, *mut c_void) -> c_int>;
+
+const XIM_PREEDIT_CALLBACKS: c_ulong = 0x0002;
+const XIM_PREEDIT_NOTHING: c_ulong = 0x0008;
+const XIM_STATUS_NOTHING: c_ulong = 0x0400;
+
+const X_BUFFER_OVERFLOW: c_int = -1;
+const X_LOOKUP_CHARS: c_int = 2;
+const X_LOOKUP_KEYSYM: c_int = 3;
+const X_LOOKUP_BOTH: c_int = 4;
+
+const KEY_PRESS: c_int = 2;
+
+const XIM_REVERSE: c_ulong = 1;
+const XIM_UNDERLINE: c_ulong = 1 << 1;
+const XIM_HIGHLIGHT: c_ulong = 1 << 2;
+const XIM_PRIMARY: c_ulong = 1 << 5;
+const XIM_SECONDARY: c_ulong = 1 << 6;
+const XIM_TERTIARY: c_ulong = 1 << 7;
+
+const XIM_FORWARD_CHAR: c_int = 0;
+const XIM_BACKWARD_CHAR: c_int = 1;
+const XIM_LINE_START: c_int = 8;
+const XIM_LINE_END: c_int = 9;
+const XIM_ABSOLUTE_POSITION: c_int = 10;
+
+const XN_INPUT_STYLE: &[u8] = b"inputStyle\0";
+const XN_CLIENT_WINDOW: &[u8] = b"clientWindow\0";
+const XN_FOCUS_WINDOW: &[u8] = b"focusWindow\0";
+const XN_PREEDIT_ATTRIBUTES: &[u8] = b"preeditAttributes\0";
+const XN_SPOT_LOCATION: &[u8] = b"spotLocation\0";
+const XN_PREEDIT_START_CALLBACK: &[u8] = b"preeditStartCallback\0";
+const XN_PREEDIT_DONE_CALLBACK: &[u8] = b"preeditDoneCallback\0";
+const XN_PREEDIT_DRAW_CALLBACK: &[u8] = b"preeditDrawCallback\0";
+const XN_PREEDIT_CARET_CALLBACK: &[u8] = b"preeditCaretCallback\0";
+
+#[repr(C)]
+struct XIMCallback {
+    client_data: XPointer,
+    callback: *const c_void,
+}
+
+#[repr(C)]
+struct XIMText {
+    length: c_ushort,
+    feedback: *mut c_ulong,
+    encoding_is_wchar: c_int,
+    string: *mut c_char,
+}
+
+#[repr(C)]
+struct XIMPreeditDrawCallbackStruct {
+    caret: c_int,
+    chg_first: c_int,
+    chg_length: c_int,
+    text: *mut XIMText,
+}
+
+#[repr(C)]
+struct XIMPreeditCaretCallbackStruct {
+    position: c_int,
+    direction: c_int,
+    _style: c_int,
+}
+
+#[repr(C)]
+struct XPoint {
+    x: c_short,
+    y: c_short,
+}
+
+#[link(name = "X11")]
+extern "C" {
+    fn XSetLocaleModifiers(modifier_list: *const c_char) -> *mut c_char;
+    fn XOpenIM(
+        display: *mut Display,
+        db: *mut c_void,
+        res_name: *mut c_char,
+        res_class: *mut c_char,
+    ) -> XIM;
+    fn XCloseIM(im: XIM) -> c_int;
+    fn XCreateIC(im: XIM, ...) -> XIC;
+    fn XDestroyIC(ic: XIC);
+    fn XSetICFocus(ic: XIC);
+    fn XUnsetICFocus(ic: XIC);
+    fn XSetICValues(ic: XIC, ...) -> *mut c_char;
+    fn XVaCreateNestedList(unused: c_int, ...) -> *mut c_void;
+    fn XFree(data: *mut c_void) -> c_int;
+    fn XFilterEvent(event: *mut XEvent, window: c_ulong) -> c_int;
+    fn XCheckTypedEvent(display: *mut Display, event_type: c_int, event: *mut XEvent) -> c_int;
+    fn XESetWireToEvent(
+        display: *mut Display,
+        event_number: c_int,
+        proc_: WireToEventProc,
+    ) -> WireToEventProc;
+    fn Xutf8LookupString(
+        ic: XIC,
+        event: *mut XKeyEvent,
+        buffer: *mut c_char,
+        bytes: c_int,
+        keysym: *mut c_ulong,
+        status: *mut c_int,
+    ) -> c_int;
+}
+
+extern "C" {
+    fn mbstowcs(dest: *mut libc::wchar_t, src: *const c_char, n: libc::size_t) -> libc::size_t;
+}
+
+pub struct InputMethod {
+    display: *mut Display,
+    xim: XIM,
+}
+
+impl InputMethod {
+    pub fn open(display: *mut Display) -> anyhow::Result<Self> {
+        let xim = unsafe {
+            libc::setlocale(libc::LC_CTYPE, b"\0".as_ptr() as *const c_char);
+            XSetLocaleModifiers(b"\0".as_ptr() as *const c_char);
+            XOpenIM(display, std::ptr::null_mut(), std::ptr::null_mut(), std::ptr::null_mut())
+        };
+        ensure!(!xim.is_null(), "XOpenIM failed");
+        Ok(Self { display, xim })
+    }
+
+    pub fn filter_event(&self, event: &xcb::GenericEvent) -> bool {
+        let event_type = c_int::from(event.response_type() & 0x7f);
+        unsafe {
+            let mut xev: XEvent = std::mem::zeroed();
+            let proc_ = XESetWireToEvent(self.display, event_type, None);
+            XESetWireToEvent(self.display, event_type, proc_);
+            let converted = match proc_ {
+                Some(proc_) => proc_(self.display, &mut xev, event.ptr as *mut c_void) != 0,
+                None => false,
+            };
+            converted && XFilterEvent(&mut xev, 0) != 0
+        }
+    }
+
+    pub fn take_key_events(&self) -> Vec<XKeyEvent> {
+        let mut events = vec![];
+        unsafe {
+            let mut xev: XEvent = std::mem::zeroed();
+            while XCheckTypedEvent(self.display, KEY_PRESS, &mut xev) != 0 {
+                events.push(xev.key);
+            }
+        }
+        events
+    }
+
+    pub fn create_context(&self, window: xcb::xproto::Window) -> Option<InputContext> {
+        let preedit = Box::new(RefCell::new(PreeditState::default()));
+        let client_data = &*preedit as *const RefCell<PreeditState> as XPointer;
+        let window = c_ulong::from(window);
+
+        let callbacks = Box::new([
+            XIMCallback { client_data, callback: preedit_start as *const c_void },
+            XIMCallback { client_data, callback: preedit_done as *const c_void },
+            XIMCallback { client_data, callback: preedit_draw as *const c_void },
+            XIMCallback { client_data, callback: preedit_caret as *const c_void },
+        ]);
+
+        let xic = unsafe {
+            let attrs = XVaCreateNestedList(
+                0,
+                XN_PREEDIT_START_CALLBACK.as_ptr(),
+                &callbacks[0] as *const XIMCallback,
+                XN_PREEDIT_DONE_CALLBACK.as_ptr(),
+                &callbacks[1] as *const XIMCallback,
+                XN_PREEDIT_DRAW_CALLBACK.as_ptr(),
+                &callbacks[2] as *const XIMCallback,
+                XN_PREEDIT_CARET_CALLBACK.as_ptr(),
+                &callbacks[3] as *const XIMCallback,
+                std::ptr::null::<c_char>(),
+            );
+            let xic = XCreateIC(
+                self.xim,
+                XN_INPUT_STYLE.as_ptr(),
+                XIM_PREEDIT_CALLBACKS | XIM_STATUS_NOTHING,
+                XN_CLIENT_WINDOW.as_ptr(),
+                window,
+                XN_FOCUS_WINDOW.as_ptr(),
+                window,
+                XN_PREEDIT_ATTRIBUTES.as_ptr(),
+                attrs,
+                std::ptr::null::<c_char>(),
+            );
+            XFree(attrs);
+
+            if xic.is_null() {
+                XCreateIC(
+                    self.xim,
+                    XN_INPUT_STYLE.as_ptr(),
+                    XIM_PREEDIT_NOTHING | XIM_STATUS_NOTHING,
+                    XN_CLIENT_WINDOW.as_ptr(),
+                    window,
+                    XN_FOCUS_WINDOW.as_ptr(),
+                    window,
+                    std::ptr::null::<c_char>(),
+                )
+            } else {
+                xic
+            }
+        };
+
+        if xic.is_null() {
+            eprintln!("failed to create an X input context");
+            return None;
+        }
+
+        Some(InputContext { xic, preedit, _callbacks: callbacks, spot: None })
+    }
+}
+
+impl Drop for InputMethod {
+    fn drop(&mut self) {
+        unsafe {
+            XCloseIM(self.xim);
+        }
+    }
+}
+
+#[derive(Default)]
+struct PreeditState {
+    preedit: Preedit,
+    changed: bool,
+}
+
+pub struct InputContext {
+    xic: XIC,
+    preedit: Box<RefCell<PreeditState>>,
+    _callbacks: Box<[XIMCallback; 4]>,
+    spot: Option<(i16, i16)>,
+}
+
+impl InputContext {
+    pub fn focus(&self, focused: bool) {
+        unsafe {
+            if focused {
+                XSetICFocus(self.xic);
+            } else {
+                XUnsetICFocus(self.xic);
+            }
+        }
+    }
+
+    pub fn set_spot_location(&mut self, x: i16, y: i16) {
+        if self.spot == Some((x, y)) {
+            return;
+        }
+        self.spot = Some((x, y));
+        let spot = XPoint { x, y };
+        unsafe {
+            let attrs = XVaCreateNestedList(
+                0,
+                XN_SPOT_LOCATION.as_ptr(),
+                &spot as *const XPoint,
+                std::ptr::null::<c_char>(),
+            );
+            XSetICValues(
+                self.xic,
+                XN_PREEDIT_ATTRIBUTES.as_ptr(),
+                attrs,
+                std::ptr::null::<c_char>(),
+            );
+            XFree(attrs);
+        }
+    }
+
+    pub fn take_preedit(&self) -> Option<Option<Preedit>> {
+        let mut state = self.preedit.borrow_mut();
+        if !state.changed {
+            return None;
+        }
+        state.changed = false;
+        if state.preedit.text.is_empty() {
+            Some(None)
+        } else {
+            Some(Some(state.preedit.clone()))
+        }
+    }
+
+    pub fn lookup_key(&self, event: &mut XKeyEvent) -> Option<(KeyCode, Modifiers)> {
+        let mut buf = vec![0u8; 64];
+        let mut keysym: c_ulong = 0;
+        let mut status: c_int = 0;
+
+        let mut len = unsafe {
+            Xutf8LookupString(
+                self.xic,
+                event,
+                buf.as_mut_ptr() as *mut c_char,
+                buf.len() as c_int,
+                &mut keysym,
+                &mut status,
+            )
+        };
+        if status == X_BUFFER_OVERFLOW {
+            buf.resize(len as usize, 0);
+            len = unsafe {
+                Xutf8LookupString(
+                    self.xic,
+                    event,
+                    buf.as_mut_ptr() as *mut c_char,
+                    buf.len() as c_int,
+                    &mut keysym,
+                    &mut status,
+                )
+            };
+        }
+
+        let mods = modifiers_from_state(event.state as u16);
+        let text = String::from_utf8_lossy(&buf[..len.max(0) as usize]).into_owned();
+
+        if (status == X_LOOKUP_CHARS || status == X_LOOKUP_BOTH)
+            && text.chars().all(|c| !c.is_control())
+        {
+            let mut chars = text.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) => Some((KeyCode::Char(c), mods)),
+                (Some(_), Some(_)) => Some((KeyCode::Composed(text), mods)),
+                _ => None,
+            };
+        }
+
+        if status == X_LOOKUP_KEYSYM || status == X_LOOKUP_BOTH {
+            if let Some(c) = text.chars().next() {
+                return Some((KeyCode::Char(c), mods));
+            }
+            return keysym_to_keycode(keysym as u32).map(|code| (code, mods));
+        }
+
+        None
+    }
+}
+
+impl Drop for InputContext {
+    fn drop(&mut self) {
+        unsafe {
+            XDestroyIC(self.xic);
+        }
+    }
+}
+
+unsafe fn preedit_from_client_data<'a>(client_data: XPointer) -> &'a RefCell<PreeditState> {
+    &*(client_data as *const RefCell<PreeditState>)
+}
+
+unsafe extern "C" fn preedit_start(_xic: XIC, client_data: XPointer, _data: XPointer) -> c_int {
+    let mut state = preedit_from_client_data(client_data).borrow_mut();
+    state.preedit = Preedit::default();
+    state.changed = true;
+    -1
+}
+
+unsafe extern "C" fn preedit_done(_xic: XIC, client_data: XPointer, _data: XPointer) {
+    let mut state = preedit_from_client_data(client_data).borrow_mut();
+    state.preedit = Preedit::default();
+    state.changed = true;
+}
+
+fn feedback_from_xim(feedback: c_ulong) -> PreeditFeedback {
+    let mut result = PreeditFeedback::NONE;
+    if feedback & XIM_REVERSE != 0 {
+        result |= PreeditFeedback::REVERSE;
+    }
+    if feedback & XIM_UNDERLINE != 0 {
+        result |= PreeditFeedback::UNDERLINE;
+    }
+    if feedback & (XIM_HIGHLIGHT | XIM_PRIMARY | XIM_SECONDARY | XIM_TERTIARY) != 0 {
+        result |= PreeditFeedback::HIGHLIGHT;
+    }
+    result
+}
+
+fn char_from_wchar(c: libc::wchar_t) -> char {
+    std::char::from_u32(c as u32).unwrap_or(std::char::REPLACEMENT_CHARACTER)
+}
+
+/// Decodes the `length` characters of the text, which are wide characters
+/// or a multibyte string in the encoding of the locale.  Returns `None`
+/// when only the feedback of the characters changed.
+unsafe fn decode_xim_text(text: &XIMText) -> Option<Vec<char>> {
+    if text.string.is_null() {
+        return None;
+    }
+    let length = usize::from(text.length);
+    if text.encoding_is_wchar != 0 {
+        let wide = std::slice::from_raw_parts(text.string as *const libc::wchar_t, length);
+        return Some(wide.iter().map(|&c| char_from_wchar(c)).collect());
+    }
+
+    let mut wide = vec![0 as libc::wchar_t; length + 1];
+    let converted = mbstowcs(wide.as_mut_ptr(), text.string, wide.len());
+    if converted == libc::size_t::MAX {
+        // Not valid in the locale's encoding; make the best of it.
+        return Some(CStr::from_ptr(text.string).to_string_lossy().chars().take(length).collect());
+    }
+    Some(wide[..converted.min(length)].iter().map(|&c| char_from_wchar(c)).collect())
+}
+
+unsafe extern "C" fn preedit_draw(_xic: XIC, client_data: XPointer, data: XPointer) {
+    let mut state = preedit_from_client_data(client_data).borrow_mut();
+    let draw = &*(data as *const XIMPreeditDrawCallbackStruct);
+    let preedit = &mut state.preedit;
+
+    let len = preedit.text.len();
+    let first = (draw.chg_first.max(0) as usize).min(len);
+    let end = (first + draw.chg_length.max(0) as usize).min(len);
+
+    if draw.text.is_null() {
+        preedit.text.drain(first..end);
+    } else {
+        let text = &*draw.text;
+        let feedback: Vec<PreeditFeedback> = if text.feedback.is_null() {
+            vec![]
+        } else {
+            std::slice::from_raw_parts(text.feedback, usize::from(text.length))
+                .iter()
+                .map(|&f| feedback_from_xim(f))
+                .collect()
+        };
+        let feedback_at = |i: usize| feedback.get(i).copied().unwrap_or_default();
+
+        match decode_xim_text(text) {
+            Some(chars) => {
+                let replacement: Vec<(char, PreeditFeedback)> =
+                    chars.into_iter().enumerate().map(|(i, c)| (c, feedback_at(i))).collect();
+                preedit.text.splice(first..end, replacement);
+            }
+            None => {
+                for (slot, &f) in preedit.text.iter_mut().skip(first).zip(&feedback) {
+                    slot.1 = f;
+                }
+            }
+        }
+    }
+
+    preedit.caret = (draw.caret.max(0) as usize).min(preedit.text.len());
+    state.changed = true;
+}
+
+unsafe extern "C" fn preedit_caret(_xic: XIC, client_data: XPointer, data: XPointer) {
+    let mut state = preedit_from_client_data(client_data).borrow_mut();
+    let caret = &mut *(data as *mut XIMPreeditCaretCallbackStruct);
+    let preedit = &mut state.preedit;
+
+    let len = preedit.text.len();
+    preedit.caret = match caret.direction {
+        XIM_FORWARD_CHAR => (preedit.caret + 1).min(len),
+        XIM_BACKWARD_CHAR => preedit.caret.saturating_sub(1),
+        XIM_LINE_START => 0,
+        XIM_LINE_END => len,
+        XIM_ABSOLUTE_POSITION => (caret.position.max(0) as usize).min(len),
+        // The preedit is a single line and words are left to the input
+        // method, so the other motions leave the caret where it is.
+        _ => preedit.caret,
+    };
+    caret.position = preedit.caret as c_int;
+    state.changed = true;
+}